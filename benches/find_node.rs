@@ -0,0 +1,37 @@
+use std::hint::black_box;
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use pest::Parser as _;
+use sand::parser::{Document, Rule, SandParser};
+
+/// A document with thousands of sibling sentence blocks in a few sections.
+fn large_document() -> String {
+    let mut text = String::from("#(en, ja)\n");
+    for section in 0..10 {
+        text += &format!("#s{section}# Section {section}\n");
+        for sentence in 0..1000 {
+            text += &format!("#[Sentence {sentence}][文 {sentence}]\n");
+        }
+    }
+    text
+}
+
+fn find_node_at_position(c: &mut Criterion) {
+    let text = large_document();
+    let doc: Document = SandParser::parse(Rule::doc, &text)
+        .unwrap()
+        .try_into()
+        .unwrap();
+    let offsets: Vec<usize> = (0..text.len()).step_by(997).collect();
+
+    c.bench_function("find_node_at_position", |b| {
+        b.iter(|| {
+            for &offset in &offsets {
+                black_box(doc.ast.find_node_at_position(black_box(offset)));
+            }
+        })
+    });
+}
+
+criterion_group!(benches, find_node_at_position);
+criterion_main!(benches);
@@ -0,0 +1,51 @@
+use std::hint::black_box;
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use pest::Parser as _;
+use sand::parser::{Document, Rule, SandParser, Span, reparse_range};
+
+/// A document with thousands of top-level sections.
+fn large_document() -> String {
+    let mut text = String::from("#(en, ja)\n");
+    for i in 0..2000 {
+        text += &format!("#s{i}# Section {i}\n#[Hello {i}][こんにちは {i}]\n\n");
+    }
+    text
+}
+
+fn parse(text: &str) -> Document {
+    SandParser::parse(Rule::doc, text)
+        .unwrap()
+        .try_into()
+        .unwrap()
+}
+
+fn reparse(c: &mut Criterion) {
+    let text = large_document();
+    let old = parse(&text);
+
+    // 真ん中のセクションの文だけを書き換える
+    let start = text.find("[Hello 1000]").unwrap() + 1;
+    let changed = Span {
+        start,
+        end: start + 5,
+    };
+    let mut new_text = text.clone();
+    new_text.replace_range(changed.start..changed.end, "Bye");
+
+    c.bench_function("full_parse", |b| b.iter(|| parse(black_box(&new_text))));
+    c.bench_function("reparse_range", |b| {
+        b.iter(|| {
+            reparse_range(
+                black_box(&old),
+                black_box(&text),
+                black_box(&new_text),
+                changed.clone(),
+            )
+            .unwrap()
+        })
+    });
+}
+
+criterion_group!(benches, reparse);
+criterion_main!(benches);
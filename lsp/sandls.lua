@@ -2,5 +2,13 @@ return {
 	cmd = { "sand", "lsp" },
 	filetypes = { "sand" },
 	root_markers = { ".git" },
-	settings = {},
+	settings = {
+		sand = {
+			lints = {
+				emptySection = true,
+				unknownApplyAllTarget = true,
+				nameCoverage = true,
+			},
+		},
+	},
 }
@@ -0,0 +1,281 @@
+use rustc_hash::FxHashMap;
+
+use crate::parser::{AST, Document, NodeKind, NodeMeta, Span};
+
+/// Builds a [`Document`] without going through the grammar.
+///
+/// The builder writes the equivalent sand source alongside the AST, so every node gets the
+/// span it would have if that source were parsed. The source is available via
+/// [`DocBuilder::build_with_source`].
+///
+/// ```
+/// use sand::builder::DocBuilder;
+///
+/// let doc = DocBuilder::new(["en", "ja"])
+///     .section("intro", 1, "Title")
+///     .sen(["Hi", "やあ"])
+///     .build();
+/// assert_eq!(doc.names(), ["en", "ja"]);
+/// ```
+#[derive(Debug)]
+pub struct DocBuilder {
+    names: Vec<String>,
+    name_spans: Vec<Span>,
+    source: String,
+    stack: Vec<AST>,
+}
+
+impl DocBuilder {
+    pub fn new<I, S>(names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let names: Vec<String> = names.into_iter().map(Into::into).collect();
+        let source = format!("#({})\n", names.join(", "));
+
+        let mut start = "#(".len();
+        let name_spans = names
+            .iter()
+            .map(|name| {
+                let span = Span {
+                    start,
+                    end: start + name.len(),
+                };
+                start = span.end + ", ".len();
+                span
+            })
+            .collect();
+
+        Self {
+            names,
+            name_spans,
+            source,
+            stack: vec![AST {
+                node: NodeKind::Top {
+                    aliases: FxHashMap::default(),
+                    children: vec![],
+                },
+                meta: NodeMeta {
+                    span: Span { start: 0, end: 0 },
+                    alias: None,
+                },
+            }],
+        }
+    }
+
+    /// Opens a section of the given `level`, closing any open section of the same or deeper level.
+    pub fn section<'a>(
+        mut self,
+        alias: impl Into<Option<&'a str>>,
+        level: usize,
+        content: &str,
+    ) -> Self {
+        let alias = alias.into().map(str::to_string);
+
+        while self.stack.len() > 1 && self.top_level() >= level {
+            self.close_section();
+        }
+
+        let span = self.write(&format!(
+            "#{}{} {content}\n",
+            alias.as_deref().unwrap_or_default(),
+            "#".repeat(level)
+        ));
+
+        self.stack.push(AST {
+            node: NodeKind::Section {
+                level,
+                content: content.to_string(),
+                attributes: Default::default(),
+                aliases: FxHashMap::default(),
+                children: vec![],
+            },
+            meta: NodeMeta { span, alias },
+        });
+        self
+    }
+
+    /// Adds parallel sentences, one per declared name.
+    pub fn sen<I, S>(mut self, sentences: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let sentences: Vec<String> = sentences.into_iter().map(Into::into).collect();
+
+        let span = self.write(&format!(
+            "#{}",
+            sentences
+                .iter()
+                .map(|s| format!("[{s}]"))
+                .collect::<String>()
+        ));
+        self.source.push('\n');
+
+        self.push_leaf(AST {
+            node: NodeKind::Sen(sentences, None),
+            meta: NodeMeta { span, alias: None },
+        });
+        self
+    }
+
+    /// Adds an apply-all block for every name.
+    pub fn all(self, content: &str) -> Self {
+        self.push_all(None, content)
+    }
+
+    /// Adds an apply-all block for the given names only.
+    pub fn all_for<I, S>(self, targets: I, content: &str) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.push_all(Some(targets.into_iter().map(Into::into).collect()), content)
+    }
+
+    /// Sets the alias of the most recently added sentence or apply-all block.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the current scope has no such block yet.
+    pub fn alias(mut self, alias: &str) -> Self {
+        let (_, aliases, children) = self
+            .stack
+            .last_mut()
+            .unwrap()
+            .take_mut_section_like()
+            .unwrap();
+        let index = children.len() - 1;
+        let last = &mut children[index];
+
+        // 生成したソースにもエイリアスを書き込む
+        self.source.insert_str(last.meta.span.start + 1, alias);
+        last.meta.span.end += alias.len();
+        last.meta.alias = Some(alias.to_string());
+        aliases.insert(alias.to_string(), index);
+        self
+    }
+
+    pub fn build(self) -> Document {
+        self.build_with_source().0
+    }
+
+    /// Like [`DocBuilder::build`], but also returns the sand source the spans refer to.
+    pub fn build_with_source(mut self) -> (Document, String) {
+        while self.stack.len() > 1 {
+            self.close_section();
+        }
+
+        (
+            Document::from_parts(self.names, self.name_spans, self.stack.pop().unwrap()),
+            self.source,
+        )
+    }
+
+    fn push_all(mut self, targets: Option<Vec<String>>, content: &str) -> Self {
+        let span = self.write(&match &targets {
+            Some(t) => format!("#{{[{}], {{{content}}}}}", t.join(", ")),
+            None => format!("#{{{{{content}}}}}"),
+        });
+        self.source.push('\n');
+
+        self.push_leaf(AST {
+            node: NodeKind::All {
+                all_or_names: targets,
+                content: content.to_string(),
+            },
+            meta: NodeMeta { span, alias: None },
+        });
+        self
+    }
+
+    fn write(&mut self, s: &str) -> Span {
+        let start = self.source.len();
+        self.source += s;
+        Span {
+            start,
+            end: self.source.len(),
+        }
+    }
+
+    fn top_level(&mut self) -> usize {
+        self.stack
+            .last_mut()
+            .unwrap()
+            .take_mut_section_like()
+            .unwrap()
+            .0
+    }
+
+    fn close_section(&mut self) {
+        let section = self.stack.pop().unwrap();
+        self.push_leaf(section);
+    }
+
+    fn push_leaf(&mut self, node: AST) {
+        let (_, aliases, children) = self
+            .stack
+            .last_mut()
+            .unwrap()
+            .take_mut_section_like()
+            .unwrap();
+        if let Some(alias) = &node.meta.alias {
+            aliases.insert(alias.clone(), children.len());
+        }
+        children.push(node);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DocBuilder;
+    use crate::formatter::{Selector, render_plain};
+    use crate::parser::{Document, Rule, SandParser};
+    use pest::Parser as _;
+
+    fn selector(doc: &Document, input: &str) -> Selector {
+        let pairs = SandParser::parse(Rule::Selector, input).unwrap();
+        (doc, pairs).try_into().unwrap()
+    }
+
+    #[test]
+    fn render_built_document() {
+        let doc = DocBuilder::new(["en", "ja"])
+            .section("intro", 1, "Title")
+            .sen(["Hi", "やあ"])
+            .alias("greet")
+            .all("!")
+            .section("other", 1, "Other")
+            .sen(["Bye", "じゃあ"])
+            .build();
+
+        let sel = selector(&doc, "#.intro.");
+        assert_eq!(render_plain(&doc, &sel, false), ["Hi !", "やあ !"]);
+
+        let sel = selector(&doc, "#.intro.greet.ja");
+        assert_eq!(render_plain(&doc, &sel, false), ["やあ"]);
+
+        let sel = selector(&doc, "#.en");
+        assert_eq!(render_plain(&doc, &sel, false), ["Hi ! Bye"]);
+    }
+
+    #[test]
+    fn source_round_trips() {
+        let (built, source) = DocBuilder::new(["en", "ja"])
+            .section("intro", 1, "Title")
+            .sen(["Hi", "やあ"])
+            .alias("greet")
+            .all_for(["en"], "only en")
+            .build_with_source();
+
+        let pairs = SandParser::parse(Rule::doc, &source).unwrap();
+        let parsed: Document = pairs.try_into().unwrap();
+
+        assert_eq!(
+            format!("{:?}", built.names()),
+            format!("{:?}", parsed.names())
+        );
+        assert_eq!(format!("{:?}", built.ast), format!("{:?}", parsed.ast));
+    }
+}
@@ -1,6 +1,6 @@
 use pest::iterators::Pairs;
 
-use crate::parser::{AST, Document, ParseError, Rule};
+use crate::parser::{AST, Document, ParseError, PathSegment, Resolution, Rule};
 
 #[derive(Debug)]
 pub struct Selector(pub AST);
@@ -23,6 +23,117 @@ impl TryFrom<(&Document, Pairs<'_, Rule>)> for Selector {
     }
 }
 
+impl Selector {
+    /// Builds a selector from its path segments without parsing, e.g. `["intro", "en"]` for
+    /// `#.intro.en`.
+    ///
+    /// The selector is not validated against a document, so rendering panics if the path
+    /// doesn't resolve. The spans of the node and of the segments are empty.
+    pub fn from_path(local: bool, path: Vec<String>, trailing_dot: bool) -> Selector {
+        let span = crate::parser::Span { start: 0, end: 0 };
+
+        Selector(AST {
+            meta: crate::parser::NodeMeta {
+                span: span.clone(),
+                alias: None,
+            },
+            node: crate::parser::NodeKind::Selector {
+                local,
+                segment_spans: vec![span; path.len()],
+                path: path
+                    .into_iter()
+                    .map(crate::parser::PathSegment::Ident)
+                    .collect(),
+                trailing_dot,
+                misplaced_dots: vec![],
+                names: None,
+            },
+        })
+    }
+
+    /// Returns the name this selector ends in, or `None` if it expands to several names.
+    pub fn name(&self) -> Option<&str> {
+        match &self.0.node {
+            crate::parser::NodeKind::Selector {
+                path,
+                trailing_dot,
+                names: None,
+                ..
+            } if !trailing_dot => path.last().and_then(crate::parser::PathSegment::as_ident),
+            _ => None,
+        }
+    }
+
+    /// Returns the names this selector renders, in the order [`render`] returns them.
+    pub fn target_names<'a>(&'a self, doc: &'a Document) -> Vec<&'a str> {
+        match &self.0.node {
+            crate::parser::NodeKind::Selector {
+                names: Some(names), ..
+            } => names.iter().map(String::as_str).collect(),
+            _ => match self.name() {
+                Some(name) => vec![name],
+                None => doc.names().iter().map(String::as_str).collect(),
+            },
+        }
+    }
+}
+
+/// Layout of the Markdown produced by [`render_markdown`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MarkdownOptions {
+    /// Number of blank lines written before and after each section header.
+    pub blank_lines_around_headers: usize,
+    /// Written before each child of a section or of the document.
+    pub child_separator: String,
+    /// Escape Markdown metacharacters in the content so it can't turn into emphasis, links
+    /// and the like. The section headers are still written as `#`.
+    pub escape_content: bool,
+}
+
+impl Default for MarkdownOptions {
+    fn default() -> Self {
+        Self {
+            blank_lines_around_headers: 1,
+            child_separator: " ".to_string(),
+            escape_content: false,
+        }
+    }
+}
+
+/// Options for [`render`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RenderOptions {
+    /// Render section headers as Markdown with this layout. Plain text if `None`.
+    pub markdown: Option<MarkdownOptions>,
+    /// How the whitespace in the content is rendered.
+    pub whitespace: WhitespacePolicy,
+    /// Prefix section headings with their position among the sibling sections, e.g. `1.2 Title`.
+    /// In plain text the numbered headings are written like Markdown headers without the `#`s.
+    pub number_sections: bool,
+    /// Only render this many levels of sections below the selected node, the selected section
+    /// included. Deeper sections are left out with their headings.
+    pub max_depth: Option<usize>,
+    /// Render for every declared name, even if the selector ends in a name or a name list.
+    pub all_names: bool,
+    /// Render the apply-all blocks targeting other names instead of the ones for the rendered
+    /// name, e.g. to review what a name is missing. Blocks for every name are left out.
+    pub only_other_names: bool,
+}
+
+/// How [`render`] treats the whitespace in sentences and apply-all blocks. Escapes such as
+/// `\n` are resolved under every policy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WhitespacePolicy {
+    /// Collapse every run of whitespace, line breaks included, into a single space.
+    #[default]
+    CollapseAll,
+    /// Keep the line breaks but collapse the spaces and tabs within each line. Lines that are
+    /// left empty are dropped.
+    PreserveNewlines,
+    /// Keep the whitespace as written. Only blank lines at the start and end are dropped.
+    Raw,
+}
+
 // localでもDocumentの中のASTだけ差し替えるだけでいいはず
 /// Renders the selected part(s) of a document as plain text or Markdown-formatted strings.
 ///
@@ -36,100 +147,685 @@ impl TryFrom<(&Document, Pairs<'_, Rule>)> for Selector {
 /// # Returns
 /// A vector of rendered strings, each representing a section of the document.
 pub fn render_plain(doc: &Document, sel: &Selector, markdown: bool) -> Vec<String> {
-    let (target_ast, target_name) = select(doc, sel);
-    if let Some(target_name) = target_name {
-        vec![
-            to_plain(target_ast, (target_name, &doc.names[target_name]), markdown)
-                .lines()
-                .map(trim)
-                .collect::<Vec<_>>()
-                .join("\n"),
-        ]
-    } else {
-        doc.names
+    render(
+        doc,
+        sel,
+        &RenderOptions {
+            markdown: markdown.then(MarkdownOptions::default),
+            ..Default::default()
+        },
+    )
+}
+
+/// Like [`render_plain`] with `markdown` set, but with the layout controlled by `options`.
+pub fn render_markdown(doc: &Document, sel: &Selector, options: &MarkdownOptions) -> Vec<String> {
+    render(
+        doc,
+        sel,
+        &RenderOptions {
+            markdown: Some(options.clone()),
+            ..Default::default()
+        },
+    )
+}
+
+/// Renders the selected part(s) of a document like [`render_plain`], configured by `options`.
+pub fn render(doc: &Document, sel: &Selector, options: &RenderOptions) -> Vec<String> {
+    render_labeled(doc, sel, options)
+        .into_iter()
+        .map(|(_, rendered)| rendered)
+        .collect()
+}
+
+/// Like [`render_plain`], but pairs each rendered string with the name it is rendered for.
+pub fn render_plain_labeled(
+    doc: &Document,
+    sel: &Selector,
+    markdown: bool,
+) -> Vec<(String, String)> {
+    render_labeled(
+        doc,
+        sel,
+        &RenderOptions {
+            markdown: markdown.then(MarkdownOptions::default),
+            ..Default::default()
+        },
+    )
+}
+
+/// Like [`render`], but pairs each rendered string with the name it is rendered for.
+pub fn render_labeled(
+    doc: &Document,
+    sel: &Selector,
+    options: &RenderOptions,
+) -> Vec<(String, String)> {
+    let (target_asts, mut target_names) = select(doc, sel);
+    if options.all_names {
+        target_names = (0..doc.names().len()).collect();
+    }
+
+    target_names
+        .into_iter()
+        .map(|index| {
+            let name = &doc.names()[index];
+            (
+                name.clone(),
+                render_targets(&target_asts, (index, name), options),
+            )
+        })
+        .collect()
+}
+
+/// Renders the selected part(s) of a document for the name at `name_index` only, without
+/// rendering the other names. The name doesn't have to be one the selector selects.
+///
+/// Returns `None` if there is no name at `name_index`.
+pub fn render_name(
+    doc: &Document,
+    sel: &Selector,
+    name_index: usize,
+    markdown: bool,
+) -> Option<String> {
+    let name = doc.names().get(name_index)?;
+    let (target_asts, _) = select(doc, sel);
+
+    Some(render_targets(
+        &target_asts,
+        (name_index, name),
+        &RenderOptions {
+            markdown: markdown.then(MarkdownOptions::default),
+            ..Default::default()
+        },
+    ))
+}
+
+/// Like [`render_plain`], but renders the names in parallel.
+///
+/// The output is in declaration order, identical to [`render_plain`].
+#[cfg(feature = "parallel")]
+pub fn render_plain_parallel(doc: &Document, sel: &Selector, markdown: bool) -> Vec<String> {
+    use rayon::prelude::*;
+
+    let options = RenderOptions {
+        markdown: markdown.then(MarkdownOptions::default),
+        ..Default::default()
+    };
+    let (target_asts, target_names) = select(doc, sel);
+
+    target_names
+        .into_par_iter()
+        .map(|index| render_targets(&target_asts, (index, &doc.names()[index]), &options))
+        .collect()
+}
+
+fn render_targets(target_asts: &[&AST], name: (usize, &str), options: &RenderOptions) -> String {
+    let separator = options
+        .markdown
+        .as_ref()
+        .map_or(" ", |o| o.child_separator.as_str());
+    let mut sections = 0;
+    let s = target_asts
+        .iter()
+        .map(|ast| {
+            let mut sink = TextSink::new(options);
+            if matches!(ast.node, crate::parser::NodeKind::Section { .. }) {
+                sections += 1;
+                emit(
+                    ast,
+                    name,
+                    &[sections],
+                    options.max_depth,
+                    options.only_other_names,
+                    &mut sink,
+                );
+            } else {
+                emit(
+                    ast,
+                    name,
+                    &[],
+                    options.max_depth,
+                    options.only_other_names,
+                    &mut sink,
+                );
+            }
+            sink.finish()
+        })
+        .collect::<Vec<_>>()
+        .join(separator);
+    match options.whitespace {
+        // 最初の子の前に入る区切りは落とす
+        WhitespacePolicy::Raw => trim_blank_lines(s.strip_prefix(separator).unwrap_or(&s)),
+        WhitespacePolicy::CollapseAll | WhitespacePolicy::PreserveNewlines => {
+            s.lines().map(trim).collect::<Vec<_>>().join("\n")
+        }
+    }
+}
+
+impl Document {
+    /// Returns the indices of the declared names for which `sel` renders non-empty output.
+    ///
+    /// Takes `All` targeting and missing sentences into account, so this is suitable for
+    /// translation coverage reports.
+    pub fn covered_names(&self, sel: &Selector) -> Vec<usize> {
+        let (target_asts, target_names) = select(self, sel);
+
+        target_names
+            .into_iter()
+            .filter(|&index| {
+                target_asts.iter().any(|ast| {
+                    !trim(&to_plain(
+                        ast,
+                        (index, &self.names()[index]),
+                        &RenderOptions::default(),
+                    ))
+                    .is_empty()
+                })
+            })
+            .collect()
+    }
+
+    /// Lists every sentence block with its path and its content for each declared name.
+    ///
+    /// The path holds the alias of each node, or its index if it has none, as in
+    /// [`Document::alias_index`]. The content is rendered as plain text, and `None` where a
+    /// name has no sentence. This is the data translation tools exchange.
+    pub fn translation_table(&self) -> Vec<(Vec<String>, Vec<Option<String>>)> {
+        fn walk(
+            ast: &AST,
+            names: usize,
+            path: &mut Vec<String>,
+            table: &mut Vec<(Vec<String>, Vec<Option<String>>)>,
+        ) {
+            for (i, child) in ast.content_children().into_iter().enumerate() {
+                path.push(child.meta.alias.clone().unwrap_or_else(|| i.to_string()));
+                if let crate::parser::NodeKind::Sen(sentences, _) = &child.node {
+                    let row = (0..names)
+                        .map(|index| sentences.get(index).map(|sen| normalize(&trim(sen))))
+                        .collect();
+                    table.push((path.clone(), row));
+                }
+                walk(child, names, path, table);
+                path.pop();
+            }
+        }
+
+        let mut table = Vec::new();
+        walk(&self.ast, self.names().len(), &mut Vec::new(), &mut table);
+        table
+    }
+
+    /// Counts, for each name index, the sentences and apply-all blocks that render non-empty for it.
+    ///
+    /// A name with a count of zero has no content anywhere in the document.
+    pub fn name_coverage(&self) -> Vec<(usize, usize)> {
+        let options = RenderOptions::default();
+
+        self.names()
             .iter()
             .enumerate()
             .map(|(index, name)| {
-                to_plain(target_ast, (index, name), markdown)
-                    .lines()
-                    .map(trim)
-                    .collect::<Vec<_>>()
-                    .join("\n")
+                let count = self
+                    .ast
+                    .iter()
+                    .filter(|ast| {
+                        matches!(
+                            ast.node,
+                            crate::parser::NodeKind::Sen(..) | crate::parser::NodeKind::All { .. }
+                        )
+                    })
+                    .filter(|ast| !trim(&to_plain(ast, (index, name), &options)).is_empty())
+                    .count();
+                (index, count)
             })
             .collect()
     }
+
+    /// Rewrites escapes that the grammar doesn't need (`\#` and `\/`) to the literal characters.
+    ///
+    /// Escapes that change parsing or rendering, such as `\]` or `\n`, are kept, so the
+    /// document renders the same and normalizing twice gives the same result.
+    pub fn normalize_escapes(&mut self) {
+        fn walk(ast: &mut AST) {
+            match &mut ast.node {
+                crate::parser::NodeKind::Sen(sentences, _) => {
+                    for sen in sentences {
+                        *sen = resolve_redundant_escapes(sen);
+                    }
+                }
+                crate::parser::NodeKind::All { content, .. } => {
+                    *content = resolve_redundant_escapes(content);
+                }
+                crate::parser::NodeKind::Section {
+                    content, children, ..
+                } => {
+                    *content = resolve_redundant_escapes(content);
+                    children.iter_mut().for_each(walk);
+                }
+                crate::parser::NodeKind::Top { children, .. } => {
+                    children.iter_mut().for_each(walk);
+                }
+                crate::parser::NodeKind::Selector { .. } => {}
+            }
+        }
+
+        walk(&mut self.ast);
+    }
+
+    /// Like [`Document::normalize_escapes`], but rewrites the escapes in `text`, the source the
+    /// document was parsed from, instead of in the nodes. Only the sentences, apply-all blocks
+    /// and section headings are touched, so free text and the layout stay as written.
+    ///
+    /// `text` starts at `offset` in the parsed text, e.g. at the offset of a file in an
+    /// expanded [`crate::include::Source`]. Nodes outside of it are left as they are.
+    pub fn normalize_escapes_in(&self, text: &str, offset: usize) -> String {
+        use crate::parser::NodeKind;
+
+        let mut out = String::with_capacity(text.len());
+        let mut written = 0;
+
+        // 前順なのでスパンは文書の順に並ぶ
+        for ast in self.ast.iter() {
+            if !matches!(
+                ast.node,
+                NodeKind::Sen(..) | NodeKind::All { .. } | NodeKind::Section { .. }
+            ) {
+                continue;
+            }
+
+            let span = ast.get_span();
+            let (Some(start), Some(end)) =
+                (span.start.checked_sub(offset), span.end.checked_sub(offset))
+            else {
+                continue;
+            };
+            if start < written || end > text.len() {
+                continue;
+            }
+
+            out += &text[written..start];
+            out += &resolve_redundant_escapes(&text[start..end]);
+            written = end;
+        }
+        out += &text[written..];
+
+        out
+    }
+
+    /// Keeps only the sections with the attribute `key=value` and their ancestors.
+    ///
+    /// A section without `key` inherits the result of its parent, so the unannotated
+    /// subsections of a matching section stay. An ancestor kept only for a matching
+    /// descendant keeps its heading but loses its own sentences, apply-all blocks and
+    /// selectors. Content outside of any section is kept. Aliases are reassigned to the
+    /// remaining children, so selectors to a removed section no longer resolve.
+    pub fn retain_sections_where(&mut self, key: &str, value: &str) {
+        // 残すならtrue
+        fn walk(ast: &mut AST, key: &str, value: &str, inherited: bool) -> bool {
+            let matched = ast
+                .attributes()
+                .and_then(|attributes| attributes.get(key))
+                .map_or(inherited, |v| v == value);
+            let keep_leaves = matched || matches!(ast.node, crate::parser::NodeKind::Top { .. });
+
+            let Some((_, aliases, children)) = ast.take_mut_section_like() else {
+                return true;
+            };
+
+            let mut descendant_matched = false;
+            children.retain_mut(|child| {
+                if matches!(child.node, crate::parser::NodeKind::Section { .. }) {
+                    let keep = walk(child, key, value, matched);
+                    descendant_matched |= keep;
+                    keep
+                } else {
+                    keep_leaves
+                }
+            });
+
+            aliases.clear();
+            for (index, child) in children.iter().enumerate() {
+                if let Some(alias) = &child.meta.alias {
+                    aliases.insert(alias.clone(), index);
+                }
+            }
+
+            matched || descendant_matched
+        }
+
+        walk(&mut self.ast, key, value, false);
+    }
 }
 
-/// Traverses the document AST according to the selector path and returns the targeted AST node and, if applicable, the index of the last path element in the document's names.
+/// Traverses the document AST according to the selector path and returns the targeted AST nodes and the indices of the names to render them for.
 ///
-/// If the selector has a trailing dot or an empty path, returns the root AST and no target name index. Otherwise, follows the selector path through section-like nodes, matching by alias or numeric index, and returns the final AST node and the index of the last path element if found.
+/// The names are the last path element, the `[...]` name list, or all names if the selector has a trailing dot or an empty path. The path is followed through section-like nodes, matching by alias, numeric index or section content; a `*` segment selects every child section, so there can be several (or no) targets.
 ///
 /// # Panics
 ///
 /// Panics if the selector path is invalid, which should not occur if the selector has been validated beforehand.
-fn select<'a>(doc: &'a Document, sel: &'a Selector) -> (&'a AST, Option<usize>) {
+pub(crate) fn select<'a>(doc: &'a Document, sel: &'a Selector) -> (Vec<&'a AST>, Vec<usize>) {
+    select_traced(doc, sel, &mut vec![])
+}
+
+/// Like [`select`], but records what each path segment matched in `trace`, the name at the
+/// end of the path included.
+fn select_traced<'a>(
+    doc: &'a Document,
+    sel: &'a Selector,
+    trace: &mut Vec<(PathSegment, Resolution)>,
+) -> (Vec<&'a AST>, Vec<usize>) {
     if let Selector(AST {
-        node: crate::parser::NodeKind::Selector {
-            path, trailing_dot, ..
-        },
+        node:
+            crate::parser::NodeKind::Selector {
+                path,
+                trailing_dot,
+                names,
+                ..
+            },
         ..
     }) = sel
     {
-        let (path, last) = if *trailing_dot || path.is_empty() {
-            (path.as_ref(), None)
+        let index_of = |name: &str| doc.name_index(name);
+
+        let (section_path, targets): (_, Vec<_>) = if let Some(names) = names {
+            (
+                path.as_ref(),
+                names.iter().filter_map(|n| index_of(n)).collect(),
+            )
+        } else if *trailing_dot || path.is_empty() {
+            (path.as_ref(), (0..doc.names().len()).collect())
         } else {
             (
                 &path[0..(path.len() - 1)],
-                doc.names.iter().position(|t| t == path.last().unwrap()),
+                path.last()
+                    .unwrap()
+                    .as_ident()
+                    .and_then(index_of)
+                    .into_iter()
+                    .collect(),
             )
         };
 
-        let mut curr = &doc.ast;
-        for pathi in path {
-            if let Some((alias, children)) = curr.take_section_like() {
-                if let Some(index) = alias.get(pathi) {
-                    curr = &children[*index];
-                } else if let Ok(index) = pathi.parse::<usize>() {
-                    let children_without_sel: Vec<&AST> = children
-                        .iter()
-                        .filter(|p| !matches!(&p.node, crate::parser::NodeKind::Selector { .. }))
-                        .collect();
+        // ここでselectorがvailedなのは保証されている
+        let asts = doc.ast.resolve_path_traced(section_path, trace).unwrap();
+        if section_path.len() < path.len()
+            && let [index] = targets[..]
+        {
+            trace.push((path[path.len() - 1].clone(), Resolution::Name(index)));
+        }
+        (asts, targets)
+    } else {
+        panic!()
+    }
+}
 
-                    curr = children_without_sel[index];
-                } else {
-                    panic!() // ここでselectorがvailedなのは保証されている
-                }
+/// How a selector resolves in a document, see [`explain`].
+#[derive(Debug)]
+pub struct Explanation<'a> {
+    /// Each path segment with what it matched, in order. After a `*`, a segment appears once
+    /// for every section it is resolved from.
+    pub steps: Vec<(PathSegment, Resolution)>,
+    /// The nodes the path leads to.
+    pub targets: Vec<&'a AST>,
+    /// The names rendered for the targets.
+    pub names: Vec<&'a str>,
+}
+
+/// Resolves `sel` like [`render`] does and records each step, for finding out why a selector
+/// doesn't render what was expected.
+pub fn explain<'a>(doc: &'a Document, sel: &'a Selector) -> Explanation<'a> {
+    let mut steps = vec![];
+    let (targets, names) = select_traced(doc, sel, &mut steps);
+
+    Explanation {
+        steps,
+        targets,
+        names: names.into_iter().map(|i| doc.names()[i].as_str()).collect(),
+    }
+}
+
+impl std::fmt::Display for Explanation<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use crate::parser::NodeKind;
+
+        for (segment, resolution) in &self.steps {
+            writeln!(f, "{segment}: {resolution}")?;
+        }
+        for target in &self.targets {
+            let kind = match &target.node {
+                NodeKind::Top { .. } => "document",
+                NodeKind::Section { .. } => "section",
+                NodeKind::Sen(..) => "sentences",
+                NodeKind::All { .. } => "apply-all",
+                NodeKind::Selector { .. } => "selector",
+            };
+            writeln!(f, "target: {kind}")?;
+        }
+        writeln!(f, "names: {}", self.names.join(", "))
+    }
+}
+
+/// The plain text of `ast` for a name, with the whitespace collapsed.
+pub(crate) fn plain_text(ast: &AST, name: (usize, &str)) -> String {
+    trim(&to_plain(ast, name, &RenderOptions::default()))
+}
+
+/// Receives the parts of a document as it is rendered, so output formats other than plain
+/// text and Markdown can be written. See [`render_with`].
+///
+/// The content is passed as written in the document, with escapes such as `\n` unresolved.
+pub trait RenderSink {
+    /// Called when a node starts: each selected node, and each child of a section or of the
+    /// document. Every call is matched by a [`RenderSink::leave`].
+    fn enter(&mut self) {}
+
+    /// Called when the node started by the last unmatched [`RenderSink::enter`] ends, after
+    /// its children.
+    fn leave(&mut self) {}
+
+    /// The heading of a section, before its children.
+    ///
+    /// `number` is its position among the sibling sections after the number of its parent,
+    /// e.g. `[1, 2]`, counted from the selected nodes.
+    fn section(&mut self, level: usize, content: &str, number: &[usize]);
+
+    /// The content of a sentence or apply-all block for the name at `name_i`.
+    fn text(&mut self, name_i: usize, s: &str);
+}
+
+/// The [`RenderSink`] behind [`render`], writing plain text or Markdown as set in `options`
+/// into a [`String`], or into any [`std::fmt::Write`] with [`TextSink::with_writer`].
+#[derive(Debug, Clone)]
+pub struct TextSink<'a, W = String> {
+    options: &'a RenderOptions,
+    out: W,
+    /// The first error from `out`. Nothing more is written after one.
+    result: std::fmt::Result,
+}
+
+impl<'a> TextSink<'a> {
+    pub fn new(options: &'a RenderOptions) -> Self {
+        Self::with_writer(options, String::new())
+    }
+}
+
+impl<'a, W: std::fmt::Write> TextSink<'a, W> {
+    /// Writes into `out` as the document is rendered, instead of into a [`String`].
+    pub fn with_writer(options: &'a RenderOptions, out: W) -> Self {
+        Self {
+            options,
+            out,
+            result: Ok(()),
+        }
+    }
+
+    /// The text written so far, or the writer it was written into.
+    pub fn finish(self) -> W {
+        self.out
+    }
+
+    fn write(&mut self, s: &str) {
+        if self.result.is_ok() {
+            self.result = self.out.write_str(s);
+        }
+    }
+}
+
+impl<W: std::fmt::Write> RenderSink for TextSink<'_, W> {
+    fn enter(&mut self) {
+        let options = self.options;
+        self.write(
+            options
+                .markdown
+                .as_ref()
+                .map_or(" ", |o| o.child_separator.as_str()),
+        );
+    }
+
+    fn section(&mut self, level: usize, content: &str, number: &[usize]) {
+        let prefix = if self.options.number_sections && !number.is_empty() {
+            let number: Vec<_> = number.iter().map(usize::to_string).collect();
+            format!("{} ", number.join("."))
+        } else {
+            String::new()
+        };
+
+        if let Some(markdown) = &self.options.markdown {
+            let newlines = "\n".repeat(markdown.blank_lines_around_headers + 1);
+
+            self.write(&newlines);
+            self.write(&"#".repeat(level));
+            self.write(" ");
+            self.write(&prefix);
+            if markdown.escape_content {
+                self.write(&escape_md(&normalize(content)));
             } else {
-                break;
+                self.write(&normalize(content));
             }
+            self.write(&newlines);
+        } else if self.options.number_sections {
+            self.write("\n");
+            self.write(&prefix);
+            self.write(&normalize(content));
+            self.write("\n");
         }
+    }
 
-        (curr, last)
-    } else {
-        panic!()
+    fn text(&mut self, _: usize, s: &str) {
+        let content = match self.options.whitespace {
+            WhitespacePolicy::CollapseAll => normalize(&trim(s)),
+            WhitespacePolicy::PreserveNewlines => normalize(&trim_lines(s)),
+            WhitespacePolicy::Raw => normalize(s),
+        };
+
+        if self
+            .options
+            .markdown
+            .as_ref()
+            .is_some_and(|o| o.escape_content)
+        {
+            self.write(&escape_md(&content));
+        } else {
+            self.write(&content);
+        }
+    }
+}
+
+/// Renders the selected part(s) of a document for the name at `name_i` into `sink`.
+///
+/// Each selected node is wrapped in [`RenderSink::enter`] and [`RenderSink::leave`], and the
+/// selected sections are numbered from 1 like in [`render`].
+///
+/// # Panics
+///
+/// Panics if there is no name at `name_i`.
+pub fn render_with<S: RenderSink + ?Sized>(
+    doc: &Document,
+    sel: &Selector,
+    name_i: usize,
+    sink: &mut S,
+) {
+    let name = &doc.names()[name_i];
+    let (target_asts, _) = select(doc, sel);
+    let mut sections = 0;
+
+    for ast in target_asts {
+        sink.enter();
+        if matches!(ast.node, crate::parser::NodeKind::Section { .. }) {
+            sections += 1;
+            emit(ast, (name_i, name), &[sections], None, false, sink);
+        } else {
+            emit(ast, (name_i, name), &[], None, false, sink);
+        }
+        sink.leave();
     }
 }
 
 /// Converts an AST node and its descendants to a plain text or Markdown-formatted string for a given name index and name.
 ///
-/// If `markdown` is true, section nodes are rendered as Markdown headers with appropriate heading levels.
+/// If `options.markdown` is given, section nodes are rendered as Markdown headers with appropriate heading levels.
 /// Otherwise, content is concatenated as plain text. Only content matching the specified name is included for nodes with named content.
-fn to_plain(ast: &AST, (name_i, name): (usize, &str), markdown: bool) -> String {
-    let mut s = String::new();
+fn to_plain(ast: &AST, name: (usize, &str), options: &RenderOptions) -> String {
+    let mut out = String::new();
+    // Stringへの書き込みは失敗しない
+    let _ = to_plain_write(ast, name, options, &mut out);
+    out
+}
 
-    match &ast.node {
-        crate::parser::NodeKind::Sen(v) => {
-            s += &normalize(&trim(&v[name_i]));
+/// Like the text [`render`] produces for one node, but written into `w` piece by piece
+/// instead of built up as one string. The whitespace between the lines is left as it is.
+pub fn to_plain_write<W: std::fmt::Write>(
+    ast: &AST,
+    name: (usize, &str),
+    options: &RenderOptions,
+    w: &mut W,
+) -> std::fmt::Result {
+    let mut sink = TextSink::with_writer(options, w);
+    emit(
+        ast,
+        name,
+        &[],
+        options.max_depth,
+        options.only_other_names,
+        &mut sink,
+    );
+    sink.result
+}
+
+/// Sends `ast` and its descendants to `sink` for a name. `ast` is numbered `number` among
+/// the sections, and its child sections are numbered below it. At most `depth` levels of
+/// sections are sent, `ast` included. With `other_names`, only the apply-all blocks targeting
+/// other names are sent, see [`RenderOptions::only_other_names`].
+fn emit<S: RenderSink + ?Sized>(
+    ast: &AST,
+    (name_i, name): (usize, &str),
+    number: &[usize],
+    depth: Option<usize>,
+    other_names: bool,
+    sink: &mut S,
+) {
+    let (children, depth) = match &ast.node {
+        crate::parser::NodeKind::Sen(v, _) => {
+            if let Some(sen) = v.get(name_i) {
+                sink.text(name_i, sen);
+            }
+            return;
         }
         crate::parser::NodeKind::All {
             all_or_names,
             content,
         } => {
-            if all_or_names.is_none()
-                || all_or_names.as_ref().map(|v| v.iter().any(|e| e == name)) == Some(true)
-            {
-                s += &normalize(&trim(content));
+            let applies = all_or_names.is_none()
+                || all_or_names.as_ref().map(|v| v.iter().any(|e| e == name)) == Some(true);
+            let send = if other_names {
+                all_or_names.is_some() && !applies
+            } else {
+                applies
+            };
+            if send {
+                sink.text(name_i, content);
             }
+            return;
         }
         crate::parser::NodeKind::Section {
             children,
@@ -137,51 +833,883 @@ fn to_plain(ast: &AST, (name_i, name): (usize, &str), markdown: bool) -> String
             content,
             ..
         } => {
-            if markdown {
-                s += "\n\n";
-                s += &"#".repeat(*level);
-                s += " ";
-                s += content;
-                s += "\n\n";
+            if depth == Some(0) {
+                return;
             }
+            sink.section(*level, content, number);
+            (children, depth.map(|d| d - 1))
+        }
+        crate::parser::NodeKind::Top { children, .. } => (children, depth),
+        crate::parser::NodeKind::Selector { .. } => return,
+    };
+
+    let mut sections = 0;
+    for child in children {
+        let is_section = matches!(child.node, crate::parser::NodeKind::Section { .. });
+        if is_section && depth == Some(0) {
+            continue;
+        }
 
-            for ci in children {
-                s += " ";
-                s += &to_plain(ci, (name_i, name), markdown);
+        sink.enter();
+        if is_section {
+            sections += 1;
+            let number = [number, &[sections]].concat();
+            emit(child, (name_i, name), &number, depth, other_names, sink);
+        } else {
+            emit(child, (name_i, name), &[], depth, other_names, sink);
+        }
+        sink.leave();
+    }
+}
+
+/// Renders the declared names and an indented outline of the document.
+///
+/// Each line is one node: sections show their heading, leaves show their kind, and aliases
+/// are shown in parentheses. Nodes nested deeper than `depth` levels are omitted.
+pub fn render_tree(doc: &Document, depth: Option<usize>) -> String {
+    fn walk(ast: &AST, indent: usize, depth: Option<usize>, out: &mut String) {
+        let Some((_, children)) = ast.take_section_like() else {
+            return;
+        };
+        if depth.is_some_and(|d| indent >= d) {
+            return;
+        }
+
+        for child in children {
+            let line = match &child.node {
+                crate::parser::NodeKind::Section { level, content, .. } => {
+                    format!("{} {content}", "#".repeat(*level))
+                }
+                crate::parser::NodeKind::Sen(..) => "sentences".to_string(),
+                crate::parser::NodeKind::All {
+                    all_or_names: Some(names),
+                    ..
+                } => format!("apply-all [{}]", names.join(", ")),
+                crate::parser::NodeKind::All { .. } => "apply-all all".to_string(),
+                crate::parser::NodeKind::Selector {
+                    local,
+                    path,
+                    trailing_dot,
+                    names,
+                    ..
+                } => format!(
+                    "selector #.{}{}{}{}",
+                    if *local { "/" } else { "" },
+                    path.iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join("."),
+                    if *trailing_dot { "." } else { "" },
+                    names
+                        .as_ref()
+                        .map(|names| format!("[{}]", names.join(", ")))
+                        .unwrap_or_default()
+                ),
+                crate::parser::NodeKind::Top { .. } => continue,
+            };
+
+            *out += &"  ".repeat(indent);
+            *out += &line;
+            if let Some(alias) = &child.meta.alias {
+                *out += &format!(" ({alias})");
             }
+            *out += "\n";
+
+            walk(child, indent + 1, depth, out);
         }
-        crate::parser::NodeKind::Top { children, .. } => {
-            for ci in children {
-                s += " ";
-                s += &to_plain(ci, (name_i, name), markdown);
+    }
+
+    let mut out = format!("names: {}\n", doc.names().join(", "));
+    walk(&doc.ast, 0, depth, &mut out);
+    out
+}
+
+/// One difference between two revisions of a document, found by [`diff`].
+///
+/// Nodes are identified by the alias paths of [`Document::alias_index`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// The aliased node only exists in the new document.
+    Added(Vec<String>),
+    /// The aliased node only exists in the old document.
+    Removed(Vec<String>),
+    /// The heading of the section changed.
+    SectionModified {
+        path: Vec<String>,
+        old: String,
+        new: String,
+    },
+    /// The rendered content of the sentences or apply-all block changed for `name`.
+    /// A name missing from one of the documents has empty content there.
+    ContentModified {
+        path: Vec<String>,
+        name: String,
+        old: String,
+        new: String,
+    },
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Change::Added(path) => write!(f, "+ {}", path.join(".")),
+            Change::Removed(path) => write!(f, "- {}", path.join(".")),
+            Change::SectionModified { path, old, new } => {
+                write!(f, "~ {}: {old:?} -> {new:?}", path.join("."))
             }
+            Change::ContentModified {
+                path,
+                name,
+                old,
+                new,
+            } => write!(f, "~ {}.{name}: {old:?} -> {new:?}", path.join(".")),
         }
-        _ => {}
     }
+}
 
-    s
+/// Compares the aliased nodes of two revisions of a document.
+///
+/// Removed and modified nodes are reported in the order of `old`, followed by the added ones
+/// in the order of `new`. Nodes without an alias are not compared.
+pub fn diff(old: &Document, new: &Document) -> Vec<Change> {
+    fn aliased_nodes(doc: &Document) -> Vec<(Vec<String>, &AST)> {
+        doc.alias_index()
+            .into_iter()
+            .filter_map(|(_, path, _)| {
+                let segments: Vec<_> = path
+                    .iter()
+                    .cloned()
+                    .map(crate::parser::PathSegment::Ident)
+                    .collect();
+                let node = *doc.ast.resolve_path(&segments).ok()?.first()?;
+                Some((path, node))
+            })
+            .collect()
+    }
+
+    fn content(doc: &Document, ast: &AST, name: &str) -> String {
+        doc.name_index(name)
+            .map(|index| plain_text(ast, (index, name)))
+            .unwrap_or_default()
+    }
+
+    let old_nodes = aliased_nodes(old);
+    let new_nodes = aliased_nodes(new);
+
+    let mut names: Vec<&String> = old.names().iter().collect();
+    names.extend(new.names().iter().filter(|n| old.name_index(n).is_none()));
+
+    let mut changes = vec![];
+    for (path, old_ast) in &old_nodes {
+        let Some((_, new_ast)) = new_nodes.iter().find(|(p, _)| p == path) else {
+            changes.push(Change::Removed(path.clone()));
+            continue;
+        };
+
+        match (&old_ast.node, &new_ast.node) {
+            (
+                crate::parser::NodeKind::Section {
+                    content: old_heading,
+                    ..
+                },
+                crate::parser::NodeKind::Section {
+                    content: new_heading,
+                    ..
+                },
+            ) => {
+                let (old_heading, new_heading) = (trim(old_heading), trim(new_heading));
+                if old_heading != new_heading {
+                    changes.push(Change::SectionModified {
+                        path: path.clone(),
+                        old: old_heading,
+                        new: new_heading,
+                    });
+                }
+            }
+            (crate::parser::NodeKind::Section { .. }, _)
+            | (_, crate::parser::NodeKind::Section { .. }) => {
+                // 文とセクションが入れ替わったら別物として扱う
+                changes.push(Change::Removed(path.clone()));
+                changes.push(Change::Added(path.clone()));
+            }
+            _ => {
+                for name in &names {
+                    let (old_content, new_content) =
+                        (content(old, old_ast, name), content(new, new_ast, name));
+                    if old_content != new_content {
+                        changes.push(Change::ContentModified {
+                            path: path.clone(),
+                            name: name.to_string(),
+                            old: old_content,
+                            new: new_content,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    changes.extend(
+        new_nodes
+            .into_iter()
+            .filter(|(path, _)| !old_nodes.iter().any(|(p, _)| p == path))
+            .map(|(path, _)| Change::Added(path)),
+    );
+
+    changes
+}
+
+/// Resolves escapes in `content`, collapses it onto one line and truncates it to at most
+/// `max` characters, ending with `…` when something was cut off.
+///
+/// Meant for short labels such as hovers and outlines.
+pub fn preview(content: &str, max: usize) -> String {
+    let s = trim(&normalize(content));
+    if s.chars().count() <= max {
+        return s;
+    }
+
+    let mut truncated: String = s.chars().take(max.saturating_sub(1)).collect();
+    truncated.truncate(truncated.trim_end().len());
+    if max > 0 {
+        truncated.push('…');
+    }
+    truncated
 }
 
 fn trim(s: &str) -> String {
     s.split_whitespace().collect::<Vec<_>>().join(" ")
 }
 
+/// Collapses the whitespace within each line like [`trim`] and drops the empty lines.
+fn trim_lines(s: &str) -> String {
+    s.lines()
+        .map(trim)
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Drops whitespace-only lines at the start and end while keeping the indentation in between.
+fn trim_blank_lines(s: &str) -> String {
+    let lines: Vec<&str> = s.lines().collect();
+    let start = lines
+        .iter()
+        .position(|l| !l.trim().is_empty())
+        .unwrap_or(lines.len());
+    let end = lines
+        .iter()
+        .rposition(|l| !l.trim().is_empty())
+        .map_or(start, |i| i + 1);
+
+    lines[start..end]
+        .iter()
+        .map(|l| l.trim_end())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// A backslash and the character it escapes.
+static ESCAPE: std::sync::LazyLock<regex::Regex> =
+    std::sync::LazyLock::new(|| regex::Regex::new(r"\\(.)").unwrap());
+
+fn resolve_redundant_escapes(s: &str) -> String {
+    ESCAPE
+        .replace_all(s, |caps: &regex::Captures| match &caps[1] {
+            "#" => "#".to_string(),
+            "/" => "/".to_string(),
+            other => format!("\\{other}"),
+        })
+        .into_owned()
+}
+
+/// Escapes the characters that Markdown would interpret inline with a backslash.
+fn escape_md(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        if matches!(
+            c,
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '#' | '~' | '|'
+        ) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
 fn normalize(s: &str) -> String {
-    let re = regex::Regex::new(r"\\(.)").unwrap();
-    re.replace_all(s, |caps: &regex::Captures| match &caps[1] {
-        "n" => "\n".to_string(),
-        "#" => "#".to_string(),
-        "/" => "/".to_string(),
-        "]" => "]".to_string(),
-        "}" => "}".to_string(),
-        "\\" => "\\".to_string(),
-        other => format!("\\{other}"),
-    })
-    .into_owned()
+    ESCAPE
+        .replace_all(s, |caps: &regex::Captures| match &caps[1] {
+            "n" => "\n".to_string(),
+            "#" => "#".to_string(),
+            "/" => "/".to_string(),
+            "]" => "]".to_string(),
+            "}" => "}".to_string(),
+            "\\" => "\\".to_string(),
+            other => format!("\\{other}"),
+        })
+        .into_owned()
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::formatter::Selector;
+    use crate::parser::{Document, Rule, SandParser};
+    use pest::Parser as _;
+
+    fn parse_doc(input: &str) -> Document {
+        let pairs = SandParser::parse(Rule::doc, input).unwrap();
+        pairs.try_into().unwrap()
+    }
+
+    fn selector(doc: &Document, input: &str) -> Selector {
+        let pairs = SandParser::parse(Rule::Selector, input).unwrap();
+        (doc, pairs).try_into().unwrap()
+    }
+
+    #[test]
+    fn covered_names() {
+        let doc = parse_doc(
+            r#"#(en, ja)
+#only_en# English only
+#{[en], { Only in English }}
+
+#both# Both
+#[Hello][こんにちは]
+"#,
+        );
+
+        assert_eq!(doc.covered_names(&selector(&doc, "#.only_en.")), [0]);
+        assert_eq!(
+            doc.covered_names(&selector(&doc, "#.only_en.ja")),
+            [] as [usize; 0]
+        );
+        assert_eq!(doc.covered_names(&selector(&doc, "#.both.")), [0, 1]);
+        assert_eq!(doc.covered_names(&selector(&doc, "#.")), [0, 1]);
+    }
+
+    #[test]
+    fn escaped_hash() {
+        let doc = parse_doc(
+            r#"#(en, ja)
+#sec# \#1 Title
+#[\#tag][\#タグ]
+\#not a section
+"#,
+        );
+
+        assert_eq!(
+            super::render_plain(&doc, &selector(&doc, "#.sec."), false),
+            ["#tag", "#タグ"]
+        );
+        assert_eq!(
+            super::render_plain(&doc, &selector(&doc, "#.en"), true),
+            ["\n\n# #1 Title\n\n#tag"]
+        );
+    }
+
+    #[test]
+    fn select_by_content() {
+        let doc = parse_doc(
+            r#"#(en, ja)
+## Introduction
+#[Hi][やあ]
+## Intro
+#[Hey][へい]
+## Summary
+#[Bye][じゃあ]
+"#,
+        );
+
+        // 完全一致が部分一致より優先される
+        assert_eq!(
+            super::render_plain(&doc, &selector(&doc, r#"#.{"Intro"}.en"#), false),
+            ["Hey"]
+        );
+        assert_eq!(
+            super::render_plain(&doc, &selector(&doc, r#"#.{"Sum"}."#), false),
+            ["Bye", "じゃあ"]
+        );
+    }
+
+    #[test]
+    fn select_glob() {
+        let doc = parse_doc(
+            r#"#(en, ja)
+#sec1# One
+#[Intro][前置き]
+#a## A
+#[Alpha][アルファ]
+#b## B
+#[Beta][ベータ]
+#sec2# Two
+#[Other][他]
+"#,
+        );
+
+        assert_eq!(
+            super::render_plain(&doc, &selector(&doc, "#.sec1.*.en"), false),
+            ["Alpha Beta"]
+        );
+        assert_eq!(
+            super::render_plain(&doc, &selector(&doc, "#.*."), false),
+            ["Intro Alpha Beta Other", "前置き アルファ ベータ 他"]
+        );
+        assert_eq!(
+            super::render_plain(&doc, &selector(&doc, "#.sec2.*.ja"), false),
+            [""]
+        );
+    }
+
+    #[test]
+    fn select_name_list() {
+        use crate::parser::{ParseError, SelectorError};
+
+        let doc = parse_doc(
+            r#"#(en, ja, fr)
+#sec1# One
+#sec2## Two
+#[Hello][こんにちは][Bonjour]
+"#,
+        );
+
+        let sel = selector(&doc, "#.sec1.sec2[fr, en]");
+        assert_eq!(super::render_plain(&doc, &sel, false), ["Bonjour", "Hello"]);
+        assert_eq!(sel.target_names(&doc), ["fr", "en"]);
+        assert_eq!(sel.name(), None);
+
+        let sel = selector(&doc, "#.[ja]");
+        assert_eq!(super::render_plain(&doc, &sel, false), ["こんにちは"]);
+
+        let pairs = SandParser::parse(Rule::Selector, "#.sec1[en, de]").unwrap();
+        let errs = Selector::try_from((&doc, pairs)).unwrap_err();
+        assert_eq!(
+            errs,
+            [ParseError::Selector(
+                SelectorError::UnknownName("de".to_string()),
+                crate::parser::Span { start: 0, end: 14 }
+            )]
+        );
+    }
+
+    #[test]
+    fn diff_documents() {
+        use super::{Change, diff};
+
+        let old = parse_doc(
+            r#"#(en, ja)
+#intro# Introduction
+#greeting[Hello][こんにちは]
+#usage## Usage
+#old[Old][古い]
+"#,
+        );
+        let new = parse_doc(
+            r#"#(en, ja, fr)
+#intro# Overview
+#greeting[Hello][こんにちは][Bonjour]
+#usage## Usage
+#extra[More][もっと][Plus]
+"#,
+        );
+
+        let path = |p: &[&str]| p.iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert_eq!(
+            diff(&old, &new),
+            [
+                Change::SectionModified {
+                    path: path(&["intro"]),
+                    old: "Introduction".to_string(),
+                    new: "Overview".to_string(),
+                },
+                Change::ContentModified {
+                    path: path(&["intro", "greeting"]),
+                    name: "fr".to_string(),
+                    old: String::new(),
+                    new: "Bonjour".to_string(),
+                },
+                Change::Removed(path(&["intro", "usage", "old"])),
+                Change::Added(path(&["intro", "usage", "extra"])),
+            ]
+        );
+        assert!(diff(&new, &new).is_empty());
+        assert_eq!(
+            diff(&old, &new)[1].to_string(),
+            r#"~ intro.greeting.fr: "" -> "Bonjour""#
+        );
+    }
+
+    #[test]
+    fn markdown_options() {
+        use super::{MarkdownOptions, render_markdown, render_plain};
+
+        let doc = parse_doc("#(en, ja)\n#sec# Title\n#[Hi][やあ]\n#{{!}}\n");
+        let sel = selector(&doc, "#.en");
+
+        assert_eq!(
+            render_markdown(&doc, &sel, &MarkdownOptions::default()),
+            render_plain(&doc, &sel, true)
+        );
+        assert_eq!(render_plain(&doc, &sel, true), ["\n\n# Title\n\nHi !"]);
+
+        let options = MarkdownOptions {
+            blank_lines_around_headers: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            render_markdown(&doc, &sel, &options),
+            ["\n\n\n# Title\n\n\nHi !"]
+        );
+
+        let options = MarkdownOptions {
+            child_separator: String::new(),
+            ..Default::default()
+        };
+        assert_eq!(
+            render_markdown(&doc, &sel, &options),
+            ["\n\n# Title\n\nHi!"]
+        );
+    }
+
+    #[test]
+    fn selector_from_path() {
+        use super::render_plain;
+
+        let doc = parse_doc("#(en, ja)\n#intro# Intro\n#[Hello][こんにちは]\n");
+
+        for (path, trailing_dot, input) in [
+            (vec!["intro", "en"], false, "#.intro.en"),
+            (vec!["intro"], true, "#.intro."),
+            (vec!["ja"], false, "#.ja"),
+        ] {
+            let path = path.into_iter().map(String::from).collect();
+            assert_eq!(
+                render_plain(&doc, &Selector::from_path(false, path, trailing_dot), false),
+                render_plain(&doc, &selector(&doc, input), false)
+            );
+        }
+    }
+
+    #[test]
+    fn retain_sections_where() {
+        use super::render_plain;
+
+        let mut doc = parse_doc(
+            r#"#(en, ja)
+#a#{status=published} A
+#[Hello][こんにちは]
+#sub## Sub
+#[Sub][サブ]
+#b#{status=draft} B
+#[Secret][秘密]
+#c##{status=published} C
+#[Shown][表示]
+#d#{status=draft} D
+#[Hidden][隠し]
+"#,
+        );
+        doc.retain_sections_where("status", "published");
+
+        assert_eq!(
+            render_plain(&doc, &selector(&doc, "#.en"), false),
+            ["Hello Sub Shown"]
+        );
+        assert_eq!(
+            render_plain(&doc, &selector(&doc, "#.b.c.en"), false),
+            ["Shown"]
+        );
+
+        let pairs = SandParser::parse(Rule::Selector, "#.d.en").unwrap();
+        assert!(Selector::try_from((&doc, pairs)).is_err());
+    }
+
+    #[test]
+    fn translation_table() {
+        let doc = parse_doc(
+            "#(en, ja, fr)\n#intro# Intro\n#hi[Hello][こんにちは][Bonjour]\n#[Bye][さようなら][ Au revoir ]\n",
+        );
+
+        let table = doc.translation_table();
+        assert_eq!(table.len(), 2);
+        assert!(table.iter().all(|(_, row)| row.len() == 3));
+        assert_eq!(table[0].0, ["intro", "hi"]);
+        assert_eq!(table[1].0, ["intro", "1"]);
+        assert_eq!(table[1].1[2].as_deref(), Some("Au revoir"));
+    }
+
+    #[test]
+    fn number_sections() {
+        use super::{MarkdownOptions, RenderOptions, render};
+
+        let doc = parse_doc("#(en, ja)\n## One\n### A\n### B\n## Two\n### C\n#[Hi][x]\n### D\n");
+        let sel = selector(&doc, "#.en");
+
+        let options = RenderOptions {
+            number_sections: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            render(&doc, &sel, &options),
+            ["\n1 One\n\n1.1 A\n\n1.2 B\n\n2 Two\n\n2.1 C\nHi\n2.2 D"]
+        );
+
+        let options = RenderOptions {
+            markdown: Some(MarkdownOptions {
+                blank_lines_around_headers: 0,
+                ..Default::default()
+            }),
+            number_sections: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            render(&doc, &sel, &options),
+            ["\n# 1 One\n\n## 1.1 A\n\n## 1.2 B\n\n# 2 Two\n\n## 2.1 C\nHi\n## 2.2 D"]
+        );
+    }
+
+    #[test]
+    fn render_with_custom_sink() {
+        use super::{RenderSink, render_with};
+
+        #[derive(Default)]
+        struct Events(Vec<String>);
+
+        impl RenderSink for Events {
+            fn enter(&mut self) {
+                self.0.push("enter".to_string());
+            }
+
+            fn leave(&mut self) {
+                self.0.push("leave".to_string());
+            }
+
+            fn section(&mut self, level: usize, content: &str, number: &[usize]) {
+                self.0.push(format!("section {level} {content} {number:?}"));
+            }
+
+            fn text(&mut self, name_i: usize, s: &str) {
+                self.0.push(format!("text {name_i} {s}"));
+            }
+        }
+
+        let doc = parse_doc("#(en, ja)\n## One\n#[Hi][やあ]\n### A\n#{[en], {Only en}}\n");
+
+        let mut events = Events::default();
+        render_with(&doc, &selector(&doc, "#.ja"), 1, &mut events);
+        assert_eq!(
+            events.0,
+            [
+                "enter",
+                "enter",
+                "section 1 One [1]",
+                "enter",
+                "text 1 やあ",
+                "leave",
+                "enter",
+                "section 2 A [1, 1]",
+                "enter",
+                "leave",
+                "leave",
+                "leave",
+                "leave",
+            ]
+        );
+    }
+
+    #[test]
+    fn render_single_name() {
+        use super::{render_name, render_plain};
+
+        let doc = parse_doc("#(en, ja)\n#sec# Section\n#[Hello][こんにちは]\n");
+        let sel = selector(&doc, "#.sec.");
+
+        for markdown in [false, true] {
+            assert_eq!(
+                render_name(&doc, &sel, 1, markdown).as_ref(),
+                Some(&render_plain(&doc, &sel, markdown)[1])
+            );
+        }
+        assert_eq!(render_name(&doc, &sel, 2, false), None);
+    }
+
+    #[test]
+    fn render_labeled_pairs_names() {
+        use super::render_plain_labeled;
+
+        let doc = parse_doc("#(en, ja)\n#sec# Section\n#[Hello][こんにちは]\n");
+
+        assert_eq!(
+            render_plain_labeled(&doc, &selector(&doc, "#.sec."), false),
+            [
+                ("en".to_string(), "Hello".to_string()),
+                ("ja".to_string(), "こんにちは".to_string()),
+            ]
+        );
+        assert_eq!(
+            render_plain_labeled(&doc, &selector(&doc, "#.sec.ja"), false),
+            [("ja".to_string(), "こんにちは".to_string())]
+        );
+    }
+
+    #[test]
+    fn max_depth() {
+        use super::{RenderOptions, render};
+
+        let doc = parse_doc(
+            "#(en, ja)\n## One\n#[A][あ]\n### Two\n#[B][い]\n#### Three\n#[C][う]\n## Four\n#[D][え]\n",
+        );
+        let options = |max_depth| RenderOptions {
+            max_depth,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            render(&doc, &selector(&doc, "#.en"), &options(None)),
+            ["A B C D"]
+        );
+        assert_eq!(
+            render(&doc, &selector(&doc, "#.en"), &options(Some(1))),
+            ["A D"]
+        );
+        assert_eq!(
+            render(&doc, &selector(&doc, "#.0.en"), &options(Some(2))),
+            ["A B"]
+        );
+        assert_eq!(
+            render(&doc, &selector(&doc, "#.0.en"), &options(Some(0))),
+            [""]
+        );
+    }
+
+    #[test]
+    fn escape_markdown() {
+        use super::{MarkdownOptions, render_markdown, render_plain};
+
+        let doc = parse_doc("#(en, ja)\n#sec# A_B\n#[Is it *bold?*][x]\n");
+        let sel = selector(&doc, "#.en");
+
+        assert_eq!(
+            render_plain(&doc, &sel, true),
+            ["\n\n# A_B\n\nIs it *bold?*"]
+        );
+
+        let options = MarkdownOptions {
+            escape_content: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            render_markdown(&doc, &sel, &options),
+            ["\n\n# A\\_B\n\nIs it \\*bold?\\*"]
+        );
+    }
+
+    #[test]
+    fn preserve_whitespace() {
+        use super::{RenderOptions, WhitespacePolicy, render, render_plain};
+
+        let doc = parse_doc("#(en, ja)\n#[\n    first\n        indented\n    back\\n\n][x]\n");
+        let sel = selector(&doc, "#.en");
+
+        assert_eq!(render_plain(&doc, &sel, false), ["first indented back"]);
+
+        let options = RenderOptions {
+            whitespace: WhitespacePolicy::Raw,
+            ..Default::default()
+        };
+        assert_eq!(
+            render(&doc, &sel, &options),
+            ["    first\n        indented\n    back"]
+        );
+    }
+
+    #[test]
+    fn only_other_names() {
+        use super::{RenderOptions, render};
+
+        let doc = parse_doc(
+            "#(en, ja)\n#[Hello][こんにちは]\n#{[en], { English only }}\n#{all, { Both }}\n",
+        );
+        let options = RenderOptions {
+            only_other_names: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            render(&doc, &selector(&doc, "#.ja"), &RenderOptions::default()),
+            ["こんにちは Both"]
+        );
+        assert_eq!(
+            render(&doc, &selector(&doc, "#.ja"), &options),
+            ["こんにちは English only"]
+        );
+        assert_eq!(render(&doc, &selector(&doc, "#.en"), &options), ["Hello"]);
+    }
+
+    #[test]
+    fn whitespace_policies() {
+        use super::{RenderOptions, WhitespacePolicy, render};
+
+        let doc = parse_doc("#(en, ja)\n#[First  line\n  second\tline][x]\n");
+        let sel = selector(&doc, "#.en");
+        let rendered = |whitespace| {
+            render(
+                &doc,
+                &sel,
+                &RenderOptions {
+                    whitespace,
+                    ..Default::default()
+                },
+            )
+        };
+
+        assert_eq!(
+            rendered(WhitespacePolicy::CollapseAll),
+            ["First line second line"]
+        );
+        assert_eq!(
+            rendered(WhitespacePolicy::PreserveNewlines),
+            ["First line\nsecond line"]
+        );
+        assert_eq!(
+            rendered(WhitespacePolicy::Raw),
+            ["First  line\n  second\tline"]
+        );
+    }
+
+    #[test]
+    fn render_tree() {
+        let doc = parse_doc(
+            r#"#(en, ja)
+#intro# Intro
+#s1[Hello][こんにちは]
+#sub## Sub
+#{[en], { Only }}
+#.intro.s1.
+
+## Other
+"#,
+        );
+
+        assert_eq!(
+            super::render_tree(&doc, None),
+            "names: en, ja
+# Intro (intro)
+  sentences (s1)
+  ## Sub (sub)
+    apply-all [en]
+    selector #.intro.s1.
+# Other
+"
+        );
+        assert_eq!(
+            super::render_tree(&doc, Some(1)),
+            "names: en, ja\n# Intro (intro)\n# Other\n"
+        );
+    }
+
     #[test]
     fn trim() -> Result<(), Box<dyn std::error::Error>> {
         use super::trim;
@@ -199,4 +1727,78 @@ I'm thrilled!!
 
         Ok(())
     }
+
+    #[test]
+    fn write_matches_string() {
+        use super::{MarkdownOptions, RenderOptions, render_plain, to_plain_write, trim};
+
+        let doc = parse_doc(
+            r#"#(en, ja)
+#sec1# One
+#[Hello][こんにちは]
+#sec2## Two
+#{all, {!}}
+#sec3### Three
+#{[ja], { :) }}
+"#,
+        );
+        let sec1 = doc.ast.content_children()[0];
+        let sel = Selector::from_path(false, vec!["sec1".to_string()], true);
+
+        for markdown in [false, true] {
+            let rendered = render_plain(&doc, &sel, markdown);
+            assert_eq!(rendered.len(), 2);
+            let options = RenderOptions {
+                markdown: markdown.then(MarkdownOptions::default),
+                ..Default::default()
+            };
+
+            for (name, expected) in [(0, "en"), (1, "ja")].into_iter().zip(rendered) {
+                let mut out = String::new();
+                to_plain_write(sec1, name, &options, &mut out).unwrap();
+
+                // render と同じように各行の空白をまとめる
+                let out = out.lines().map(trim).collect::<Vec<_>>().join("\n");
+                assert_eq!(out, expected, "{name:?}");
+            }
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_matches_sequential() {
+        use super::{render_plain, render_plain_parallel};
+
+        let doc = parse_doc(
+            r#"#(en, ja, fr, de)
+#sec1# One
+#[Hello][こんにちは][Bonjour][Hallo]
+#sec2## Two
+#{all, {!}}
+#{[ja, de], { :) }}
+"#,
+        );
+
+        for input in ["#.", "#.sec1.", "#.sec1.sec2.", "#.sec1.ja"] {
+            let sel = selector(&doc, input);
+            for markdown in [false, true] {
+                assert_eq!(
+                    render_plain_parallel(&doc, &sel, markdown),
+                    render_plain(&doc, &sel, markdown)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn preview_truncates() {
+        use super::preview;
+
+        assert_eq!(preview("  Hello\\nworld  ", 20), "Hello world");
+        assert_eq!(preview(r"A \# B \] C", 20), "A # B ] C");
+        assert_eq!(preview("こんにちは世界", 7), "こんにちは世界");
+        assert_eq!(preview("こんにちは世界", 4), "こんに…");
+        assert_eq!(preview("Hello world", 7), "Hello…");
+        assert_eq!(preview("Hello", 0), "");
+    }
 }
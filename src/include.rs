@@ -0,0 +1,278 @@
+use std::path::{Path, PathBuf};
+
+use rustc_hash::FxHashSet;
+use thiserror::Error;
+
+/// A document with its `@include "path"` lines expanded, ready for [`crate::parser::SandParser`].
+///
+/// Included files are placed before the file that includes them, in the order of the
+/// `@include` lines, and the `@include` lines themselves are blanked out with spaces. Every
+/// file keeps its own byte offsets within [`Source::text`], so spans can be mapped back with
+/// [`Source::locate`].
+///
+/// ```
+/// use std::path::{Path, PathBuf};
+///
+/// let source = sand::include::expand_with(Path::new("doc.sand"), |path| {
+///     Ok(match path.to_str().unwrap() {
+///         "doc.sand" => "@include \"names.sand\"\n#[Hi][やあ]\n",
+///         _ => "#(en, ja)\n",
+///     }
+///     .to_string())
+/// })
+/// .unwrap();
+///
+/// assert!(source.text().starts_with("#(en, ja)\n"));
+/// assert_eq!(source.files()[1].path, PathBuf::from("doc.sand"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Source {
+    text: String,
+    files: Vec<SourceFile>,
+}
+
+/// One file within a [`Source`].
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    /// The contents as read, including the `@include` lines, without a leading BOM.
+    pub contents: String,
+    /// Where the contents start in [`Source::text`].
+    pub offset: usize,
+}
+
+#[derive(Error, Debug)]
+pub enum IncludeError {
+    #[error("failed to read {}: {source}", .path.display())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// The file that is included again while it is being expanded.
+    #[error("{} includes itself", .0.display())]
+    Cycle(PathBuf),
+}
+
+impl Source {
+    /// The text to parse.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The files in the order they appear in [`Source::text`]. The file passed to [`expand`]
+    /// is the last one.
+    pub fn files(&self) -> &[SourceFile] {
+        &self.files
+    }
+
+    /// Returns the index in [`Source::files`] and the offset within that file for an offset
+    /// in [`Source::text`].
+    pub fn locate(&self, offset: usize) -> (usize, usize) {
+        let index = self
+            .files
+            .partition_point(|file| file.offset <= offset)
+            .saturating_sub(1);
+        let file = &self.files[index];
+
+        (
+            index,
+            offset.saturating_sub(file.offset).min(file.contents.len()),
+        )
+    }
+}
+
+/// Reads `path` and expands its `@include` lines, see [`Source`].
+///
+/// Included paths are relative to the file that includes them. A file that is included more
+/// than once, e.g. by two files that both include it, is only expanded the first time.
+pub fn expand(path: &Path) -> Result<Source, IncludeError> {
+    expand_with(path, |path| std::fs::read_to_string(path))
+}
+
+/// Like [`expand`], but reads the files with `read`.
+pub fn expand_with<F>(path: &Path, mut read: F) -> Result<Source, IncludeError>
+where
+    F: FnMut(&Path) -> std::io::Result<String>,
+{
+    let mut source = Source {
+        text: String::new(),
+        files: vec![],
+    };
+    let mut stack = vec![];
+    let mut included = FxHashSet::default();
+
+    expand_into(path, &mut read, &mut stack, &mut included, &mut source)?;
+
+    Ok(source)
+}
+
+fn expand_into<F>(
+    path: &Path,
+    read: &mut F,
+    stack: &mut Vec<PathBuf>,
+    included: &mut FxHashSet<PathBuf>,
+    source: &mut Source,
+) -> Result<(), IncludeError>
+where
+    F: FnMut(&Path) -> std::io::Result<String>,
+{
+    // `a.sand`と`./a.sand`のように書き方が違っても同じファイルとして扱う
+    let key = canonicalize(path);
+    if stack.contains(&key) {
+        return Err(IncludeError::Cycle(path.to_path_buf()));
+    }
+    if !included.insert(key.clone()) {
+        return Ok(());
+    }
+
+    let contents = read(path).map_err(|source| IncludeError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let contents = crate::parser::strip_bom(&contents).to_string();
+
+    stack.push(key);
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let mut blanked = String::with_capacity(contents.len());
+
+    for line in contents.split_inclusive('\n') {
+        let body = line.trim_end_matches(['\r', '\n']);
+
+        if let Some(included_path) = include_target(body) {
+            expand_into(&dir.join(included_path), read, stack, included, source)?;
+            // 行をスペースで埋めて、元のファイルのオフセットを保つ
+            blanked += &" ".repeat(body.len());
+            blanked += &line[body.len()..];
+        } else {
+            blanked += line;
+        }
+    }
+    stack.pop();
+
+    source.files.push(SourceFile {
+        path: path.to_path_buf(),
+        contents,
+        offset: source.text.len(),
+    });
+    source.text += &blanked;
+    if !source.text.is_empty() && !source.text.ends_with('\n') {
+        source.text.push('\n');
+    }
+
+    Ok(())
+}
+
+/// The path that identifies the file at `path`: the canonical path if the file exists,
+/// otherwise `path` with the `.` and `..` components resolved.
+pub(crate) fn canonicalize(path: &Path) -> PathBuf {
+    use std::path::Component;
+
+    std::fs::canonicalize(path).unwrap_or_else(|_| {
+        let mut normalized = PathBuf::new();
+        for component in path.components() {
+            match component {
+                Component::CurDir => {}
+                Component::ParentDir
+                    if matches!(
+                        normalized.components().next_back(),
+                        Some(Component::Normal(_))
+                    ) =>
+                {
+                    normalized.pop();
+                }
+                component => normalized.push(component),
+            }
+        }
+        normalized
+    })
+}
+
+/// The path of an `@include "path"` line.
+fn include_target(line: &str) -> Option<&str> {
+    line.trim()
+        .strip_prefix("@include")?
+        .trim_start()
+        .strip_prefix('"')?
+        .strip_suffix('"')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{IncludeError, expand_with};
+
+    fn read(path: &Path) -> std::io::Result<String> {
+        let contents = match path.to_str().unwrap() {
+            "doc.sand" => "@include \"common/names.sand\"\n#[Hi][やあ]\n",
+            "common/names.sand" => "#(en, ja)",
+            "bom.sand" => "\u{feff}@include \"common/names.sand\"\n",
+            "a.sand" => "@include \"b.sand\"\n",
+            "b.sand" => "  @include \"./a.sand\"\n",
+            "diamond.sand" => "@include \"left.sand\"\n@include \"right.sand\"\n#[Hi][やあ]\n",
+            "left.sand" | "right.sand" => "@include \"common/names.sand\"\n",
+            _ => return Err(std::io::ErrorKind::NotFound.into()),
+        };
+        Ok(contents.to_string())
+    }
+
+    #[test]
+    fn include() {
+        let source = expand_with(Path::new("doc.sand"), read).unwrap();
+
+        assert_eq!(
+            source.text(),
+            format!("#(en, ja)\n{}\n#[Hi][やあ]\n", " ".repeat(28))
+        );
+
+        let files = source.files();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].path, PathBuf::from("common/names.sand"));
+        assert_eq!(files[1].path, PathBuf::from("doc.sand"));
+        assert_eq!(files[1].offset, 10);
+
+        assert_eq!(source.locate(2), (0, 2));
+        assert_eq!(source.locate(39), (1, 29));
+    }
+
+    #[test]
+    fn include_with_bom() {
+        let source = expand_with(Path::new("bom.sand"), read).unwrap();
+
+        assert_eq!(
+            source.files()[1].contents,
+            "@include \"common/names.sand\"\n"
+        );
+        assert!(source.text().starts_with("#(en, ja)\n"));
+    }
+
+    #[test]
+    fn include_cycle() {
+        let err = expand_with(Path::new("a.sand"), read).unwrap_err();
+        assert!(matches!(err, IncludeError::Cycle(path) if path == Path::new("./a.sand")));
+
+        let err = expand_with(Path::new("missing.sand"), read).unwrap_err();
+        assert!(matches!(err, IncludeError::Io { .. }));
+    }
+
+    #[test]
+    fn include_twice() {
+        let source = expand_with(Path::new("diamond.sand"), read).unwrap();
+
+        let paths: Vec<_> = source
+            .files()
+            .iter()
+            .map(|file| file.path.to_str().unwrap())
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                "common/names.sand",
+                "left.sand",
+                "right.sand",
+                "diamond.sand"
+            ]
+        );
+        assert_eq!(source.text().matches("#(en, ja)").count(), 1);
+    }
+}
@@ -1,3 +1,8 @@
+pub mod builder;
 pub mod formatter;
+pub mod include;
+pub mod lint;
 pub mod lsp;
 pub mod parser;
+
+pub use lsp::{convert_parse_error_to_diagnostic, convert_parse_errors_to_diagnostics};
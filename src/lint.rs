@@ -0,0 +1,482 @@
+use thiserror::Error;
+
+use crate::parser::{AST, Document, NodeKind, PathSegment, Span};
+
+/// Non-fatal findings on a document that parsed successfully.
+#[derive(Error, Debug, Hash, PartialEq, Eq)]
+pub enum Lint {
+    #[error("this section has no sentences or apply-all blocks")]
+    EmptySection(Span),
+    #[error("apply-all targets a name that is not defined: {0}")]
+    UnknownApplyAllTarget(String, Span),
+    /// The span is the name in the declaration.
+    #[error("nothing in the document has content for the name: {0}")]
+    UncoveredName(String, Span),
+    /// An aliased section that no selector renders and that has the same text for every
+    /// name. Nested sections of a reported one are not reported.
+    #[error(
+        "no selector uses this section and it reads the same for every name, so it could be an apply-all block"
+    )]
+    UniformSection(Span),
+    /// A selector whose path has both numeric indices and aliases, e.g. `#.0.sec2.en`.
+    /// The name at the end of the path doesn't count as an alias.
+    #[error(
+        "this selector mixes indices and aliases; using one or the other keeps it readable when sections move"
+    )]
+    MixedSelectorPath(Span),
+    /// Nothing but the names is written, see [`Document::is_empty`]. The span covers the
+    /// declared names. No other lint is reported for such a document.
+    #[error("the document declares names but has no content")]
+    NoContent(Span),
+    /// A section nested deeper than [`LintOptions::max_section_depth`], with the limit. Only
+    /// the outermost such section is reported, the span is its heading.
+    #[error("sections are nested more than {0} levels deep")]
+    DeepSection(usize, Span),
+    /// An alias that no selector path goes through. The span is the aliased node.
+    #[error("no selector uses the alias: {0}")]
+    UnusedAlias(String, Span),
+}
+
+/// How serious a [`Lint`] is, from the least.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// A suggestion, e.g. about the style of the document.
+    Information,
+    /// Likely a mistake, though the document renders.
+    Warning,
+}
+
+impl Lint {
+    /// A stable kebab-case identifier of the kind of lint, e.g. `empty-section`.
+    pub fn id(&self) -> &'static str {
+        match self {
+            Lint::EmptySection(_) => "empty-section",
+            Lint::UnknownApplyAllTarget(..) => "unknown-apply-all-target",
+            Lint::UncoveredName(..) => "uncovered-name",
+            Lint::UniformSection(_) => "uniform-section",
+            Lint::MixedSelectorPath(_) => "mixed-selector-path",
+            Lint::NoContent(_) => "no-content",
+            Lint::DeepSection(..) => "deep-section",
+            Lint::UnusedAlias(..) => "unused-alias",
+        }
+    }
+
+    /// The part of the source the lint is about.
+    pub fn span(&self) -> &Span {
+        match self {
+            Lint::EmptySection(span)
+            | Lint::UnknownApplyAllTarget(_, span)
+            | Lint::UncoveredName(_, span)
+            | Lint::UniformSection(span)
+            | Lint::MixedSelectorPath(span)
+            | Lint::NoContent(span)
+            | Lint::DeepSection(_, span)
+            | Lint::UnusedAlias(_, span) => span,
+        }
+    }
+
+    /// How serious the lint is.
+    pub fn severity(&self) -> Severity {
+        match self {
+            Lint::UncoveredName(..)
+            | Lint::UniformSection(_)
+            | Lint::MixedSelectorPath(_)
+            | Lint::UnusedAlias(..) => Severity::Information,
+            Lint::EmptySection(_)
+            | Lint::UnknownApplyAllTarget(..)
+            | Lint::NoContent(_)
+            | Lint::DeepSection(..) => Severity::Warning,
+        }
+    }
+}
+
+/// The default of [`LintOptions::max_section_depth`].
+pub const DEFAULT_MAX_SECTION_DEPTH: usize = 32;
+
+/// Settings for [`check_with`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintOptions {
+    /// Sections nested deeper than this are reported as [`Lint::DeepSection`].
+    pub max_section_depth: usize,
+    /// Report [`Lint::UnusedAlias`], off by default.
+    pub unused_aliases: bool,
+    /// Report [`Lint::MixedSelectorPath`], off by default.
+    pub mixed_selector_paths: bool,
+    /// Report [`Lint::UniformSection`], off by default.
+    pub uniform_sections: bool,
+}
+
+impl Default for LintOptions {
+    fn default() -> Self {
+        Self {
+            max_section_depth: DEFAULT_MAX_SECTION_DEPTH,
+            unused_aliases: false,
+            mixed_selector_paths: false,
+            uniform_sections: false,
+        }
+    }
+}
+
+/// Collects every lint found in `doc`, in document order.
+pub fn check(doc: &Document) -> Vec<Lint> {
+    check_with(doc, &LintOptions::default())
+}
+
+/// Like [`check`], with the limits in `options`.
+pub fn check_with(doc: &Document, options: &LintOptions) -> Vec<Lint> {
+    if doc.is_empty()
+        && let (Some(first), Some(last)) = (doc.name_spans.first(), doc.name_spans.last())
+    {
+        return vec![Lint::NoContent(Span {
+            start: first.start,
+            end: last.end,
+        })];
+    }
+
+    let mut lints = vec![];
+
+    for (index, count) in doc.name_coverage() {
+        if count == 0 {
+            lints.push(Lint::UncoveredName(
+                doc.names()[index].clone(),
+                doc.name_spans[index].clone(),
+            ));
+        }
+    }
+
+    for ast in doc.ast.iter() {
+        match &ast.node {
+            NodeKind::Section { children, .. } if children.is_empty() => {
+                lints.push(Lint::EmptySection(ast.get_span()));
+            }
+            NodeKind::All {
+                all_or_names: Some(targets),
+                ..
+            } => {
+                for target in targets {
+                    if doc.name_index(target).is_none() {
+                        lints.push(Lint::UnknownApplyAllTarget(target.clone(), ast.get_span()));
+                    }
+                }
+            }
+            NodeKind::Selector {
+                path,
+                trailing_dot,
+                names,
+                ..
+            } if options.mixed_selector_paths => {
+                // 末尾が名前ならエイリアスではない
+                let path = if *trailing_dot || names.is_some() || path.is_empty() {
+                    &path[..]
+                } else {
+                    &path[..path.len() - 1]
+                };
+                let idents: Vec<_> = path.iter().filter_map(PathSegment::as_ident).collect();
+                let numeric = |s: &&str| s.parse::<usize>().is_ok();
+
+                if idents.iter().any(numeric) && !idents.iter().all(numeric) {
+                    lints.push(Lint::MixedSelectorPath(ast.get_span()));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    lints.extend(deep_sections(doc, options.max_section_depth));
+    if options.uniform_sections {
+        lints.extend(uniform_sections(doc));
+    }
+    if options.unused_aliases {
+        lints.extend(unused_aliases(doc));
+    }
+
+    lints
+}
+
+fn deep_sections(doc: &Document, max: usize) -> Vec<Lint> {
+    // 見出しのレベルは飛ばせるので、入れ子の深さは数えて求める
+    fn walk(ast: &AST, depth: usize, max: usize, lints: &mut Vec<Lint>) {
+        let Some((_, children)) = ast.take_section_like() else {
+            return;
+        };
+
+        for child in children {
+            if !matches!(child.node, NodeKind::Section { .. }) {
+                continue;
+            }
+            if depth + 1 > max {
+                lints.push(Lint::DeepSection(max, child.get_span()));
+            } else {
+                walk(child, depth + 1, max, lints);
+            }
+        }
+    }
+
+    let mut lints = vec![];
+    walk(&doc.ast, 0, max, &mut lints);
+    lints
+}
+
+fn uniform_sections(doc: &Document) -> Vec<Lint> {
+    if doc.names().len() < 2 {
+        return vec![];
+    }
+
+    let selected = selected_spans(doc);
+    let mut reported: Vec<Span> = vec![];
+
+    for (_, path, span) in doc.alias_index() {
+        let within = |outer: &Span| outer.start <= span.start && span.end <= outer.end;
+        if selected.iter().chain(&reported).any(within) {
+            continue;
+        }
+
+        let segments: Vec<_> = path.into_iter().map(PathSegment::Ident).collect();
+        let Some(node) = doc
+            .ast
+            .resolve_path(&segments)
+            .ok()
+            .and_then(|nodes| nodes.first().copied())
+            .filter(|node| matches!(node.node, NodeKind::Section { .. }))
+        else {
+            continue;
+        };
+
+        let first = crate::formatter::plain_text(node, (0, &doc.names()[0]));
+        let uniform =
+            !first.is_empty()
+                && doc.names().iter().enumerate().skip(1).all(|(index, name)| {
+                    crate::formatter::plain_text(node, (index, name)) == first
+                });
+
+        if uniform {
+            reported.push(span);
+        }
+    }
+
+    reported.into_iter().map(Lint::UniformSection).collect()
+}
+
+fn unused_aliases(doc: &Document) -> Vec<Lint> {
+    let mut referenced = vec![];
+    for_each_selector(doc, |base, path| {
+        // 途中の節もセレクタから参照されている
+        for end in 1..=path.len() {
+            if let Ok(nodes) = base.resolve_path(&path[..end]) {
+                referenced.extend(nodes.iter().map(|node| node.get_span()));
+            }
+        }
+    });
+
+    doc.alias_index()
+        .into_iter()
+        .filter(|(_, _, span)| !referenced.contains(span))
+        .map(|(alias, _, span)| Lint::UnusedAlias(alias, span))
+        .collect()
+}
+
+/// The spans of the nodes rendered by the selectors in `doc`.
+fn selected_spans(doc: &Document) -> Vec<Span> {
+    let mut spans = vec![];
+    for_each_selector(doc, |base, path| {
+        if let Ok(targets) = base.resolve_path(path) {
+            spans.extend(targets.iter().map(|target| target.get_span()));
+        }
+    });
+    spans
+}
+
+/// Calls `f` with the node each selector in `doc` starts from and its path without the name
+/// at the end.
+fn for_each_selector<'a>(doc: &'a Document, mut f: impl FnMut(&'a AST, &'a [PathSegment])) {
+    fn walk<'a>(ast: &'a AST, doc: &'a Document, f: &mut impl FnMut(&'a AST, &'a [PathSegment])) {
+        let Some((_, children)) = ast.take_section_like() else {
+            return;
+        };
+
+        for child in children {
+            if let NodeKind::Selector {
+                local,
+                path,
+                trailing_dot,
+                names,
+                ..
+            } = &child.node
+            {
+                let base = if *local { ast } else { &doc.ast };
+                let path = if *trailing_dot || names.is_some() || path.is_empty() {
+                    &path[..]
+                } else {
+                    &path[..path.len() - 1]
+                };
+                f(base, path);
+            }
+            walk(child, doc, f);
+        }
+    }
+
+    walk(&doc.ast, doc, &mut f);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Lint, check};
+    use crate::parser::{Document, Rule, SandParser};
+    use pest::Parser as _;
+
+    fn parse_doc(input: &str) -> Document {
+        let pairs = SandParser::parse(Rule::doc, input).unwrap();
+        pairs.try_into().unwrap()
+    }
+
+    #[test]
+    fn empty_section_and_unknown_target() {
+        let doc = parse_doc(
+            r#"
+#(en, ja)
+#empty# Empty
+#full# Full
+#{[en, fr], { Hello }}
+#{[ja], { やあ }}
+"#,
+        );
+        let lints = check(&doc);
+
+        assert_eq!(lints.len(), 2);
+        assert!(matches!(lints[0], Lint::EmptySection(_)));
+        assert!(matches!(&lints[1], Lint::UnknownApplyAllTarget(name, _) if name == "fr"));
+    }
+
+    #[test]
+    fn uncovered_name() {
+        let doc = parse_doc("#(en, ja)\n#sec# Section\n#{[en], { Hello }}\n#[Hi][\n]\n");
+        assert_eq!(doc.name_coverage(), [(0, 2), (1, 0)]);
+
+        let lints = check(&doc);
+        assert_eq!(lints.len(), 1);
+        assert!(matches!(
+            &lints[0],
+            Lint::UncoveredName(name, span) if name == "ja" && span.start == 6 && span.end == 8
+        ));
+    }
+
+    #[test]
+    fn uniform_section() {
+        use super::{LintOptions, check_with};
+
+        let doc = parse_doc(
+            r#"#(en, ja)
+#same# Same
+#[OK][OK]
+#{{ Shared }}
+#varied# Varied
+#[Hello][こんにちは]
+#used# Used
+#[OK][OK]
+#.used.en
+"#,
+        );
+        assert!(check(&doc).is_empty());
+
+        let options = LintOptions {
+            uniform_sections: true,
+            ..Default::default()
+        };
+        let lints = check_with(&doc, &options);
+
+        assert_eq!(lints.len(), 1);
+        assert!(matches!(&lints[0], Lint::UniformSection(span) if span.start == 10));
+    }
+
+    #[test]
+    fn mixed_selector_path() {
+        use super::{LintOptions, check_with};
+
+        let options = LintOptions {
+            mixed_selector_paths: true,
+            ..Default::default()
+        };
+        let input = "#(en, ja)\n#sec# Section\n#sub## Sub\n#[Hello][こんにちは]\n#other# Other\n";
+        for selector in ["#.sec.sub.en", "#.0.0.en", "#.sec.sub.", "#.0.0[en, ja]"] {
+            let doc = parse_doc(&format!("{input}{selector}\n"));
+            assert!(check_with(&doc, &options).is_empty(), "{selector}");
+        }
+
+        let doc = parse_doc(&format!("{input}#.0.sub.en\n"));
+        assert!(check(&doc).is_empty());
+
+        let lints = check_with(&doc, &options);
+        assert_eq!(lints.len(), 1);
+        assert!(matches!(&lints[0], Lint::MixedSelectorPath(span) if span.start == 75));
+    }
+
+    #[test]
+    fn no_content() {
+        let doc = parse_doc("#(en, ja)\n");
+        assert!(doc.is_empty());
+
+        let lints = check(&doc);
+        assert_eq!(lints.len(), 1);
+        assert!(matches!(&lints[0], Lint::NoContent(span) if span.start == 2 && span.end == 8));
+
+        assert!(!parse_doc("#(en, ja)\n#[Hi][やあ]\n").is_empty());
+    }
+
+    #[test]
+    fn unused_alias() {
+        use super::{LintOptions, check_with};
+
+        let doc = parse_doc(
+            "#(en, ja)\n#used# Used\n#inner## Inner\n#[Hi][やあ]\n#spare# Spare\n#[Bye][またね]\n#.used.inner.en\n",
+        );
+        assert!(check(&doc).is_empty());
+
+        let options = LintOptions {
+            unused_aliases: true,
+            ..Default::default()
+        };
+        let lints = check_with(&doc, &options);
+
+        assert_eq!(lints.len(), 1);
+        assert!(
+            matches!(&lints[0], Lint::UnusedAlias(alias, span) if alias == "spare" && span.start == 51)
+        );
+    }
+
+    #[test]
+    fn deep_section() {
+        use super::{LintOptions, check_with};
+
+        let doc = parse_doc(
+            "#(en, ja)\n## One\n### Two\n#### Three\n##### Four\n#[A][あ]\n## Five\n#[B][い]\n",
+        );
+        assert!(check(&doc).is_empty());
+
+        let options = LintOptions {
+            max_section_depth: 2,
+            ..Default::default()
+        };
+        let lints = check_with(&doc, &options);
+        assert_eq!(lints.len(), 1);
+        assert!(
+            matches!(&lints[0], Lint::DeepSection(2, span) if span.start == 25 && span.end == 36)
+        );
+    }
+
+    #[test]
+    fn deep_section_with_skipped_level() {
+        use super::{LintOptions, check_with};
+
+        let doc = parse_doc("#(en, ja)\n## One\n##### Four\n#[A][あ]\n");
+
+        let options = LintOptions {
+            max_section_depth: 1,
+            ..Default::default()
+        };
+        let lints = check_with(&doc, &options);
+        assert_eq!(lints.len(), 1);
+        assert!(
+            matches!(&lints[0], Lint::DeepSection(1, span) if span.start == 17 && span.end == 28)
+        );
+    }
+}
@@ -1,27 +1,146 @@
+use crate::formatter::Selector;
+use crate::include::{IncludeError, Source};
 use crate::parser::{AST, Document, NodeKind, Rule};
 use rustc_hash::FxHashMap;
 use tokio::sync::Mutex;
 use tower_lsp::jsonrpc::Result;
 use tower_lsp::lsp_types::*;
-use tower_lsp::{Client, LanguageServer};
+use tower_lsp::{Client, ClientSocket, LanguageServer, LspService};
 
+use crate::lint::Lint;
 use crate::parser::{ParseError, Span};
 
+/// The longest content shown in hover labels, in characters.
+const HOVER_PREVIEW_LEN: usize = 60;
+
 #[derive(Debug)]
 pub struct SandServer {
     pub client: Client,
 
     document_map: Mutex<FxHashMap<Url, String>>,
+    settings: Mutex<SandSettings>,
+    /// The diagnostics last sent for each document, sorted.
+    published: Mutex<FxHashMap<Url, Vec<Diagnostic>>>,
+    /// Written before the `source` of every published diagnostic, from the `sourcePrefix`
+    /// initialization option. Empty by default.
+    source_prefix: Mutex<String>,
+    /// Whether the client can register `workspace/didChangeWatchedFiles` dynamically, from the
+    /// client capabilities sent with `initialize`.
+    watch_files: Mutex<bool>,
+}
+
+/// Client-side settings, read from the `sand` section of the workspace configuration.
+///
+/// ```json
+/// { "sand": { "draft": false, "lints": { "emptySection": true, "unknownApplyAllTarget": true, "nameCoverage": true, "uniformSection": false, "mixedSelectorPath": false, "noContent": true, "unusedAlias": false, "maxSectionDepth": 32 } } }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SandSettings {
+    /// `sand.lints.emptySection`
+    pub empty_section: bool,
+    /// `sand.lints.unknownApplyAllTarget`
+    pub unknown_apply_all_target: bool,
+    /// `sand.lints.nameCoverage`
+    pub name_coverage: bool,
+    /// `sand.lints.uniformSection`, off by default
+    pub uniform_section: bool,
+    /// `sand.lints.mixedSelectorPath`, off by default
+    pub mixed_selector_path: bool,
+    /// `sand.lints.noContent`
+    pub no_content: bool,
+    /// `sand.lints.unusedAlias`, off by default
+    pub unused_alias: bool,
+    /// `sand.lints.maxSectionDepth`, sections nested deeper are reported
+    pub max_section_depth: usize,
+    /// `sand.draft`, off by default. Reports a missing `#(...)` line as a warning instead of
+    /// an error, for documents still being written.
+    pub draft: bool,
+}
+
+impl Default for SandSettings {
+    fn default() -> Self {
+        Self {
+            empty_section: true,
+            unknown_apply_all_target: true,
+            name_coverage: true,
+            uniform_section: false,
+            mixed_selector_path: false,
+            no_content: true,
+            unused_alias: false,
+            max_section_depth: crate::lint::DEFAULT_MAX_SECTION_DEPTH,
+            draft: false,
+        }
+    }
+}
+
+impl SandSettings {
+    /// Reads the settings from a `workspace/didChangeConfiguration` payload.
+    ///
+    /// Both the nested form (`{"sand": {"lints": {...}}}`) and the flat dotted form
+    /// (`{"sand.lints.emptySection": false}`) are accepted. Missing keys keep their defaults.
+    pub fn from_value(value: &LSPAny) -> Self {
+        let setting = |key: &str| -> Option<&LSPAny> {
+            let path = format!("sand.lints.{key}");
+            value
+                .get(&path)
+                .or_else(|| value.get("sand")?.get("lints")?.get(key))
+        };
+        let lookup = |key: &str| setting(key).and_then(|v| v.as_bool());
+
+        let default = Self::default();
+        Self {
+            empty_section: lookup("emptySection").unwrap_or(default.empty_section),
+            unknown_apply_all_target: lookup("unknownApplyAllTarget")
+                .unwrap_or(default.unknown_apply_all_target),
+            name_coverage: lookup("nameCoverage").unwrap_or(default.name_coverage),
+            uniform_section: lookup("uniformSection").unwrap_or(default.uniform_section),
+            mixed_selector_path: lookup("mixedSelectorPath").unwrap_or(default.mixed_selector_path),
+            no_content: lookup("noContent").unwrap_or(default.no_content),
+            unused_alias: lookup("unusedAlias").unwrap_or(default.unused_alias),
+            max_section_depth: setting("maxSectionDepth")
+                .and_then(|v| v.as_u64())
+                .map_or(default.max_section_depth, |depth| depth as usize),
+            draft: value
+                .get("sand.draft")
+                .or_else(|| value.get("sand")?.get("draft"))
+                .and_then(|v| v.as_bool())
+                .unwrap_or(default.draft),
+        }
+    }
+
+    fn is_enabled(&self, lint: &Lint) -> bool {
+        match lint {
+            Lint::EmptySection(_) => self.empty_section,
+            Lint::UnknownApplyAllTarget(..) => self.unknown_apply_all_target,
+            Lint::UncoveredName(..) => self.name_coverage,
+            Lint::NoContent(_) => self.no_content,
+            // 残りは LintOptions で出すかどうかが決まる
+            Lint::UniformSection(_)
+            | Lint::MixedSelectorPath(_)
+            | Lint::UnusedAlias(..)
+            | Lint::DeepSection(..) => true,
+        }
+    }
 }
 
+/// Converts a byte offset into an LSP position with UTF-16 columns.
+///
+/// `\n`, `\r\n` and a bare `\r` each end a line, as in the LSP spec. The `\r` of a CRLF line
+/// ending counts as one column at the end of its line, which is what LSP clients expect.
 fn byte_offset_to_position(text: &str, offset: usize) -> Position {
+    let mut offset = offset.min(text.len());
+    // 文字の途中なら直前の文字境界に寄せる
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+
     let mut line = 0;
     let mut utf16_char_offset = 0;
     for (i, c) in text.char_indices() {
         if i == offset {
             break;
         }
-        if c == '\n' {
+        if ends_line(text, i, c) {
             line += 1;
             utf16_char_offset = 0;
         } else {
@@ -34,6 +153,7 @@ fn byte_offset_to_position(text: &str, offset: usize) -> Position {
     }
 }
 
+/// The inverse of [`byte_offset_to_position`]. Positions past the end of the text map to its length.
 fn position_to_byte_offset(text: &str, position: &Position) -> usize {
     let mut current_line = 0;
     let mut utf16_char_offset = 0;
@@ -44,7 +164,7 @@ fn position_to_byte_offset(text: &str, position: &Position) -> usize {
             return i;
         }
 
-        if c == '\n' {
+        if ends_line(text, i, c) {
             current_line += 1;
             utf16_char_offset = 0;
         } else {
@@ -60,12 +180,200 @@ fn position_to_byte_offset(text: &str, position: &Position) -> usize {
     text.len()
 }
 
+/// Whether the char `c` at byte `i` of `text` ends a line: a `\n`, or a `\r` not followed by
+/// one.
+fn ends_line(text: &str, i: usize, c: char) -> bool {
+    c == '\n' || (c == '\r' && text.as_bytes().get(i + 1) != Some(&b'\n'))
+}
+
 fn pos_to_ast<'a>(text: &str, pos: &'a Position, ast: &'a AST) -> Option<&'a AST> {
     let offset = position_to_byte_offset(text, pos);
 
     ast.find_node_at_position(offset)
 }
 
+/// Returns the span of the identifier (`[A-Za-z0-9_]+`) around `offset`, if any.
+fn ident_at(text: &str, offset: usize) -> Option<Span> {
+    let is_ident = |c: char| c.is_ascii_alphanumeric() || c == '_';
+
+    let start = text
+        .get(..offset)?
+        .char_indices()
+        .rev()
+        .take_while(|&(_, c)| is_ident(c))
+        .last()
+        .map_or(offset, |(i, _)| i);
+    let end = text[offset..]
+        .char_indices()
+        .find(|&(_, c)| !is_ident(c))
+        .map_or(text.len(), |(i, _)| offset + i);
+
+    (start < end).then_some(Span { start, end })
+}
+
+/// Finds the name at `offset` and returns its index in [`Document::names`].
+///
+/// Names are resolved inside the target list of an `ApplyAll`, at the end of a selector and
+/// in the declaration itself.
+fn definition_at(doc: &Document, text: &str, offset: usize) -> Option<usize> {
+    let word = ident_at(text, offset)?;
+    let index = doc.name_index(&text[word.start..word.end])?;
+
+    let is_name = if doc.name_spans.contains(&word) {
+        true
+    } else {
+        let node = doc.ast.find_node_at_position(offset)?;
+        let span = node.get_span();
+
+        match &node.node {
+            NodeKind::All {
+                all_or_names: Some(_),
+                ..
+            } => {
+                // 対象の名前は最初の `[...]` の中にある
+                let source = &text[span.start..span.end];
+                let open = span.start + source.find('[')?;
+                let close = span.start + source.find(']')?;
+                open < word.start && word.end <= close
+            }
+            NodeKind::Selector { names: None, .. } => word.end == span.end,
+            // 名前のリストは最後の `[...]` の中にある
+            NodeKind::Selector { .. } => text[span.start..word.start].contains('['),
+            _ => false,
+        }
+    };
+
+    is_name.then_some(index)
+}
+
+/// Describes which names an apply-all block with the target list `all_or_names` applies to,
+/// marking targets that are not in `names`.
+fn apply_all_targets(names: &[String], all_or_names: Option<&[String]>) -> String {
+    match all_or_names {
+        None => "Applies to: all names".to_string(),
+        Some(targets) => {
+            let targets: Vec<_> = targets
+                .iter()
+                .map(|t| {
+                    if names.contains(t) {
+                        t.clone()
+                    } else {
+                        format!("{t} (not declared)")
+                    }
+                })
+                .collect();
+            format!("Applies to: {}", targets.join(", "))
+        }
+    }
+}
+
+/// Lists the sentence for each name of a sentence block, e.g. `en: Hello / ja: こんにちは`.
+/// Names without a sentence and sentences without a name are marked.
+fn sentence_table(names: &[String], sentences: &[String]) -> String {
+    (0..names.len().max(sentences.len()))
+        .map(|i| {
+            let sentence = sentences
+                .get(i)
+                .map(|s| crate::formatter::preview(s, HOVER_PREVIEW_LEN));
+            match (names.get(i), sentence) {
+                (Some(name), Some(sentence)) => format!("{name}: {sentence}"),
+                (Some(name), None) => format!("{name}: (missing)"),
+                (None, sentence) => format!("(no name): {}", sentence.unwrap_or_default()),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" / ")
+}
+
+/// Offers to rewrite the sentence block at `offset` as an apply-all block `#{{ ... }}` when it
+/// reads the same for every name. The alias is kept.
+fn apply_all_action(uri: &Url, text: &str, doc: &Document, offset: usize) -> Option<CodeAction> {
+    let node = doc.ast.find_node_at_position(offset)?;
+    let NodeKind::Sen(sentences, _) = &node.node else {
+        return None;
+    };
+
+    if doc.names().len() < 2 {
+        return None;
+    }
+    let first = crate::formatter::plain_text(node, (0, &doc.names()[0]));
+    let uniform = !first.is_empty()
+        && doc
+            .names()
+            .iter()
+            .enumerate()
+            .skip(1)
+            .all(|(index, name)| crate::formatter::plain_text(node, (index, name)) == first);
+    if !uniform {
+        return None;
+    }
+
+    let span = node.get_span();
+    let edit = TextEdit {
+        range: Range {
+            start: byte_offset_to_position(text, span.start),
+            end: byte_offset_to_position(text, span.end),
+        },
+        new_text: format!(
+            "#{}{{{{ {} }}}}",
+            node.alias().unwrap_or_default(),
+            sentences[0].trim()
+        ),
+    };
+
+    Some(CodeAction {
+        title: "Convert to an apply-all block".to_string(),
+        kind: Some(CodeActionKind::REFACTOR_REWRITE),
+        edit: Some(WorkspaceEdit {
+            changes: Some([(uri.clone(), vec![edit])].into_iter().collect()),
+            ..Default::default()
+        }),
+        ..Default::default()
+    })
+}
+
+/// Lists the aliased nodes of `doc` whose alias or section content contains `query`,
+/// ignoring case. An empty query matches every alias.
+fn symbols_in(uri: &Url, text: &str, doc: &Document, query: &str) -> Vec<SymbolInformation> {
+    use crate::parser::PathSegment;
+
+    let query = query.to_lowercase();
+
+    doc.alias_index()
+        .into_iter()
+        .filter_map(|(alias, path, span)| {
+            let segments: Vec<_> = path.iter().cloned().map(PathSegment::Ident).collect();
+            let node = *doc.ast.resolve_path(&segments).ok()?.first()?;
+
+            let (kind, content) = match &node.node {
+                NodeKind::Section { content, .. } => (SymbolKind::NAMESPACE, Some(content)),
+                _ => (SymbolKind::STRING, None),
+            };
+            let matches = alias.to_lowercase().contains(&query)
+                || content.is_some_and(|c| c.to_lowercase().contains(&query));
+            if !matches {
+                return None;
+            }
+
+            #[allow(deprecated)]
+            Some(SymbolInformation {
+                name: alias,
+                kind,
+                tags: None,
+                deprecated: None,
+                location: Location {
+                    uri: uri.clone(),
+                    range: Range {
+                        start: byte_offset_to_position(text, span.start),
+                        end: byte_offset_to_position(text, span.end),
+                    },
+                },
+                container_name: (path.len() > 1).then(|| path[..path.len() - 1].join(".")),
+            })
+        })
+        .collect()
+}
+
 fn convert_pest_error_to_diagnostic(
     file_content: &str,
     error: pest::error::Error<Rule>,
@@ -81,12 +389,14 @@ fn convert_pest_error_to_diagnostic(
     let start_pos = byte_offset_to_position(file_content, span.start);
     let end_pos = byte_offset_to_position(file_content, span.end);
 
+    let message = crate::parser::pest_error_message(&error.variant);
+
     Diagnostic {
         range: Range::new(start_pos, end_pos),
         severity: Some(DiagnosticSeverity::ERROR),
         code: None,
         source: Some("Sand Parser".to_string()),
-        message: error.variant.message().to_string(),
+        message,
         related_information: None,
         tags: None,
         data: None,
@@ -94,13 +404,42 @@ fn convert_pest_error_to_diagnostic(
     }
 }
 
-fn convert_parse_error_to_diagnostic(file_content: &str, error: ParseError) -> Diagnostic {
+/// Converts a validation error into an LSP diagnostic for the document `uri` with text `file_content`.
+///
+/// The diagnostic always has severity `ERROR` and source `"Sand Validator"`.
+pub fn convert_parse_error_to_diagnostic(
+    uri: &Url,
+    file_content: &str,
+    error: ParseError,
+) -> Diagnostic {
+    let related_information = match &error {
+        ParseError::MultipleNameDefine(first, _) => Some(vec![DiagnosticRelatedInformation {
+            location: Location::new(
+                uri.clone(),
+                Range::new(
+                    byte_offset_to_position(file_content, first.start),
+                    byte_offset_to_position(file_content, first.end),
+                ),
+            ),
+            message: "names are first defined here".to_string(),
+        }]),
+        _ => None,
+    };
+
     let (span, message) = match &error {
-        ParseError::MultipleNameDefine(span)
+        ParseError::MultipleNameDefine(_, span)
         | ParseError::DuplicateNames(_, span)
         | ParseError::DuplicateAlias(_, span)
         | ParseError::AliasConflictWithNames(_, span)
         | ParseError::NumberOfSentences(span)
+        | ParseError::UnknownLabel(_, span)
+        | ParseError::DuplicateLabel(_, span)
+        | ParseError::MissingLabel(_, span)
+        | ParseError::SkippedSectionLevel { span, .. }
+        | ParseError::UnknownStatement(span)
+        | ParseError::DuplicateAttribute(_, span)
+        | ParseError::EmptyNames(span)
+        | ParseError::TooManyNames(_, span)
         | ParseError::Selector(_, span) => (span.clone(), error.to_string()),
         ParseError::MissingNames => (Span { start: 0, end: 1 }, error.to_string()),
     };
@@ -114,6 +453,29 @@ fn convert_parse_error_to_diagnostic(file_content: &str, error: ParseError) -> D
         code: None,
         source: Some("Sand Validator".to_string()),
         message,
+        related_information,
+        tags: None,
+        data: None,
+        code_description: None,
+    }
+}
+
+fn convert_lint_to_diagnostic(file_content: &str, lint: Lint) -> Diagnostic {
+    let span = lint.span().clone();
+    let severity = match lint.severity() {
+        crate::lint::Severity::Information => DiagnosticSeverity::INFORMATION,
+        crate::lint::Severity::Warning => DiagnosticSeverity::WARNING,
+    };
+
+    let start_pos = byte_offset_to_position(file_content, span.start);
+    let end_pos = byte_offset_to_position(file_content, span.end);
+
+    Diagnostic {
+        range: Range::new(start_pos, end_pos),
+        severity: Some(severity),
+        code: None,
+        source: Some("Sand Linter".to_string()),
+        message: lint.to_string(),
         related_information: None,
         tags: None,
         data: None,
@@ -121,25 +483,222 @@ fn convert_parse_error_to_diagnostic(file_content: &str, error: ParseError) -> D
     }
 }
 
-fn convert_parse_errors_to_diagnostics(
+/// Converts every error with [`convert_parse_error_to_diagnostic`].
+///
+/// ```
+/// use pest::Parser as _;
+/// use sand::parser::{Document, Rule, SandParser};
+/// use tower_lsp::lsp_types::{DiagnosticSeverity, Url};
+///
+/// let text = "#(en, ja)\n#[Hello]\n";
+/// let errs = Document::try_from(SandParser::parse(Rule::doc, text).unwrap()).unwrap_err();
+///
+/// let uri = Url::parse("file:///doc.sand").unwrap();
+/// let diagnostics = sand::convert_parse_errors_to_diagnostics(&uri, text, errs);
+///
+/// assert_eq!(diagnostics.len(), 1);
+/// assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::ERROR));
+/// assert_eq!(diagnostics[0].source.as_deref(), Some("Sand Validator"));
+/// ```
+pub fn convert_parse_errors_to_diagnostics(
+    uri: &Url,
     file_content: &str,
     errors: Vec<ParseError>,
 ) -> Vec<Diagnostic> {
     errors
         .into_iter()
-        .map(|err| convert_parse_error_to_diagnostic(file_content, err))
+        .map(|err| convert_parse_error_to_diagnostic(uri, file_content, err))
+        .collect()
+}
+
+/// Result of the custom `sand/version` request.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VersionInfo {
+    pub version: String,
+    /// Number of documents the server currently has open.
+    pub open_documents: usize,
+}
+
+/// Parameters of the custom `sand/preview` request.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PreviewParams {
+    /// An open document.
+    pub uri: Url,
+    /// A global selector such as `#.intro.`.
+    pub selector: String,
+}
+
+/// The rendered text for one name.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PreviewEntry {
+    pub name: String,
+    pub text: String,
+}
+
+/// Result of the custom `sand/preview` request, one entry per selected name.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PreviewResult {
+    pub entries: Vec<PreviewEntry>,
+}
+
+/// Renders `sel` as plain text for each name it selects, following its path from `base`.
+///
+/// If `sel` points at an apply-all block that does not target a name, the text for that name
+/// says so instead of being empty.
+fn preview_entries(doc: &Document, base: &AST, sel: &Selector) -> Vec<PreviewEntry> {
+    let scoped = Document::from_parts(doc.names().to_vec(), doc.name_spans.clone(), base.clone());
+    let rendered = crate::formatter::render_plain(&scoped, sel, false);
+    let (targets, _) = crate::formatter::select(&scoped, sel);
+
+    sel.target_names(&scoped)
+        .into_iter()
+        .zip(rendered)
+        .map(|(name, text)| {
+            let text = match targets.as_slice() {
+                [
+                    AST {
+                        node:
+                            NodeKind::All {
+                                all_or_names: Some(names),
+                                ..
+                            },
+                        ..
+                    },
+                ] if !names.iter().any(|n| n == name) => {
+                    format!("this apply-all does not apply to name {name}")
+                }
+                _ => text,
+            };
+
+            PreviewEntry {
+                name: name.to_string(),
+                text,
+            }
+        })
         .collect()
 }
 
+/// See [`SandServer::selector_preview_at`].
+fn selector_preview(doc: &Document, offset: usize) -> Option<String> {
+    let node = doc.ast.find_node_at_position(offset)?;
+    let NodeKind::Selector { local, .. } = &node.node else {
+        return None;
+    };
+    let base = if *local {
+        doc.ast.find_parent_at_position(offset)?
+    } else {
+        &doc.ast
+    };
+
+    let entries = preview_entries(doc, base, &Selector(node.clone()));
+    Some(
+        entries
+            .into_iter()
+            .map(|entry| entry.text)
+            .collect::<Vec<_>>()
+            .join("\n\n---\n\n"),
+    )
+}
+
+/// Builds the LSP service, including the custom `sand/*` methods.
+pub fn service() -> (LspService<SandServer>, ClientSocket) {
+    LspService::build(SandServer::new)
+        .custom_method("sand/version", SandServer::version)
+        .custom_method("sand/preview", SandServer::preview)
+        .finish()
+}
+
 impl SandServer {
     pub fn new(client: Client) -> Self {
         Self {
             client,
             document_map: Mutex::new(FxHashMap::default()),
+            settings: Mutex::new(SandSettings::default()),
+            published: Mutex::new(FxHashMap::default()),
+            source_prefix: Mutex::new(String::new()),
+            watch_files: Mutex::new(false),
         }
     }
 
-    fn generate_diagnostics(text: &str) -> Vec<Diagnostic> {
+    /// Asks the client to send `workspace/didChangeWatchedFiles` for the `*.sand` files.
+    async fn register_watched_files(&self) {
+        let watchers = DidChangeWatchedFilesRegistrationOptions {
+            watchers: vec![FileSystemWatcher {
+                glob_pattern: GlobPattern::String("**/*.sand".to_string()),
+                kind: None,
+            }],
+        };
+        let registration = Registration {
+            id: "sand-watched-files".to_string(),
+            method: "workspace/didChangeWatchedFiles".to_string(),
+            register_options: serde_json::to_value(watchers).ok(),
+        };
+        if let Err(e) = self.client.register_capability(vec![registration]).await {
+            self.client
+                .log_message(
+                    MessageType::WARNING,
+                    format!("failed to watch sand files: {e}"),
+                )
+                .await;
+        }
+    }
+
+    fn generate_diagnostics(uri: &Url, text: &str, settings: &SandSettings) -> Vec<Diagnostic> {
+        let source = match expand_includes(uri, text) {
+            None => return Self::diagnose(uri, text, settings),
+            Some(Ok(source)) => source,
+            Some(Err(err)) => {
+                return vec![Diagnostic {
+                    range: Range::default(),
+                    severity: Some(DiagnosticSeverity::ERROR),
+                    source: Some("Sand Parser".to_string()),
+                    message: err.to_string(),
+                    ..Default::default()
+                }];
+            }
+        };
+
+        // 展開後の行番号を、文書の中の行番号に戻す
+        let line_of = |offset: usize| byte_offset_to_position(source.text(), offset).line;
+        let files = source.files();
+        let root_line = line_of(files.last().unwrap().offset);
+        let to_root = |range: &mut Range| {
+            range.start.line -= root_line;
+            range.end.line -= root_line;
+        };
+
+        Self::diagnose(uri, source.text(), settings)
+            .into_iter()
+            .map(|mut diagnostic| {
+                if diagnostic.range.start.line >= root_line {
+                    to_root(&mut diagnostic.range);
+                    for related in diagnostic.related_information.iter_mut().flatten() {
+                        if related.location.range.start.line >= root_line {
+                            to_root(&mut related.location.range);
+                        }
+                    }
+                } else {
+                    // 読み込んだファイルの中の診断は、文書の先頭に出す
+                    let index = files
+                        .iter()
+                        .rposition(|file| line_of(file.offset) <= diagnostic.range.start.line)
+                        .unwrap_or(0);
+                    diagnostic.message =
+                        format!("{}: {}", files[index].path.display(), diagnostic.message);
+                    diagnostic.range = Range::default();
+                    diagnostic.related_information = None;
+                }
+                diagnostic
+            })
+            .collect()
+    }
+
+    /// The diagnostics for `text` on its own, without expanding `@include` lines.
+    fn diagnose(uri: &Url, text: &str, settings: &SandSettings) -> Vec<Diagnostic> {
         use crate::parser::{Document, Rule, SandParser};
         use pest::Parser as _;
 
@@ -154,8 +713,33 @@ impl SandServer {
             Ok(pairs) => {
                 let doc: std::result::Result<Document, _> = pairs.try_into();
 
-                if let Err(errs) = doc {
-                    diagnostics.extend(convert_parse_errors_to_diagnostics(text, errs));
+                match doc {
+                    Err(errs) => {
+                        diagnostics.extend(errs.into_iter().map(|err| {
+                            let missing_names = err == ParseError::MissingNames;
+                            let mut diagnostic = convert_parse_error_to_diagnostic(uri, text, err);
+                            if missing_names && settings.draft {
+                                diagnostic.severity = Some(DiagnosticSeverity::WARNING);
+                            }
+                            diagnostic
+                        }));
+                    }
+                    Ok(doc) => {
+                        diagnostics.extend(
+                            crate::lint::check_with(
+                                &doc,
+                                &crate::lint::LintOptions {
+                                    max_section_depth: settings.max_section_depth,
+                                    unused_aliases: settings.unused_alias,
+                                    mixed_selector_paths: settings.mixed_selector_path,
+                                    uniform_sections: settings.uniform_section,
+                                },
+                            )
+                            .into_iter()
+                            .filter(|lint| settings.is_enabled(lint))
+                            .map(|lint| convert_lint_to_diagnostic(text, lint)),
+                        );
+                    }
                 }
             }
         }
@@ -163,15 +747,105 @@ impl SandServer {
         diagnostics
     }
 
+    /// Handles `sand/version`, a handshake for editor integration tests.
+    pub async fn version(&self) -> Result<VersionInfo> {
+        Ok(VersionInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            open_documents: self.document_map.lock().await.len(),
+        })
+    }
+
+    /// Handles `sand/preview`: renders `params.selector` in the open document as plain text,
+    /// so the client can show it in a preview pane.
+    pub async fn preview(&self, params: PreviewParams) -> Result<PreviewResult> {
+        use crate::parser::SandParser;
+        use pest::Parser as _;
+        use tower_lsp::jsonrpc::{Error, ErrorCode};
+
+        let doc = self.parse(&params.uri).await?;
+
+        let invalid_selector = |message: String| Error {
+            code: ErrorCode::InvalidParams,
+            message: format!("invalid selector: {message}").into(),
+            data: None,
+        };
+
+        let pairs = SandParser::parse(Rule::Selector, &params.selector)
+            .map_err(|err| invalid_selector(err.variant.message().to_string()))?;
+        let sel = Selector::try_from((&doc, pairs)).map_err(|errs| {
+            invalid_selector(
+                errs.iter()
+                    .map(|e| e.to_string())
+                    .collect::<Vec<_>>()
+                    .join("; "),
+            )
+        })?;
+
+        Ok(PreviewResult {
+            entries: preview_entries(&doc, &doc.ast, &sel),
+        })
+    }
+
+    /// Renders the selector at `position` in the open document `uri` as plain text, the
+    /// names separated by rules. Local selectors are followed from their section.
+    ///
+    /// This is what the hover over a selector shows.
+    pub async fn selector_preview_at(&self, uri: &Url, position: &Position) -> Option<String> {
+        let doc = self.parse(uri).await.ok()?;
+        let offset = position_to_byte_offset(self.document_map.lock().await.get(uri)?, position);
+
+        selector_preview(&doc, offset)
+    }
+
     async fn publish_diagnostics(&self, uri: Url, text: String) {
-        self.client
-            .publish_diagnostics(uri, Self::generate_diagnostics(&text), None)
-            .await;
+        let diagnostics = self.diagnostics_for(&uri, &text).await;
+
+        // 前回と同じなら送らない (エディタ側のちらつきを防ぐ)
+        if let Some(diagnostics) = self.changed_diagnostics(&uri, diagnostics).await {
+            self.client
+                .publish_diagnostics(uri, diagnostics, None)
+                .await;
+        }
+    }
+
+    /// The diagnostics to publish for `text`, with the current settings and source prefix.
+    async fn diagnostics_for(&self, uri: &Url, text: &str) -> Vec<Diagnostic> {
+        let settings = self.settings.lock().await.clone();
+        let mut diagnostics = Self::generate_diagnostics(uri, text, &settings);
+
+        let prefix = self.source_prefix.lock().await;
+        if !prefix.is_empty() {
+            for diagnostic in &mut diagnostics {
+                if let Some(source) = &mut diagnostic.source {
+                    source.insert_str(0, &prefix);
+                }
+            }
+        }
+
+        diagnostics
+    }
+
+    /// Sorts `diagnostics` and records them as published for `uri`.
+    ///
+    /// Returns `None` if they are the same as the ones published last time.
+    async fn changed_diagnostics(
+        &self,
+        uri: &Url,
+        mut diagnostics: Vec<Diagnostic>,
+    ) -> Option<Vec<Diagnostic>> {
+        diagnostics.sort_by(|a, b| {
+            (a.range.start, a.range.end, &a.message).cmp(&(b.range.start, b.range.end, &b.message))
+        });
+
+        let mut published = self.published.lock().await;
+        if published.get(uri) == Some(&diagnostics) {
+            return None;
+        }
+        published.insert(uri.clone(), diagnostics.clone());
+        Some(diagnostics)
     }
 
     async fn parse(&self, url: &Url) -> Result<Document> {
-        use crate::parser::{Rule, SandParser};
-        use pest::Parser as _;
         use tower_lsp::jsonrpc::{Error, ErrorCode};
 
         let map = self.document_map.lock().await;
@@ -182,24 +856,123 @@ impl SandServer {
             data: None,
         })?;
 
-        let pairs = SandParser::parse(Rule::doc, text).map_err(|err| Error {
-            code: ErrorCode::ParseError,
-            message: err.variant.message().to_string().into(),
-            data: None,
-        })?;
+        parse_text(url, text).map(|(doc, _)| doc)
+    }
+}
 
-        pairs.try_into().map_err(|errs: Vec<ParseError>| Error {
+/// Parses the open document `text` at `uri` with its `@include` lines expanded.
+///
+/// The spans are moved back into `text`; the nodes and the names from included files end up
+/// at its start. Also returns where each name is declared, in whichever file that is.
+fn parse_text(uri: &Url, text: &str) -> Result<(Document, Vec<Location>)> {
+    use crate::parser::{Rule, SandParser};
+    use pest::Parser as _;
+    use tower_lsp::jsonrpc::{Error, ErrorCode};
+
+    let source = match expand_includes(uri, text) {
+        Some(source) => Some(source.map_err(|err| Error {
             code: ErrorCode::ParseError,
-            message: format!(
-                "Parse validation failed: {}",
-                errs.iter()
-                    .map(|e| e.to_string())
-                    .collect::<Vec<_>>()
-                    .join("; ")
-            )
-            .into(),
+            message: err.to_string().into(),
             data: None,
-        })
+        })?),
+        None => None,
+    };
+    let expanded = source.as_ref().map_or(text, |source| source.text());
+
+    let pairs = SandParser::parse(Rule::doc, expanded).map_err(|err| Error {
+        code: ErrorCode::ParseError,
+        message: crate::parser::pest_error_message(&err.variant).into(),
+        data: None,
+    })?;
+
+    let mut doc: Document = pairs.try_into().map_err(|errs: Vec<ParseError>| Error {
+        code: ErrorCode::ParseError,
+        message: format!(
+            "Parse validation failed: {}",
+            errs.iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>()
+                .join("; ")
+        )
+        .into(),
+        data: None,
+    })?;
+
+    let Some(source) = source else {
+        let locations = doc
+            .name_spans
+            .iter()
+            .map(|span| Location {
+                uri: uri.clone(),
+                range: Range {
+                    start: byte_offset_to_position(text, span.start),
+                    end: byte_offset_to_position(text, span.end),
+                },
+            })
+            .collect();
+        return Ok((doc, locations));
+    };
+
+    let root = source.files().len() - 1;
+    let mut locations = vec![];
+    for span in &mut doc.name_spans {
+        let (index, start) = source.locate(span.start);
+        let file = &source.files()[index];
+        let end = start + (span.end - span.start);
+
+        locations.push(Location {
+            uri: if index == root {
+                uri.clone()
+            } else {
+                Url::from_file_path(&file.path).unwrap_or_else(|()| uri.clone())
+            },
+            range: Range {
+                start: byte_offset_to_position(&file.contents, start),
+                end: byte_offset_to_position(&file.contents, end),
+            },
+        });
+
+        // 文書の中のオフセットに戻す。読み込んだファイルの名前は先頭に寄る
+        *span = if index == root {
+            Span { start, end }
+        } else {
+            Span { start: 0, end: 0 }
+        };
+    }
+
+    // 文書の中のオフセットに戻す。読み込んだファイルの部分は先頭に寄る
+    doc.ast.shift_spans(-(source.files()[root].offset as isize));
+    Ok((doc, locations))
+}
+
+/// Expands the `@include` lines of the open document `text` at `uri`, reading the included
+/// files from disk. `None` if the document includes nothing or is not a local file.
+fn expand_includes(uri: &Url, text: &str) -> Option<std::result::Result<Source, IncludeError>> {
+    if !text.contains("@include") {
+        return None;
+    }
+    let path = uri.to_file_path().ok()?;
+
+    Some(crate::include::expand_with(&path, |p| {
+        if p == path {
+            Ok(text.to_string())
+        } else {
+            std::fs::read_to_string(p)
+        }
+    }))
+}
+
+/// Whether the open document `text` at `uri` includes one of the `changed` paths, given as
+/// [`crate::include::canonicalize`] returns them. A document whose includes fail to expand
+/// counts, since the change may be what broke or fixed them.
+fn includes_any(uri: &Url, text: &str, changed: &[std::path::PathBuf]) -> bool {
+    match expand_includes(uri, text) {
+        None => false,
+        Some(Ok(source)) => source
+            .files()
+            .iter()
+            .any(|file| changed.contains(&crate::include::canonicalize(&file.path))),
+        Some(Err(_)) => true,
     }
 }
 
@@ -220,7 +993,8 @@ In the examples above:
 
   * The hashes (##) determine the level of the section. Two hashes (##) indicate a Level 1 Section, three hashes (###) indicate a Level 2 Section, and so on.
   * The optional **`Ident`** (like `sec1` or `sec2`) acts as an **alias** for the section. This alias can be used for quick referencing or navigation within your document.
-  * The content of the section must be on a single line with a line break at the end.
+  * The content of the section must be on a single line with a line break at the end. A line ending in `\` continues on the next line (write `\\` for a literal backslash at the end).
+  * A section can be at most one level deeper than the section it is in, so top-level sections are Level 1. Skipping a level (e.g. `####` right under `##`) is only an error with `sand check --strict`.
 "#;
 
     pub(super) const ALL_DOC: &str = r#"
@@ -266,6 +1040,11 @@ Use when you have one piece of content per declared name (e.g. multiple language
 
 * You must provide exactly one sentence block **per** declared name, in the same order.
 * The `Ident` (`alias`) is optional but useful for reference.
+* Blocks can be labeled with the name instead, then the order doesn't matter:
+
+```sand
+#alias[ja: こんにちは！][en: Hello!]
+```
 "#;
 
     pub(super) const SELECTOR_DOC: &str = r##"
@@ -277,6 +1056,7 @@ Chooses one or more named contexts (e.g. languages, formats) relative to your cu
 * `#.` or `#..` – selects all names from the document root.
 * `#./foo.en` – starts from the *current* section (due to `/`) and picks `foo` → `en`.
 * Without `/`, selection begins at the document root.
+* A local selector must be written inside a section.
 
 * **Identifiers & Indexes**
 
@@ -300,6 +1080,16 @@ Chooses one or more named contexts (e.g. languages, formats) relative to your cu
 #./0.en               // index-based local
 ```
 
+* **Content Match (`{"..."}`)**
+
+* `#.{"Introduction"}.en` selects the child section whose heading is `Introduction`.
+* If no heading is equal, a heading containing the string is used. More than one match is an error.
+* Escape `"` and `\` inside the quotes with `\`.
+
+* **Glob (`*`)**
+
+* `#.sec1.*.en` selects every child section of `sec1` and renders them one after another.
+
 * **Trailing Dot (`.`)**
 
 * A selector ending in `.` (e.g. `#.sec1.sec2.`) expands to *all* declared names, as if you had written one selector per name:
@@ -308,37 +1098,71 @@ Chooses one or more named contexts (e.g. languages, formats) relative to your cu
 #.sec1.sec2.   // same as #.sec1.sec2.en and #.sec1.sec2.ja
 ```
 
+* **Name List (`[...]`)**
+
+* A selector ending in a bracketed list of names (e.g. `#.sec1.sec2[en, fr]`) selects just those names, in the listed order.
+* Every listed name must be declared.
+
 * **Minimal Forms**
 
 * `#.` or `#..` with nothing else simply means “select every name” in the appropriate scope (global or local).
 "##;
 }
 
+/// The capabilities sent from `initialize`. Each provider set here has its method
+/// implemented on [`SandServer`], so add them together.
+fn server_capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Options(
+            TextDocumentSyncOptions {
+                open_close: Some(true),
+                change: Some(TextDocumentSyncKind::FULL),
+                will_save: Some(false),
+                will_save_wait_until: Some(false),
+                save: Some(TextDocumentSyncSaveOptions::Supported(true)),
+            },
+        )),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        workspace_symbol_provider: Some(OneOf::Left(true)),
+        code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+        ..Default::default()
+    }
+}
+
 #[tower_lsp::async_trait]
 impl LanguageServer for SandServer {
-    async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+        if let Some(prefix) = params
+            .initialization_options
+            .as_ref()
+            .and_then(|options| options.get("sourcePrefix")?.as_str())
+        {
+            *self.source_prefix.lock().await = prefix.to_string();
+        }
+
+        *self.watch_files.lock().await = params
+            .capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.did_change_watched_files?.dynamic_registration)
+            .unwrap_or(false);
+
         Ok(InitializeResult {
             server_info: Some(ServerInfo {
                 name: "SandServer".to_string(),
                 version: Some(env!("CARGO_PKG_VERSION").to_string()),
             }),
-            capabilities: ServerCapabilities {
-                text_document_sync: Some(TextDocumentSyncCapability::Options(
-                    TextDocumentSyncOptions {
-                        open_close: Some(true),
-                        change: Some(TextDocumentSyncKind::FULL),
-                        will_save: Some(false),
-                        will_save_wait_until: Some(false),
-                        save: Some(TextDocumentSyncSaveOptions::Supported(true)),
-                    },
-                )),
-                hover_provider: Some(HoverProviderCapability::Simple(true)),
-                ..Default::default()
-            },
+            capabilities: server_capabilities(),
         })
     }
 
     async fn initialized(&self, _: InitializedParams) {
+        // ファイル監視は静的なcapabilityがないので、対応しているクライアントにはここで登録する
+        if *self.watch_files.lock().await {
+            self.register_watched_files().await;
+        }
+
         self.client
             .log_message(MessageType::INFO, "server initialized!")
             .await;
@@ -349,18 +1173,16 @@ impl LanguageServer for SandServer {
     }
 
     async fn did_open(&self, params: DidOpenTextDocumentParams) {
+        let text = crate::parser::strip_bom(&params.text_document.text).to_string();
         let mut map = self.document_map.lock().await;
-        map.insert(
-            params.text_document.uri.clone(),
-            params.text_document.text.clone(),
-        );
+        map.insert(params.text_document.uri.clone(), text.clone());
         self.client
             .log_message(
                 MessageType::INFO,
                 format!("file opened: {}", params.text_document.uri),
             )
             .await;
-        self.publish_diagnostics(params.text_document.uri, params.text_document.text)
+        self.publish_diagnostics(params.text_document.uri, text)
             .await;
     }
 
@@ -372,7 +1194,7 @@ impl LanguageServer for SandServer {
             .content_changes
             .into_iter()
             .next()
-            .map(|change| change.text);
+            .map(|change| crate::parser::strip_bom(&change.text).to_string());
 
         if let Some(text) = new_text {
             let mut map = self.document_map.lock().await;
@@ -395,9 +1217,61 @@ impl LanguageServer for SandServer {
         }
     }
 
+    async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
+        let settings = SandSettings::from_value(&params.settings);
+        *self.settings.lock().await = settings;
+
+        self.client
+            .log_message(MessageType::INFO, "configuration changed")
+            .await;
+
+        let documents: Vec<_> = self
+            .document_map
+            .lock()
+            .await
+            .iter()
+            .map(|(uri, text)| (uri.clone(), text.clone()))
+            .collect();
+        for (uri, text) in documents {
+            self.publish_diagnostics(uri, text).await;
+        }
+    }
+
+    /// Forgets the diagnostics published for the changed files, so they are sent again, and
+    /// re-publishes the open documents that are among them or `@include` one of them. The text
+    /// of an open file comes from the editor, not from the disk.
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        let mut changed = vec![];
+        for change in &params.changes {
+            self.published.lock().await.remove(&change.uri);
+            if let Ok(path) = change.uri.to_file_path() {
+                changed.push(crate::include::canonicalize(&path));
+            }
+        }
+
+        let documents: Vec<_> = self
+            .document_map
+            .lock()
+            .await
+            .iter()
+            .filter(|(uri, text)| {
+                params.changes.iter().any(|change| &change.uri == *uri)
+                    || includes_any(uri, text, &changed)
+            })
+            .map(|(uri, text)| (uri.clone(), text.clone()))
+            .collect();
+        for (uri, text) in documents {
+            self.publish_diagnostics(uri, text).await;
+        }
+    }
+
     async fn did_close(&self, params: DidCloseTextDocumentParams) {
         let mut map = self.document_map.lock().await;
         map.remove(&params.text_document.uri);
+        self.published
+            .lock()
+            .await
+            .remove(&params.text_document.uri);
         self.client
             .log_message(
                 MessageType::INFO,
@@ -431,53 +1305,44 @@ impl LanguageServer for SandServer {
             &doc.ast,
         )
         .and_then(|ast| match &ast.node {
-            NodeKind::Sen(_) => Some(Hover {
+            NodeKind::Sen(sentences, _) => Some(Hover {
                 contents: HoverContents::Markup(MarkupContent {
                     kind: MarkupKind::Markdown,
-                    value: _doc::SENTENCE_DOC.into(),
+                    value: format!(
+                        "{}\n\n---\n\n{}",
+                        sentence_table(doc.names(), sentences),
+                        _doc::SENTENCE_DOC
+                    ),
                 }),
                 range: None,
             }),
-            NodeKind::All { .. } => Some(Hover {
+            NodeKind::All { all_or_names, .. } => Some(Hover {
                 contents: HoverContents::Markup(MarkupContent {
                     kind: MarkupKind::Markdown,
-                    value: _doc::ALL_DOC.into(),
+                    value: format!(
+                        "{}\n\n---\n\n{}",
+                        apply_all_targets(doc.names(), all_or_names.as_deref()),
+                        _doc::ALL_DOC
+                    ),
                 }),
                 range: None,
             }),
-            NodeKind::Section { .. } => Some(Hover {
+            NodeKind::Section { content, .. } => Some(Hover {
                 contents: HoverContents::Markup(MarkupContent {
                     kind: MarkupKind::Markdown,
-                    value: _doc::SECTION_DOC.into(),
+                    value: format!(
+                        "**{}**\n\n---\n\n{}",
+                        crate::formatter::preview(content, HOVER_PREVIEW_LEN),
+                        _doc::SECTION_DOC
+                    ),
                 }),
                 range: None,
             }),
-            NodeKind::Selector { local, .. } => {
-                // どうにかして親を取得
-                let target_ast = if *local {
-                    let parent = doc.ast.find_parent_at_position(position_to_byte_offset(
-                        text,
-                        &params.text_document_position_params.position,
-                    ));
-                    if let Some(parent) = parent {
-                        parent.clone()
-                    } else {
-                        eprintln!("failed to find the parent"); // TODO: error log
-                        return None;
-                    }
-                } else {
-                    doc.ast.clone()
-                };
-
-                let rendered = crate::formatter::render_plain(
-                    &Document {
-                        names: doc.names,
-                        ast: target_ast,
-                    },
-                    &crate::formatter::Selector(ast.clone()),
-                    false,
-                )
-                .join("\n\n---\n\n");
+            NodeKind::Selector { .. } => {
+                let rendered = selector_preview(
+                    &doc,
+                    position_to_byte_offset(text, &params.text_document_position_params.position),
+                )?;
 
                 Some(Hover {
                     contents: HoverContents::Markup(MarkupContent {
@@ -492,4 +1357,833 @@ impl LanguageServer for SandServer {
             _ => None,
         }))
     }
+
+    async fn goto_definition(
+        &self,
+        params: GotoDefinitionParams,
+    ) -> Result<Option<GotoDefinitionResponse>> {
+        let uri = params.text_document_position_params.text_document.uri;
+
+        let map = self.document_map.lock().await;
+        let Some(text) = map.get(&uri) else {
+            return Ok(None);
+        };
+        let (doc, locations) = parse_text(&uri, text)?;
+
+        let offset = position_to_byte_offset(text, &params.text_document_position_params.position);
+
+        Ok(definition_at(&doc, text, offset)
+            .map(|index| GotoDefinitionResponse::Scalar(locations[index].clone())))
+    }
+
+    async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
+        let uri = params.text_document.uri;
+        let doc = self.parse(&uri).await?;
+
+        let map = self.document_map.lock().await;
+        let Some(text) = map.get(&uri) else {
+            return Ok(None);
+        };
+
+        let offset = position_to_byte_offset(text, &params.range.start);
+
+        Ok(Some(
+            apply_all_action(&uri, text, &doc, offset)
+                .into_iter()
+                .map(CodeActionOrCommand::CodeAction)
+                .collect(),
+        ))
+    }
+
+    async fn symbol(
+        &self,
+        params: WorkspaceSymbolParams,
+    ) -> Result<Option<Vec<SymbolInformation>>> {
+        // 開いているドキュメントしか持っていないので、その中だけを探す
+        let map = self.document_map.lock().await;
+        let symbols: Vec<_> = map
+            .iter()
+            .filter_map(|(uri, text)| {
+                let (doc, _) = parse_text(uri, text).ok()?;
+                Some(symbols_in(uri, text, &doc, &params.query))
+            })
+            .flatten()
+            .collect();
+
+        Ok(Some(symbols))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        SandServer, SandSettings, apply_all_action, byte_offset_to_position,
+        convert_pest_error_to_diagnostic, definition_at, position_to_byte_offset,
+        server_capabilities,
+    };
+    use tower_lsp::lsp_types::{DiagnosticSeverity, Position, Url};
+
+    fn uri() -> Url {
+        Url::parse("file:///test.sand").unwrap()
+    }
+
+    #[test]
+    fn byte_offset_snaps_to_char_boundary() {
+        let text = "a\n🦀b";
+
+        let crab = Position {
+            line: 1,
+            character: 0,
+        };
+        assert_eq!(byte_offset_to_position(text, 2), crab);
+        for inside in 3..6 {
+            assert_eq!(byte_offset_to_position(text, inside), crab);
+        }
+        assert_eq!(
+            byte_offset_to_position(text, 6),
+            Position {
+                line: 1,
+                character: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn crlf_positions_round_trip() {
+        let text = "#(en, ja)\r\n#[やあ][x]\r\n";
+
+        assert_eq!(byte_offset_to_position(text, 9), Position::new(0, 9));
+        assert_eq!(byte_offset_to_position(text, 10), Position::new(0, 10));
+        assert_eq!(byte_offset_to_position(text, 11), Position::new(1, 0));
+        assert_eq!(
+            byte_offset_to_position(text, text.len()),
+            Position::new(2, 0)
+        );
+
+        for (offset, _) in text.char_indices() {
+            let position = byte_offset_to_position(text, offset);
+            assert_eq!(position_to_byte_offset(text, &position), offset);
+        }
+
+        // 単独の\rも改行になる
+        let text = "#(en, ja)\r#[やあ][x]\r";
+
+        assert_eq!(byte_offset_to_position(text, 9), Position::new(0, 9));
+        assert_eq!(byte_offset_to_position(text, 10), Position::new(1, 0));
+        assert_eq!(
+            byte_offset_to_position(text, text.len()),
+            Position::new(2, 0)
+        );
+
+        for (offset, _) in text.char_indices() {
+            let position = byte_offset_to_position(text, offset);
+            assert_eq!(position_to_byte_offset(text, &position), offset);
+        }
+    }
+
+    #[test]
+    fn crlf_diagnostics() {
+        let diagnostics = SandServer::generate_diagnostics(
+            &uri(),
+            "#(en, ja)\r\n#(en)\r\n",
+            &SandSettings::default(),
+        );
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range.start, Position::new(1, 0));
+        let related = diagnostics[0].related_information.as_ref().unwrap();
+        assert_eq!(related[0].location.range.end, Position::new(0, 9));
+
+        let lf = SandServer::generate_diagnostics(&uri(), LINTED, &SandSettings::default());
+        let crlf = SandServer::generate_diagnostics(
+            &uri(),
+            &LINTED.replace('\n', "\r\n"),
+            &SandSettings::default(),
+        );
+        assert_eq!(
+            lf.iter().map(|d| d.range.start).collect::<Vec<_>>(),
+            crlf.iter().map(|d| d.range.start).collect::<Vec<_>>()
+        );
+    }
+
+    const LINTED: &str = r#"
+#(en, ja)
+#empty# Empty
+#full# Full
+#{[en, fr], { Hello }}
+#{[ja], { やあ }}
+"#;
+
+    #[test]
+    fn empty_document_reports_missing_names() {
+        let diagnostics = SandServer::generate_diagnostics(&uri(), "", &SandSettings::default());
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::ERROR));
+        assert_eq!(diagnostics[0].range.start, Position::new(0, 0));
+    }
+
+    #[test]
+    fn multiple_name_define_has_related_information() {
+        let diagnostics = SandServer::generate_diagnostics(
+            &uri(),
+            "#(en, ja)\n#(en)\n",
+            &SandSettings::default(),
+        );
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range.start, Position::new(1, 0));
+
+        let related = diagnostics[0].related_information.as_ref().unwrap();
+        assert_eq!(related.len(), 1);
+        assert_eq!(related[0].location.uri, uri());
+        assert_eq!(related[0].location.range.start, Position::new(0, 0));
+        assert_eq!(related[0].location.range.end, Position::new(0, 9));
+    }
+
+    #[test]
+    fn settings_toggle_lints() {
+        let all = SandServer::generate_diagnostics(&uri(), LINTED, &SandSettings::default());
+        assert_eq!(all.len(), 2);
+
+        let settings = SandSettings::from_value(&serde_json::json!({
+            "sand": { "lints": { "emptySection": false } }
+        }));
+        assert!(!settings.empty_section);
+        assert!(settings.unknown_apply_all_target);
+
+        let filtered = SandServer::generate_diagnostics(&uri(), LINTED, &settings);
+        assert_eq!(filtered.len(), 1);
+        assert!(filtered[0].message.contains("fr"));
+
+        let settings = SandSettings::from_value(&serde_json::json!({
+            "sand.lints.unknownApplyAllTarget": false,
+            "sand.lints.emptySection": false,
+        }));
+        assert!(SandServer::generate_diagnostics(&uri(), LINTED, &settings).is_empty());
+
+        let settings = SandSettings::from_value(&serde_json::json!({
+            "sand": { "lints": { "maxSectionDepth": 1 } }
+        }));
+        assert_eq!(settings.max_section_depth, 1);
+        let diagnostics = SandServer::generate_diagnostics(
+            &uri(),
+            "#(en, ja)\n## One\n### Two\n#[A][あ]\n",
+            &settings,
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::WARNING));
+        assert_eq!(diagnostics[0].range.start, Position::new(2, 0));
+    }
+
+    #[test]
+    fn draft_downgrades_missing_names() {
+        let text = "#a# One\n#a# Two\n";
+
+        let diagnostics = SandServer::generate_diagnostics(&uri(), text, &SandSettings::default());
+        assert_eq!(diagnostics.len(), 3);
+        assert!(
+            diagnostics
+                .iter()
+                .all(|d| d.severity == Some(DiagnosticSeverity::ERROR))
+        );
+
+        let settings = SandSettings::from_value(&serde_json::json!({ "sand": { "draft": true } }));
+        assert!(settings.draft);
+        let diagnostics = SandServer::generate_diagnostics(&uri(), text, &settings);
+        let severity = |missing: bool| {
+            diagnostics
+                .iter()
+                .find(|d| d.message.contains("names") == missing)
+                .and_then(|d| d.severity)
+        };
+        assert_eq!(severity(true), Some(DiagnosticSeverity::WARNING));
+        assert_eq!(severity(false), Some(DiagnosticSeverity::ERROR));
+    }
+
+    #[test]
+    fn goto_name_definition() {
+        use crate::parser::{Document, Rule, SandParser, Span};
+        use pest::Parser as _;
+
+        let text = "#(en, ja)\n#sec# Title\n#{[ja, en],{ Hello }}\n#.sec.en\n";
+        let doc: Document = SandParser::parse(Rule::doc, text)
+            .unwrap()
+            .try_into()
+            .unwrap();
+        let en = Span { start: 2, end: 4 };
+        let definition =
+            |offset| definition_at(&doc, text, offset).map(|index| doc.name_spans[index].clone());
+
+        let target = text.find("en]").unwrap();
+        assert_eq!(definition(target), Some(en.clone()));
+        assert_eq!(definition(target + 1), Some(en.clone()));
+
+        let ja = text.find("ja,").unwrap();
+        assert_eq!(definition(ja), Some(Span { start: 6, end: 8 }));
+
+        assert_eq!(definition(text.rfind("en").unwrap()), Some(en.clone()));
+        assert_eq!(definition(3), Some(en));
+
+        assert_eq!(definition(text.find("Hello").unwrap()), None);
+        assert_eq!(definition(text.find("sec.").unwrap()), None);
+    }
+
+    #[tokio::test]
+    async fn goto_name_definition_in_included_file() {
+        use tower_lsp::LanguageServer as _;
+        use tower_lsp::lsp_types::{
+            GotoDefinitionParams, GotoDefinitionResponse, Location, Range, TextDocumentIdentifier,
+            TextDocumentPositionParams,
+        };
+
+        let (service, _socket) = super::service();
+        let server = service.inner();
+
+        let dir = std::env::temp_dir().join(format!("sand-lsp-goto-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let names = dir.join("names.sand");
+        std::fs::write(&names, "#(en, ja)\n").unwrap();
+        let uri = Url::from_file_path(dir.join("doc.sand")).unwrap();
+
+        let text = "@include \"names.sand\"\n#{[en],{Hi}}\n";
+        server
+            .document_map
+            .lock()
+            .await
+            .insert(uri.clone(), text.to_string());
+
+        let definition = |position| {
+            server.goto_definition(GotoDefinitionParams {
+                text_document_position_params: TextDocumentPositionParams {
+                    text_document: TextDocumentIdentifier { uri: uri.clone() },
+                    position,
+                },
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            })
+        };
+
+        // 宣言は読み込んだファイルの中にある
+        assert_eq!(
+            definition(Position::new(1, 3)).await.unwrap(),
+            Some(GotoDefinitionResponse::Scalar(Location {
+                uri: Url::from_file_path(&names).unwrap(),
+                range: Range::new(Position::new(0, 2), Position::new(0, 4)),
+            }))
+        );
+        // `@include` の `in` は名前ではない
+        assert_eq!(definition(Position::new(0, 2)).await.unwrap(), None);
+    }
+
+    #[test]
+    fn advertised_capabilities() {
+        use tower_lsp::lsp_types::{
+            CodeActionProviderCapability, HoverProviderCapability, OneOf, TextDocumentSyncKind,
+        };
+
+        let capabilities = server_capabilities();
+
+        assert_eq!(
+            capabilities.hover_provider,
+            Some(HoverProviderCapability::Simple(true))
+        );
+        assert_eq!(capabilities.definition_provider, Some(OneOf::Left(true)));
+        assert_eq!(
+            capabilities.workspace_symbol_provider,
+            Some(OneOf::Left(true))
+        );
+        assert_eq!(
+            capabilities.code_action_provider,
+            Some(CodeActionProviderCapability::Simple(true))
+        );
+        assert!(matches!(
+            capabilities.text_document_sync,
+            Some(tower_lsp::lsp_types::TextDocumentSyncCapability::Options(options))
+                if options.change == Some(TextDocumentSyncKind::FULL)
+        ));
+        assert!(capabilities.completion_provider.is_none());
+    }
+
+    #[test]
+    fn apply_all_code_action() {
+        use crate::parser::{Document, Rule, SandParser};
+        use pest::Parser as _;
+        use tower_lsp::lsp_types::Range;
+
+        let text = "#(en, ja)\n#same[ OK ][OK]\n#[Hello][こんにちは]\n";
+        let doc: Document = SandParser::parse(Rule::doc, text)
+            .unwrap()
+            .try_into()
+            .unwrap();
+
+        let action = apply_all_action(&uri(), text, &doc, text.find("OK").unwrap()).unwrap();
+        let changes = action.edit.unwrap().changes.unwrap();
+        let edits = &changes[&uri()];
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].new_text, "#same{{ OK }}");
+        assert_eq!(
+            edits[0].range,
+            Range::new(Position::new(1, 0), Position::new(1, 15))
+        );
+
+        assert!(apply_all_action(&uri(), text, &doc, text.find("Hello").unwrap()).is_none());
+    }
+
+    #[test]
+    fn sentence_hover_lists_names() {
+        use crate::parser::NodeKind;
+
+        let names = ["en".to_string(), "ja".to_string()];
+        let table = |node: &NodeKind| match node {
+            NodeKind::Sen(sentences, _) => super::sentence_table(&names, sentences),
+            _ => unreachable!(),
+        };
+
+        let node = NodeKind::Sen(vec!["Hello".to_string(), "こんにちは".to_string()], None);
+        assert_eq!(table(&node), "en: Hello / ja: こんにちは");
+
+        let node = NodeKind::Sen(vec![" Hello\n world ".to_string()], None);
+        assert_eq!(table(&node), "en: Hello world / ja: (missing)");
+
+        let node = NodeKind::Sen(
+            vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            None,
+        );
+        assert_eq!(table(&node), "en: a / ja: b / (no name): c");
+    }
+
+    #[test]
+    fn apply_all_hover_lists_targets() {
+        use crate::parser::NodeKind;
+
+        let names = ["en".to_string(), "ja".to_string()];
+        let targets = |node: &NodeKind| match node {
+            NodeKind::All { all_or_names, .. } => {
+                super::apply_all_targets(&names, all_or_names.as_deref())
+            }
+            _ => unreachable!(),
+        };
+
+        let node = NodeKind::All {
+            all_or_names: Some(vec!["en".to_string(), "mobile".to_string()]),
+            content: "Hello".to_string(),
+        };
+        assert_eq!(targets(&node), "Applies to: en, mobile (not declared)");
+
+        let node = NodeKind::All {
+            all_or_names: None,
+            content: "Hello".to_string(),
+        };
+        assert_eq!(targets(&node), "Applies to: all names");
+    }
+
+    #[tokio::test]
+    async fn unchanged_diagnostics_are_not_republished() {
+        let (service, _socket) = super::service();
+        let server = service.inner();
+        let settings = SandSettings::default();
+
+        let mut published = 0;
+        for text in [
+            "#(en, ja)\n#[Hi]\n",
+            "#(en, ja)\n#[Hi]\n\n  \n",
+            "#(en, ja)\n#[Hi][やあ]\n",
+            "#(en, ja)\n#[Hi][やあ]\n\n",
+        ] {
+            let diagnostics = SandServer::generate_diagnostics(&uri(), text, &settings);
+            if server
+                .changed_diagnostics(&uri(), diagnostics)
+                .await
+                .is_some()
+            {
+                published += 1;
+            }
+        }
+        assert_eq!(published, 2);
+    }
+
+    #[test]
+    fn diagnostics_expand_includes() {
+        let dir = std::env::temp_dir().join(format!("sand-lsp-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("names.sand"), "#(en, ja)\n#[Oops]\n").unwrap();
+        let uri = Url::from_file_path(dir.join("doc.sand")).unwrap();
+
+        let text = "@include \"names.sand\"\n#[Hi][やあ]\n#[Hi]\n";
+        let diagnostics = SandServer::generate_diagnostics(&uri, text, &SandSettings::default());
+
+        // 読み込んだファイルの中の診断は先頭に、文書の中の診断はその位置に出す
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].range.start, Position::new(0, 0));
+        assert!(diagnostics[0].message.contains("names.sand"));
+        assert_eq!(diagnostics[1].range.start, Position::new(2, 0));
+    }
+
+    #[tokio::test]
+    async fn watched_file_change_clears_published() {
+        use tower_lsp::LanguageServer as _;
+        use tower_lsp::lsp_types::{DidChangeWatchedFilesParams, FileChangeType, FileEvent};
+
+        let (service, _socket) = super::service();
+        let server = service.inner();
+
+        let closed = Url::parse("file:///closed.sand").unwrap();
+        let text = "#(en, ja)\n#[Hi]\n";
+        for uri in [&closed, &uri()] {
+            let diagnostics = SandServer::generate_diagnostics(uri, text, &SandSettings::default());
+            server.changed_diagnostics(uri, diagnostics).await;
+        }
+        server
+            .document_map
+            .lock()
+            .await
+            .insert(uri(), "#(en, ja)\n#[Hi][やあ]\n".to_string());
+
+        server
+            .did_change_watched_files(DidChangeWatchedFilesParams {
+                changes: [&closed, &uri()]
+                    .map(|uri| FileEvent::new(uri.clone(), FileChangeType::CHANGED))
+                    .into(),
+            })
+            .await;
+
+        let published = server.published.lock().await;
+        assert!(!published.contains_key(&closed));
+        // 開いている文書はエディタの内容で出し直す
+        assert_eq!(published.get(&uri()), Some(&vec![]));
+    }
+
+    #[tokio::test]
+    async fn watched_include_change_republishes_includer() {
+        use tower_lsp::LanguageServer as _;
+        use tower_lsp::lsp_types::{DidChangeWatchedFilesParams, FileChangeType, FileEvent};
+
+        let (service, _socket) = super::service();
+        let server = service.inner();
+
+        let dir = std::env::temp_dir().join(format!("sand-lsp-watch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let names = dir.join("names.sand");
+        std::fs::write(&names, "#(en, ja)\n").unwrap();
+        let uri = Url::from_file_path(dir.join("doc.sand")).unwrap();
+
+        let text = "@include \"names.sand\"\n#[Hi][やあ]\n".to_string();
+        server
+            .document_map
+            .lock()
+            .await
+            .insert(uri.clone(), text.clone());
+        server.publish_diagnostics(uri.clone(), text).await;
+        assert_eq!(server.published.lock().await.get(&uri), Some(&vec![]));
+
+        // 読み込まれているファイルだけが変わっても、読み込んでいる文書を出し直す
+        std::fs::write(&names, "#(en, ja, fr)\n").unwrap();
+        server
+            .did_change_watched_files(DidChangeWatchedFilesParams {
+                changes: vec![FileEvent::new(
+                    Url::from_file_path(&names).unwrap(),
+                    FileChangeType::CHANGED,
+                )],
+            })
+            .await;
+
+        let published = server.published.lock().await;
+        assert!(!published.get(&uri).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn watched_files_need_dynamic_registration() {
+        use tower_lsp::LanguageServer as _;
+        use tower_lsp::lsp_types::{
+            ClientCapabilities, DidChangeWatchedFilesClientCapabilities, InitializeParams,
+            WorkspaceClientCapabilities,
+        };
+
+        let (service, _socket) = super::service();
+        let server = service.inner();
+
+        server
+            .initialize(InitializeParams::default())
+            .await
+            .unwrap();
+        assert!(!*server.watch_files.lock().await);
+
+        let capabilities = ClientCapabilities {
+            workspace: Some(WorkspaceClientCapabilities {
+                did_change_watched_files: Some(DidChangeWatchedFilesClientCapabilities {
+                    dynamic_registration: Some(true),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        server
+            .initialize(InitializeParams {
+                capabilities,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert!(*server.watch_files.lock().await);
+    }
+
+    #[tokio::test]
+    async fn version_request() {
+        use tower_lsp::jsonrpc::Request;
+        use tower_service::Service as _;
+
+        let (mut service, _socket) = super::service();
+
+        // initializeの前のリクエストはエラーになる
+        let initialize = Request::build("initialize")
+            .params(serde_json::json!({ "capabilities": {} }))
+            .id(1)
+            .finish();
+        service.call(initialize).await.unwrap();
+
+        let request = Request::build("sand/version").id(2).finish();
+        let response = service.call(request).await.unwrap().unwrap();
+        let info: super::VersionInfo =
+            serde_json::from_value(response.result().unwrap().clone()).unwrap();
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(info.open_documents, 0);
+    }
+
+    #[test]
+    fn pest_error_lists_expected_rules() {
+        use crate::parser::Rule;
+        use pest::error::{Error, ErrorVariant};
+
+        let text = "#(en\n";
+        let error = Error::new_from_pos(
+            ErrorVariant::ParsingError {
+                positives: vec![Rule::Ident],
+                negatives: vec![Rule::Selector],
+            },
+            pest::Position::new(text, 4).unwrap(),
+        );
+
+        let diagnostic = convert_pest_error_to_diagnostic(text, error);
+        assert_eq!(
+            diagnostic.message,
+            "failed to parse input, expected: [Ident], not: [Selector]"
+        );
+        assert_eq!(diagnostic.range.start, Position::new(0, 4));
+
+        let error = Error::new_from_pos(
+            ErrorVariant::CustomError {
+                message: "custom".to_string(),
+            },
+            pest::Position::new(text, 0).unwrap(),
+        );
+        assert_eq!(
+            convert_pest_error_to_diagnostic(text, error).message,
+            "custom"
+        );
+    }
+
+    #[test]
+    fn uncovered_name_is_information() {
+        let diagnostics = SandServer::generate_diagnostics(
+            &uri(),
+            "#(en, ja)\n#[Hi][\n]\n",
+            &SandSettings::default(),
+        );
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].severity,
+            Some(DiagnosticSeverity::INFORMATION)
+        );
+        assert_eq!(diagnostics[0].range.start, Position::new(0, 6));
+        assert!(diagnostics[0].message.contains("ja"));
+    }
+
+    #[tokio::test]
+    async fn workspace_symbol() {
+        use tower_lsp::LanguageServer as _;
+        use tower_lsp::lsp_types::WorkspaceSymbolParams;
+
+        let (service, _socket) = super::service();
+        let server = service.inner();
+        let other = Url::parse("file:///other.sand").unwrap();
+        {
+            let mut map = server.document_map.lock().await;
+            map.insert(
+                uri(),
+                "#(en, ja)\n#intro# Intro\n#greeting[Hello][こんにちは]\n".to_string(),
+            );
+            map.insert(other.clone(), "#(en, ja)\n#outro# Outro\n".to_string());
+        }
+
+        let symbol = |query: &str| {
+            server.symbol(WorkspaceSymbolParams {
+                query: query.to_string(),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            })
+        };
+
+        let symbols = symbol("greet").await.unwrap().unwrap();
+        assert_eq!(symbols.len(), 1);
+        assert_eq!(symbols[0].name, "greeting");
+        assert_eq!(symbols[0].location.uri, uri());
+        assert_eq!(symbols[0].location.range.start, Position::new(2, 0));
+        assert_eq!(symbols[0].container_name.as_deref(), Some("intro"));
+
+        let symbols = symbol("OUTRO").await.unwrap().unwrap();
+        assert_eq!(symbols.len(), 1);
+        assert_eq!(symbols[0].location.uri, other);
+
+        assert_eq!(symbol("").await.unwrap().unwrap().len(), 3);
+        assert!(symbol("missing").await.unwrap().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn workspace_symbol_with_include() {
+        use tower_lsp::LanguageServer as _;
+        use tower_lsp::lsp_types::WorkspaceSymbolParams;
+
+        let (service, _socket) = super::service();
+        let server = service.inner();
+
+        let dir = std::env::temp_dir().join(format!("sand-lsp-symbol-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("names.sand"), "#(en, ja)\n").unwrap();
+        let uri = Url::from_file_path(dir.join("doc.sand")).unwrap();
+        server.document_map.lock().await.insert(
+            uri.clone(),
+            "@include \"names.sand\"\n#intro# Intro\n#[Hi][やあ]\n".to_string(),
+        );
+
+        let symbols = server
+            .symbol(WorkspaceSymbolParams {
+                query: "intro".to_string(),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(symbols.len(), 1);
+        assert_eq!(symbols[0].location.uri, uri);
+        assert_eq!(symbols[0].location.range.start, Position::new(1, 0));
+    }
+
+    #[tokio::test]
+    async fn preview_request() {
+        use super::{PreviewEntry, PreviewParams};
+
+        let (service, _socket) = super::service();
+        let server = service.inner();
+        server.document_map.lock().await.insert(
+            uri(),
+            "#(en, ja)\n#intro# Intro\n#[Hello][こんにちは]\n".to_string(),
+        );
+
+        let preview = |selector: &str| {
+            server.preview(PreviewParams {
+                uri: uri(),
+                selector: selector.to_string(),
+            })
+        };
+
+        let result = preview("#.intro.").await.unwrap();
+        assert_eq!(
+            result.entries,
+            [
+                PreviewEntry {
+                    name: "en".to_string(),
+                    text: "Hello".to_string(),
+                },
+                PreviewEntry {
+                    name: "ja".to_string(),
+                    text: "こんにちは".to_string(),
+                },
+            ]
+        );
+
+        let result = preview("#.intro.ja").await.unwrap();
+        assert_eq!(result.entries.len(), 1);
+        assert_eq!(result.entries[0].name, "ja");
+
+        assert!(preview("#.missing.en").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn local_selector_preview() {
+        let (service, _socket) = super::service();
+        let server = service.inner();
+        server.document_map.lock().await.insert(
+            uri(),
+            "#(en, ja)\n#[Top][トップ]\n#intro# Intro\n#./en\n#[Hello][こんにちは]\n".to_string(),
+        );
+
+        assert_eq!(
+            server
+                .selector_preview_at(&uri(), &Position::new(3, 0))
+                .await
+                .as_deref(),
+            Some("Hello")
+        );
+        assert_eq!(
+            server
+                .selector_preview_at(&uri(), &Position::new(1, 2))
+                .await,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn apply_all_preview_for_other_name() {
+        let (service, _socket) = super::service();
+        let server = service.inner();
+        server.document_map.lock().await.insert(
+            uri(),
+            "#(en, ja)\n#greet{[en], {Hi}}\n#.greet.ja\n#.greet.en\n".to_string(),
+        );
+
+        assert_eq!(
+            server
+                .selector_preview_at(&uri(), &Position::new(2, 0))
+                .await
+                .as_deref(),
+            Some("this apply-all does not apply to name ja")
+        );
+        assert_eq!(
+            server
+                .selector_preview_at(&uri(), &Position::new(3, 0))
+                .await
+                .as_deref(),
+            Some("Hi")
+        );
+    }
+
+    #[tokio::test]
+    async fn source_prefix() {
+        use tower_lsp::LanguageServer as _;
+        use tower_lsp::lsp_types::InitializeParams;
+
+        let (service, _socket) = super::service();
+        let server = service.inner();
+
+        let diagnostics = server.diagnostics_for(&uri(), "#(en, ja)\n#(en)\n").await;
+        assert_eq!(diagnostics[0].source.as_deref(), Some("Sand Validator"));
+
+        server
+            .initialize(InitializeParams {
+                initialization_options: Some(serde_json::json!({ "sourcePrefix": "docs: " })),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let diagnostics = server.diagnostics_for(&uri(), "#(en, ja)\n#(en)\n").await;
+        assert_eq!(
+            diagnostics[0].source.as_deref(),
+            Some("docs: Sand Validator")
+        );
+    }
 }
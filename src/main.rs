@@ -1,9 +1,10 @@
 use anyhow::Result;
 
-use sand::parser::{Document, ParseError, Rule, Span};
+use sand::include::Source;
+use sand::parser::{Document, ParseError, ParseOptions, Rule, Span};
 
-use std::path::PathBuf;
-use tokio::{fs::File, io::AsyncReadExt};
+use rustc_hash::FxHashSet;
+use std::path::{Path, PathBuf};
 
 use clap::{CommandFactory, Parser, Subcommand};
 
@@ -12,6 +13,10 @@ use clap::{CommandFactory, Parser, Subcommand};
 struct Args {
     #[command(subcommand)]
     command: Command,
+
+    /// Number of columns a tab takes up when showing source in error messages.
+    #[arg(long, global = true, value_name = "N", default_value_t = 4)]
+    tab_width: usize,
 }
 
 #[derive(Subcommand, Debug)]
@@ -26,6 +31,119 @@ enum Command {
         /// Must point to a readable file containing the source to validate.
         #[arg(value_name = "FILE", value_parser)]
         input: PathBuf,
+
+        /// Only validate the file, without printing the parsed document.
+        #[arg(long, short)]
+        quiet: bool,
+    },
+
+    /// Check a file for errors without printing it.
+    ///
+    /// If the file has no errors, lints such as empty sections are reported as warnings.
+    /// Exits with status 1 if there are errors.
+    Check {
+        /// Path to the input file.
+        #[arg(value_name = "FILE", value_parser)]
+        input: PathBuf,
+
+        /// How to report the errors.
+        #[arg(long, value_enum, default_value_t = CheckFormat::Human)]
+        format: CheckFormat,
+
+        /// Also report free text that is not part of any statement and sections that skip a
+        /// level.
+        #[arg(long)]
+        strict: bool,
+
+        /// Exit with status 1 on warnings and notes too.
+        #[arg(long)]
+        deny_warnings: bool,
+    },
+
+    /// Check every `*.sand` file in a directory and its subdirectories for errors.
+    ///
+    /// Prints whether each file is ok and a summary. Exits with status 1 if any file has
+    /// errors.
+    CheckAll {
+        /// The directory to search.
+        #[arg(value_name = "DIR", value_parser)]
+        dir: PathBuf,
+
+        /// Also report free text that is not part of any statement and sections that skip a
+        /// level.
+        #[arg(long)]
+        strict: bool,
+
+        /// Count files with warnings or notes as failed too.
+        #[arg(long)]
+        deny_warnings: bool,
+    },
+
+    /// Print the declared names and an outline of the document.
+    ///
+    /// Shows sections, sentences, apply-all blocks and selectors as an indented tree,
+    /// a human-readable alternative to the `parse` debug output.
+    Tree {
+        /// Path to the input file.
+        #[arg(value_name = "FILE", value_parser)]
+        input: PathBuf,
+
+        /// Maximum nesting depth to print.
+        #[arg(long, value_name = "N")]
+        depth: Option<usize>,
+    },
+
+    /// Print the number of names, sections, sentences, apply-all blocks and selectors.
+    Stats {
+        /// Path to the input file.
+        #[arg(value_name = "FILE", value_parser)]
+        input: PathBuf,
+
+        /// Print the counts as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Print the document with redundant escapes resolved.
+    ///
+    /// `\#` and `\/` in content are written as `#` and `/`. Escapes that are
+    /// needed, such as `\]` or `\n`, are kept. Running it again changes nothing.
+    Normalize {
+        /// Path to the input file.
+        #[arg(value_name = "FILE", value_parser)]
+        input: PathBuf,
+    },
+
+    /// Show the structural differences between two revisions of a document.
+    ///
+    /// Compares aliased sections, sentences and apply-all blocks by their alias paths.
+    Diff {
+        /// The old revision.
+        #[arg(value_name = "OLD", value_parser)]
+        old: PathBuf,
+
+        /// The new revision.
+        #[arg(value_name = "NEW", value_parser)]
+        new: PathBuf,
+    },
+
+    /// Export the sentences as a translation table.
+    ///
+    /// Writes one row per sentence block, keyed by its alias path, with a column per name.
+    Export {
+        /// Path to the input file.
+        #[arg(value_name = "FILE", value_parser)]
+        input: PathBuf,
+
+        /// The file format to write.
+        #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+
+        /// The name whose sentences are the translations (`msgstr`) in PO files.
+        ///
+        /// Defaults to the second declared name. The first declared name is the source.
+        #[arg(long, value_name = "NAME")]
+        name: Option<String>,
     },
 
     /// Launch the Language Server Protocol (LSP) server.
@@ -44,30 +162,154 @@ enum Command {
     ///
     /// Extracts and displays specific content from the document based on
     /// the provided selector syntax.
-    Out {
-        /// Selector string to filter document content.
-        ///
-        /// Uses dot-notation to navigate the document structure.
-        selector: String,
-        /// Path to the input file to process.
-        #[arg(long, short, value_name = "FILE", value_parser)]
-        input: PathBuf,
+    Out(OutArgs),
+}
 
-        /// Output as Markdown Text
-        #[arg(long, short)]
-        markdown: bool,
-    },
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum CheckFormat {
+    /// Annotated source snippets on stderr.
+    Human,
+    /// A SARIF 2.1.0 report on stdout, e.g. for GitHub code scanning.
+    Sarif,
+    /// GitHub Actions workflow commands on stdout, shown as annotations on pull requests.
+    Github,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum ExportFormat {
+    /// Comma-separated values with a header row of `path` and the names.
+    Csv,
+    /// A gettext PO file translating the first name into `--name`.
+    Po,
+}
+
+#[derive(clap::Args, Debug)]
+struct OutArgs {
+    /// Selector string to filter document content.
+    ///
+    /// Uses dot-notation to navigate the document structure.
+    selector: String,
+    /// Path to the input file to process.
+    #[arg(long, short, value_name = "FILE", value_parser)]
+    input: PathBuf,
+
+    /// Output as Markdown Text
+    #[arg(long, short)]
+    markdown: bool,
+
+    /// Style the Markdown output for the terminal (bold headers, dim rules).
+    #[arg(long, requires = "markdown")]
+    pretty: bool,
+
+    /// Escape Markdown metacharacters such as `*` and `_` in the content.
+    #[arg(long, requires = "markdown")]
+    escape_markdown: bool,
+
+    /// Begin the output for each name with a YAML front matter block holding the name
+    /// and the declared names, e.g. for static site generators.
+    #[arg(long, requires = "markdown")]
+    front_matter: bool,
+
+    /// Write the output for each name into this template instead of under a header, e.g.
+    /// `## {name}\n{content}\n`. `\n` in the template is a line break.
+    #[arg(long, value_name = "TEMPLATE", value_parser = parse_template)]
+    template: Option<String>,
+
+    /// When to use colors.
+    #[arg(long, value_name = "WHEN", default_value_t = clap::ColorChoice::Auto)]
+    color: clap::ColorChoice,
+
+    /// Keep the whitespace of the content as written instead of collapsing it.
+    #[arg(long)]
+    raw: bool,
+
+    /// Number the section headings, e.g. `1.2 Title`.
+    #[arg(long)]
+    number_sections: bool,
+
+    /// Only render this many levels of sections, e.g. `1` for the top-level sections of the
+    /// selection without their subsections.
+    #[arg(long, value_name = "N")]
+    max_depth: Option<usize>,
+
+    /// Only output the sections with this attribute, e.g. `status=published`.
+    ///
+    /// The ancestors of a matching section keep their headings, and the subsections
+    /// without the attribute follow their parent. Applied before the selector.
+    #[arg(long = "where", value_name = "KEY=VALUE", value_parser = parse_attribute)]
+    filter: Option<(String, String)>,
+
+    /// Only output the content for this declared name.
+    ///
+    /// Useful with selectors that expand to all names, such as `#.sec.`.
+    #[arg(long, value_name = "NAME")]
+    name: Option<String>,
+
+    /// Output every declared name, even if the selector ends in a name such as `#.sec.en`.
+    #[arg(long, conflicts_with = "name")]
+    all_names: bool,
+
+    /// Output the apply-all blocks for the other names instead of the ones for each name,
+    /// to review what a name is missing. Blocks for every name are left out.
+    #[arg(long)]
+    only_other_names: bool,
+
+    /// Before the output, print what each segment of the selector matched and the kind of
+    /// node it leads to.
+    #[arg(long)]
+    explain: bool,
+
+    /// Exit with status 2 if the selector renders nothing for the selected names.
+    #[arg(long)]
+    error_on_empty: bool,
+
+    /// Re-render whenever the input file or a file it includes changes.
+    ///
+    /// Errors are reported without exiting, so the file can be fixed and saved again.
+    #[arg(long, short)]
+    watch: bool,
+}
+
+fn parse_template(s: &str) -> std::result::Result<String, String> {
+    if s.contains("{content}") {
+        Ok(s.replace("\\n", "\n"))
+    } else {
+        Err("the template has no `{content}` placeholder".to_string())
+    }
+}
+
+fn parse_attribute(s: &str) -> std::result::Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected KEY=VALUE, found `{s}`"))
+}
+
+use codespan_reporting::diagnostic::{Diagnostic, Label, Severity};
+
+/// Lints with [`sand::lint::Severity::Information`] are reported as notes.
+fn lint_severity(lint: &sand::lint::Lint) -> Severity {
+    match lint.severity() {
+        sand::lint::Severity::Information => Severity::Note,
+        sand::lint::Severity::Warning => Severity::Warning,
+    }
 }
 
-use codespan_reporting::diagnostic::{Diagnostic, Label};
+pub fn convert_lint(file_id: usize, lint: &sand::lint::Lint) -> Diagnostic<usize> {
+    let span = lint.span();
+    Diagnostic::new(lint_severity(lint))
+        .with_message(lint.to_string())
+        .with_labels(vec![Label::primary(file_id, span.start..span.end)])
+}
 
 pub fn convert_parse_error(file_id: usize, err: &ParseError) -> Diagnostic<usize> {
     match err {
-        ParseError::MultipleNameDefine(span) => Diagnostic::error()
+        ParseError::MultipleNameDefine(first, span) => Diagnostic::error()
             .with_message("names are defined more than once")
             .with_labels(vec![
                 Label::primary(file_id, span.start..span.end)
                     .with_message("this is a repeated definition"),
+                Label::secondary(file_id, first.start..first.end)
+                    .with_message("names are first defined here"),
             ]),
         ParseError::DuplicateNames(name, span) => Diagnostic::error()
             .with_message(format!("duplicate name: `{name}`"))
@@ -98,6 +340,53 @@ pub fn convert_parse_error(file_id: usize, err: &ParseError) -> Diagnostic<usize
                 Label::primary(file_id, span.start..span.end)
                     .with_message("the number of this sentences is incorrect"),
             ]),
+        ParseError::UnknownLabel(label, span) => Diagnostic::error()
+            .with_message(format!("unknown sentence label: `{label}`"))
+            .with_labels(vec![
+                Label::primary(file_id, span.start..span.end)
+                    .with_message("this label is not a declared name"),
+            ]),
+        ParseError::DuplicateLabel(label, span) => Diagnostic::error()
+            .with_message(format!("duplicate sentence label: `{label}`"))
+            .with_labels(vec![
+                Label::primary(file_id, span.start..span.end)
+                    .with_message("this label is already used"),
+            ]),
+        ParseError::MissingLabel(name, span) => Diagnostic::error()
+            .with_message(format!("no sentence for the name `{name}`"))
+            .with_labels(vec![
+                Label::primary(file_id, span.start..span.end)
+                    .with_message(format!("add a block labeled `{name}:`")),
+            ]),
+        ParseError::SkippedSectionLevel { from, to, span } => Diagnostic::error()
+            .with_message(format!("the section skips from level {from} to level {to}"))
+            .with_labels(vec![
+                Label::primary(file_id, span.start..span.end)
+                    .with_message(format!("expected at most level {}", from + 1)),
+            ]),
+        ParseError::UnknownStatement(span) => Diagnostic::error()
+            .with_message("the text is not a statement and is ignored")
+            .with_labels(vec![
+                Label::primary(file_id, span.start..span.end)
+                    .with_message("not part of any section, sentence or selector"),
+            ]),
+        ParseError::EmptyNames(span) => Diagnostic::error()
+            .with_message("the name declaration is empty")
+            .with_labels(vec![
+                Label::primary(file_id, span.start..span.end).with_message("declare names here"),
+            ]),
+        ParseError::TooManyNames(max, span) => Diagnostic::error()
+            .with_message(format!("more than {max} names are declared"))
+            .with_labels(vec![
+                Label::primary(file_id, span.start..span.end)
+                    .with_message(format!("declare at most {max} names")),
+            ]),
+        ParseError::DuplicateAttribute(key, span) => Diagnostic::error()
+            .with_message(format!("duplicate section attribute: `{key}`"))
+            .with_labels(vec![
+                Label::primary(file_id, span.start..span.end)
+                    .with_message("this key is already given"),
+            ]),
     }
 }
 
@@ -105,8 +394,6 @@ pub fn convert_pest_error(
     file_id: usize,
     error: pest::error::Error<sand::parser::Rule>,
 ) -> Diagnostic<usize> {
-    use pest::error::ErrorVariant;
-
     let span = {
         let (start, end) = match error.location {
             pest::error::InputLocation::Pos(pos) => (pos, pos + 1),
@@ -115,41 +402,35 @@ pub fn convert_pest_error(
         Span { start, end }
     };
 
-    match error.variant {
-        ErrorVariant::ParsingError {
-            positives,
-            negatives,
-        } => {
-            let mut msg = String::from("failed to parse input");
-            if !positives.is_empty() {
-                msg += &format!(", expected: {positives:?}");
-            }
-            if !negatives.is_empty() {
-                msg += &format!(", not: {negatives:?}");
-            }
-
-            Diagnostic::error()
-                .with_message(msg)
-                .with_labels(vec![Label::primary(file_id, span.start..span.end)])
-        }
-        ErrorVariant::CustomError { message } => Diagnostic::error()
-            .with_message(message)
-            .with_labels(vec![Label::primary(file_id, span.start..span.end)]),
-    }
+    Diagnostic::error()
+        .with_message(sand::parser::pest_error_message(&error.variant))
+        .with_labels(vec![Label::primary(file_id, span.start..span.end)])
 }
 
 use codespan_reporting::files::SimpleFiles;
 
-fn report(files: &SimpleFiles<String, String>, diag: Diagnostic<usize>) {
+fn report(files: &SimpleFiles<String, String>, diag: Diagnostic<usize>, tab_width: usize) {
     use codespan_reporting::term::{Config, emit, termcolor};
 
     let writer = termcolor::StandardStream::stderr(termcolor::ColorChoice::Auto);
-    let config = Config::default();
+    let config = Config {
+        tab_width,
+        ..Config::default()
+    };
     emit(&mut writer.lock(), &config, files, &diag)
         .unwrap_or_else(|e| eprintln!("failed to emit diagnostics: {e}"));
 }
 
-fn parse_with_reporting<'a, T, F>(rule: Rule, input: &'a str, filename: &str, f: F) -> T
+/// Parses `input` with `rule` and runs `f` on the result, reporting any errors to stderr.
+///
+/// Returns `None` if anything was reported.
+fn parse_with_reporting<'a, T, F>(
+    rule: Rule,
+    input: &'a str,
+    filename: &str,
+    tab_width: usize,
+    f: F,
+) -> Option<T>
 where
     F: FnOnce(
         &mut SimpleFiles<String, String>,
@@ -166,105 +447,863 @@ where
     let pairs = match pairs {
         Err(e) => {
             let diag = convert_pest_error(file_id, e);
-            report(&files, diag);
-            std::process::exit(1)
+            report(&files, diag, tab_width);
+            return None;
         }
         Ok(p) => p,
     };
 
     match f(&mut files, file_id, pairs) {
-        Ok(val) => val,
+        Ok(val) => Some(val),
         Err(errs) => {
             for err in errs {
                 let diag = convert_parse_error(file_id, &err);
-                report(&files, diag);
+                report(&files, diag, tab_width);
             }
-            std::process::exit(1)
+            None
+        }
+    }
+}
+
+/// Parses a document read with [`sand::include::expand`], reporting errors to stderr in the
+/// file they are in.
+fn convert_source_to_doc(
+    source: &Source,
+    options: ParseOptions,
+    tab_width: usize,
+) -> Option<Document> {
+    match parse_source(source, options) {
+        Ok(doc) => Some(doc),
+        Err(diags) => {
+            report_in_source(&mut SimpleFiles::new(), source, diags, tab_width);
+            None
         }
     }
 }
 
-fn convert_to_doc_displaying_errs(input: &str, filename: &str) -> Document {
-    parse_with_reporting(Rule::doc, input, filename, |_, _, pairs| pairs.try_into())
+/// Parses and lints a document read with [`sand::include::expand`], reporting the errors or
+/// the lints to stderr. The files of `source` are added to `files`.
+///
+/// Returns the severity of the most serious diagnostic reported, `None` if there were none.
+fn check_source_in(
+    files: &mut SimpleFiles<String, String>,
+    source: &Source,
+    options: ParseOptions,
+    tab_width: usize,
+) -> Option<Severity> {
+    let diags = match parse_source(source, options) {
+        Ok(doc) => sand::lint::check(&doc)
+            .iter()
+            .map(|lint| convert_lint(0, lint))
+            .collect(),
+        Err(diags) => diags,
+    };
+
+    let max = diags.iter().map(|diag| diag.severity).max();
+    report_in_source(files, source, diags, tab_width);
+    max
+}
+
+/// Parses the expanded text of `source`. The diagnostics are for file 0, with offsets into
+/// the expanded text.
+fn parse_source(
+    source: &Source,
+    options: ParseOptions,
+) -> std::result::Result<Document, Vec<Diagnostic<usize>>> {
+    use pest::Parser as _;
+
+    match sand::parser::SandParser::parse(Rule::doc, source.text()) {
+        Err(e) => Err(vec![convert_pest_error(0, e)]),
+        Ok(pairs) => Document::try_from((pairs, options))
+            .map_err(|errs| errs.iter().map(|err| convert_parse_error(0, err)).collect()),
+    }
+}
+
+/// Reports diagnostics from [`parse_source`] in the files they are in, adding the files of
+/// `source` to `files`.
+fn report_in_source(
+    files: &mut SimpleFiles<String, String>,
+    source: &Source,
+    diags: Vec<Diagnostic<usize>>,
+    tab_width: usize,
+) {
+    let file_ids: Vec<usize> = source
+        .files()
+        .iter()
+        .map(|file| files.add(file.path.display().to_string(), file.contents.clone()))
+        .collect();
+
+    for mut diag in diags {
+        // 展開後のオフセットを、それぞれのファイルの中のオフセットに戻す
+        for label in &mut diag.labels {
+            let (index, start) = source.locate(label.range.start);
+            let file = &source.files()[index];
+            let end = label
+                .range
+                .end
+                .saturating_sub(file.offset)
+                .clamp(start, file.contents.len());
+
+            label.file_id = file_ids[index];
+            label.range = start..end;
+        }
+        report(files, diag, tab_width);
+    }
+}
+
+/// Whether `check` fails with the most serious diagnostic at `max_severity`.
+fn fails_check(max_severity: Option<Severity>, deny_warnings: bool) -> bool {
+    let threshold = if deny_warnings {
+        Severity::Note
+    } else {
+        Severity::Error
+    };
+    max_severity.is_some_and(|severity| severity >= threshold)
 }
 
 fn convert_to_sel_displaying_errs(
     input: &str,
     doc: &Document,
     filename: &str,
-) -> sand::formatter::Selector {
-    parse_with_reporting(Rule::Selector, input, filename, |_, _, pairs| {
+    tab_width: usize,
+) -> Option<sand::formatter::Selector> {
+    parse_with_reporting(Rule::Selector, input, filename, tab_width, |_, _, pairs| {
         (doc, pairs).try_into()
     })
 }
 
+/// One error found by [`diagnose`], or a lint found by [`lint_findings`].
+struct Finding {
+    /// `sand/<kind>`, see [`ParseError::id`] and [`sand::lint::Lint::id`].
+    rule_id: String,
+    severity: Severity,
+    message: String,
+    span: Option<Span>,
+}
+
+/// Parses `input` into a document, collecting every error as a [`Finding`].
+fn diagnose(input: &str, options: ParseOptions) -> std::result::Result<Document, Vec<Finding>> {
+    use pest::Parser as _;
+
+    let pairs = sand::parser::SandParser::parse(Rule::doc, input).map_err(|e| {
+        let (start, end) = match e.location {
+            pest::error::InputLocation::Pos(pos) => (pos, pos + 1),
+            pest::error::InputLocation::Span((s, e)) => (s, e),
+        };
+        vec![Finding {
+            rule_id: "sand/syntax".to_string(),
+            severity: Severity::Error,
+            message: sand::parser::pest_error_message(&e.variant),
+            span: Some(Span { start, end }),
+        }]
+    })?;
+
+    Document::try_from((pairs, options)).map_err(|errs| {
+        errs.into_iter()
+            .map(|err| Finding {
+                rule_id: format!("sand/{}", err.id()),
+                severity: Severity::Error,
+                message: err.to_string(),
+                span: err.span().cloned(),
+            })
+            .collect()
+    })
+}
+
+/// The [`sand::lint::check`] lints of `doc` as [`Finding`]s.
+fn lint_findings(doc: &Document) -> Vec<Finding> {
+    sand::lint::check(doc)
+        .into_iter()
+        .map(|lint| Finding {
+            rule_id: format!("sand/{}", lint.id()),
+            severity: lint_severity(&lint),
+            message: lint.to_string(),
+            span: Some(lint.span().clone()),
+        })
+        .collect()
+}
+
+/// Writes the [`Document::translation_table`] of `doc` as CSV (RFC 4180).
+///
+/// Missing sentences are empty fields.
+fn translation_csv(doc: &Document) -> String {
+    fn field(s: &str) -> String {
+        if s.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", s.replace('"', "\"\""))
+        } else {
+            s.to_string()
+        }
+    }
+
+    let mut csv = String::new();
+    let header: Vec<_> = std::iter::once("path")
+        .chain(doc.names().iter().map(String::as_str))
+        .map(field)
+        .collect();
+    csv += &header.join(",");
+    csv += "\r\n";
+
+    for (path, row) in doc.translation_table() {
+        let fields: Vec<_> = std::iter::once(path.join("."))
+            .chain(row.into_iter().map(Option::unwrap_or_default))
+            .map(|f| field(&f))
+            .collect();
+        csv += &fields.join(",");
+        csv += "\r\n";
+    }
+
+    csv
+}
+
+/// Writes the [`Document::translation_table`] of `doc` as a gettext PO file.
+///
+/// The first declared name is the source (`msgid`) and the name at `target` the
+/// translation (`msgstr`), with the alias path as the context. Missing translations are
+/// left untranslated, and sentences without source text are skipped.
+fn translation_po(doc: &Document, target: usize) -> String {
+    fn quote(s: &str) -> String {
+        let escaped = s
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+            .replace('\t', "\\t");
+        format!("\"{escaped}\"")
+    }
+
+    let mut po = String::new();
+    po += "msgid \"\"\nmsgstr \"\"\n";
+    po += "\"Content-Type: text/plain; charset=UTF-8\\n\"\n";
+    po += &format!("\"Language: {}\\n\"\n", doc.names()[target]);
+
+    for (path, row) in doc.translation_table() {
+        let Some(source) = row[0].as_deref().filter(|s| !s.is_empty()) else {
+            continue;
+        };
+        let translation = row[target].as_deref().unwrap_or_default();
+
+        po += "\n";
+        po += &format!("msgctxt {}\n", quote(&path.join(".")));
+        po += &format!("msgid {}\n", quote(source));
+        po += &format!("msgstr {}\n", quote(translation));
+    }
+
+    po
+}
+
+/// A 1-based line and UTF-16 column, see [`sand::parser::line_column`].
+type LineColumn = (usize, usize);
+
+/// The file a [`Finding`] in the expanded text of `source` is in, and its start and end as
+/// 1-based lines and UTF-16 columns within that file. Findings without a span are in the
+/// file passed to [`sand::include::expand`].
+fn locate_finding(
+    source: &Source,
+    finding: &Finding,
+) -> (String, Option<(LineColumn, LineColumn)>) {
+    let Some(span) = &finding.span else {
+        let file = source.files().last().unwrap();
+        return (file.path.display().to_string(), None);
+    };
+
+    let (index, start) = source.locate(span.start);
+    let file = &source.files()[index];
+    let end = span
+        .end
+        .saturating_sub(file.offset)
+        .clamp(start, file.contents.len());
+
+    (
+        file.path.display().to_string(),
+        Some((
+            sand::parser::line_column(&file.contents, start),
+            sand::parser::line_column(&file.contents, end),
+        )),
+    )
+}
+
+/// The counts of `sand stats`, one per line with the values aligned.
+fn stats_text(stats: &sand::parser::Stats) -> String {
+    let mut rows = vec![
+        ("names".to_string(), stats.names),
+        (
+            "sections".to_string(),
+            stats.sections_per_level.iter().sum(),
+        ),
+    ];
+    rows.extend(
+        stats
+            .sections_per_level
+            .iter()
+            .enumerate()
+            .map(|(i, count)| (format!("  level {}", i + 1), *count)),
+    );
+    rows.extend([
+        ("sentences".to_string(), stats.sentences),
+        ("apply-all".to_string(), stats.apply_alls),
+        ("selectors".to_string(), stats.selectors),
+        ("max depth".to_string(), stats.max_depth),
+    ]);
+
+    let width = rows.iter().map(|(label, _)| label.len()).max().unwrap_or(0);
+    rows.iter()
+        .map(|(label, count)| format!("{label:<width$}  {count}\n"))
+        .collect()
+}
+
+/// Builds a SARIF 2.1.0 log of `findings` in the files of `source`.
+fn sarif_report(source: &Source, findings: &[Finding]) -> serde_json::Value {
+    use serde_json::json;
+
+    let mut rule_ids: Vec<&str> = findings.iter().map(|f| f.rule_id.as_str()).collect();
+    rule_ids.sort_unstable();
+    rule_ids.dedup();
+
+    let results: Vec<_> = findings
+        .iter()
+        .map(|finding| {
+            let (path, region) = locate_finding(source, finding);
+            let mut physical_location = json!({ "artifactLocation": { "uri": path } });
+            if let Some(((start_line, start_column), (end_line, end_column))) = region {
+                physical_location["region"] = json!({
+                    "startLine": start_line,
+                    "startColumn": start_column,
+                    "endLine": end_line,
+                    "endColumn": end_column,
+                });
+            }
+
+            json!({
+                "ruleId": finding.rule_id,
+                "level": match finding.severity {
+                    Severity::Bug | Severity::Error => "error",
+                    Severity::Warning => "warning",
+                    Severity::Note | Severity::Help => "note",
+                },
+                "message": { "text": finding.message },
+                "locations": [{ "physicalLocation": physical_location }],
+            })
+        })
+        .collect();
+
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "sand",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rule_ids.iter().map(|id| json!({ "id": id })).collect::<Vec<_>>(),
+                }
+            },
+            "results": results,
+        }]
+    })
+}
+
+/// Writes `findings` as GitHub Actions workflow commands, e.g.
+/// `::error file=doc.sand,line=3,col=1::message`, one per line.
+fn github_annotations(source: &Source, findings: &[Finding]) -> String {
+    // ワークフローコマンドの値は%と改行を、プロパティは更に:と,をエスケープする
+    let escape_data = |s: &str| {
+        s.replace('%', "%25")
+            .replace('\r', "%0D")
+            .replace('\n', "%0A")
+    };
+    let escape_property = |s: &str| escape_data(s).replace(':', "%3A").replace(',', "%2C");
+
+    let mut out = String::new();
+    for finding in findings {
+        let command = match finding.severity {
+            Severity::Bug | Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note | Severity::Help => "notice",
+        };
+        let (path, region) = locate_finding(source, finding);
+        let mut properties = format!("file={}", escape_property(&path));
+        if let Some(((line, col), _)) = region {
+            properties += &format!(",line={line},col={col}");
+        }
+        out += &format!(
+            "::{command} {properties}::{}\n",
+            escape_data(&finding.message)
+        );
+    }
+    out
+}
+
 fn print_completions<G: clap_complete::Generator>(g: G) {
     let mut cmd = Args::command();
     let name = cmd.get_name().to_string();
     clap_complete::generate(g, &mut cmd, name, &mut std::io::stdout());
 }
 
+/// The `*.sand` files in `dir` and its subdirectories, sorted.
+async fn find_sand_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut found = vec![];
+    let mut dirs = vec![dir.to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if entry.file_type().await?.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "sand") {
+                found.push(path);
+            }
+        }
+    }
+
+    found.sort();
+    Ok(found)
+}
+
+/// Renders and prints the `out` command for `source`.
+///
+/// Returns the exit status to use if errors were reported: 2 for empty output with
+/// `--error-on-empty`, 1 otherwise.
+fn print_out(source: &Source, args: &OutArgs, tab_width: usize) -> std::result::Result<(), i32> {
+    let mut doc = convert_source_to_doc(source, ParseOptions::default(), tab_width).ok_or(1)?;
+    if let Some((key, value)) = &args.filter {
+        doc.retain_sections_where(key, value);
+    }
+    let sel = convert_to_sel_displaying_errs(&args.selector, &doc, "<user>", tab_width).ok_or(1)?;
+
+    if doc.is_empty() {
+        let diagnostic = if args.error_on_empty {
+            Diagnostic::error()
+        } else {
+            Diagnostic::warning()
+        };
+        report(
+            &SimpleFiles::new(),
+            diagnostic.with_message("the document declares names but has no content"),
+            tab_width,
+        );
+        return if args.error_on_empty { Err(2) } else { Ok(()) };
+    }
+
+    if args.explain {
+        println!("{}", sand::formatter::explain(&doc, &sel));
+    }
+
+    let mut rendered = sand::formatter::render_labeled(
+        &doc,
+        &sel,
+        &sand::formatter::RenderOptions {
+            markdown: args.markdown.then(|| sand::formatter::MarkdownOptions {
+                escape_content: args.escape_markdown,
+                ..Default::default()
+            }),
+            whitespace: if args.raw {
+                sand::formatter::WhitespacePolicy::Raw
+            } else {
+                sand::formatter::WhitespacePolicy::CollapseAll
+            },
+            number_sections: args.number_sections,
+            max_depth: args.max_depth,
+            all_names: args.all_names,
+            only_other_names: args.only_other_names,
+        },
+    );
+
+    if let Some(name) = &args.name {
+        if doc.name_index(name).is_none() {
+            report(
+                &SimpleFiles::new(),
+                Diagnostic::error()
+                    .with_message(format!("name `{name}` is not declared"))
+                    .with_notes(vec![format!("declared names: {}", doc.names().join(", "))]),
+                tab_width,
+            );
+            return Err(1);
+        }
+
+        let Some(index) = rendered.iter().position(|(t, _)| t == name) else {
+            let targets: Vec<_> = rendered.iter().map(|(t, _)| t.as_str()).collect();
+            report(
+                &SimpleFiles::new(),
+                Diagnostic::error().with_message(format!(
+                    "the selector already selects `{}`, not `{name}`",
+                    targets.join(", ")
+                )),
+                tab_width,
+            );
+            return Err(1);
+        };
+        rendered = vec![rendered.swap_remove(index)];
+    }
+
+    if args.error_on_empty && rendered.iter().all(|(_, r)| r.trim().is_empty()) {
+        report(
+            &SimpleFiles::new(),
+            Diagnostic::error()
+                .with_message(format!("the selector `{}` renders nothing", args.selector)),
+            tab_width,
+        );
+        return Err(2);
+    }
+
+    if args.pretty {
+        for (_, r) in &mut rendered {
+            *r = prettify_markdown(r);
+        }
+    }
+
+    if args.front_matter {
+        for (name, r) in &mut rendered {
+            *r = format!(
+                "---\nname: {name}\nnames: [{}]\n---\n{r}",
+                doc.names().join(", ")
+            );
+        }
+    }
+
+    if let Some(template) = &args.template {
+        for (name, content) in rendered {
+            print!(
+                "{}",
+                template
+                    .replace("{name}", &name)
+                    .replace("{content}", &content)
+            );
+        }
+    } else if let [(_, content)] = rendered.as_slice() {
+        println!("{content}");
+    } else {
+        let width = terminal_size::terminal_size()
+            .map(|(w, _h)| match w {
+                terminal_size::Width(w) => w as usize,
+            })
+            .unwrap_or(80);
+
+        for (name, content) in rendered {
+            use colored::Colorize;
+
+            let (name, bar) = name_header(&name, width);
+
+            let bar = if args.pretty {
+                bar.dimmed()
+            } else {
+                bar.normal()
+            };
+
+            println!("{} {bar}", name.bold().underline().red());
+            println!();
+            println!("{content}");
+            println!();
+        }
+    }
+
+    Ok(())
+}
+
+/// The fewest `─` in a [`name_header`].
+const MIN_HEADER_RULE: usize = 3;
+
+/// Splits the header line written above each name's output into the name and the rule that
+/// fills the rest of `width` columns, with a space between them.
+///
+/// Names too long for the width are cut off with `…`, so the header stays on one line, and the
+/// rule always has at least a few dashes.
+fn name_header(name: &str, width: usize) -> (String, String) {
+    let name = sand::formatter::preview(name, width.saturating_sub(MIN_HEADER_RULE + 1).max(1));
+    let rule = "─".repeat(
+        width
+            .saturating_sub(name.chars().count() + 1)
+            .max(MIN_HEADER_RULE),
+    );
+    (name, rule)
+}
+
+/// Styles Markdown produced by [`sand::formatter::render_plain`] for a terminal.
+///
+/// Headers are made bold and thematic breaks are dimmed; everything else is left as is.
+/// Honors `colored`'s override, so with colors disabled the input is returned unchanged.
+fn prettify_markdown(markdown: &str) -> String {
+    use colored::Colorize;
+
+    markdown
+        .lines()
+        .map(|line| {
+            if line.starts_with('#') && line.trim_start_matches('#').starts_with(' ') {
+                line.bold().to_string()
+            } else if line.len() >= 3 && line.chars().all(|c| c == '-') {
+                line.dimmed().to_string()
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Re-runs [`print_out`] every time the input file or a file it includes is written.
+async fn watch_out(args: &OutArgs, tab_width: usize) -> Result<()> {
+    use notify::{RecursiveMode, Watcher};
+    use std::time::Duration;
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if let Ok(event) = event
+            && !event.kind.is_access()
+        {
+            let _ = tx.send(event);
+        }
+    })?;
+
+    // 入力と、そこから読み込んだファイル
+    let mut files = FxHashSet::default();
+    let mut dirs = FxHashSet::default();
+    files.insert(std::path::absolute(&args.input)?);
+
+    loop {
+        let source = sand::include::expand(&args.input);
+        if let Ok(source) = &source {
+            for file in source.files() {
+                files.insert(std::path::absolute(&file.path)?);
+            }
+        }
+
+        // 描画中の変更も拾えるように、描画より先に監視を始める
+        // エディタは別のファイルに書いてから置き換えることがあるので、ディレクトリごと監視する
+        for file in &files {
+            let dir = file.parent().unwrap_or(Path::new("/"));
+            if dirs.insert(dir.to_path_buf()) {
+                watcher.watch(dir, RecursiveMode::NonRecursive)?;
+            }
+        }
+
+        // 画面をクリアしてから描画し直す
+        print!("\x1b[2J\x1b[H");
+        match source {
+            Ok(source) => {
+                let _ = print_out(&source, args, tab_width);
+            }
+            Err(e) => eprintln!("{e}"),
+        }
+
+        loop {
+            let Some(event) = rx.recv().await else {
+                return Ok(());
+            };
+            if event.paths.iter().any(|path| files.contains(path)) {
+                break;
+            }
+        }
+
+        // 一回の保存で続けて届くイベントをまとめる
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        while rx.try_recv().is_ok() {}
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
+    let tab_width = args.tab_width;
 
     match args.command {
-        Command::Parse { input } => {
-            let mut file = File::open(&input).await?;
+        Command::Parse { input, quiet } => {
+            let source = sand::include::expand(&input)?;
 
-            let mut contents = String::new();
-            file.read_to_string(&mut contents).await?;
+            let Some(doc) = convert_source_to_doc(&source, ParseOptions::default(), tab_width)
+            else {
+                std::process::exit(1)
+            };
+            if !quiet {
+                println!("{doc:?}");
+            }
+        }
+        Command::Check {
+            input,
+            format,
+            strict,
+            deny_warnings,
+        } => {
+            let options = ParseOptions {
+                strict,
+                ..Default::default()
+            };
 
-            let filename = input.display().to_string();
-            let doc = convert_to_doc_displaying_errs(&contents, &filename);
-            println!("{doc:?}");
+            let source = sand::include::expand(&input)?;
+            let max_severity = match format {
+                CheckFormat::Human => {
+                    check_source_in(&mut SimpleFiles::new(), &source, options, tab_width)
+                }
+                CheckFormat::Sarif | CheckFormat::Github => {
+                    let findings = match diagnose(source.text(), options) {
+                        Ok(doc) => lint_findings(&doc),
+                        Err(findings) => findings,
+                    };
+                    if let CheckFormat::Sarif = format {
+                        println!(
+                            "{}",
+                            serde_json::to_string_pretty(&sarif_report(&source, &findings))?
+                        );
+                    } else {
+                        print!("{}", github_annotations(&source, &findings));
+                    }
+                    findings.iter().map(|finding| finding.severity).max()
+                }
+            };
+            if fails_check(max_severity, deny_warnings) {
+                std::process::exit(1)
+            }
+        }
+        Command::CheckAll {
+            dir,
+            strict,
+            deny_warnings,
+        } => {
+            let options = ParseOptions {
+                strict,
+                ..Default::default()
+            };
+            let paths = find_sand_files(&dir).await?;
+            let mut files = SimpleFiles::new();
+            let mut failed = 0;
+
+            for path in &paths {
+                let ok = match sand::include::expand(path) {
+                    Ok(source) => {
+                        let max_severity =
+                            check_source_in(&mut files, &source, options.clone(), tab_width);
+                        !fails_check(max_severity, deny_warnings)
+                    }
+                    Err(e) => {
+                        report(
+                            &files,
+                            Diagnostic::error().with_message(e.to_string()),
+                            tab_width,
+                        );
+                        false
+                    }
+                };
+                if ok {
+                    println!("{}: ok", path.display());
+                } else {
+                    failed += 1;
+                    println!("{}: failed", path.display());
+                }
+            }
+
+            println!("{} files checked, {failed} with errors", paths.len());
+            if failed > 0 {
+                std::process::exit(1)
+            }
+        }
+        Command::Tree { input, depth } => {
+            let source = sand::include::expand(&input)?;
+
+            let Some(doc) = convert_source_to_doc(&source, ParseOptions::default(), tab_width)
+            else {
+                std::process::exit(1)
+            };
+            print!("{}", sand::formatter::render_tree(&doc, depth));
+        }
+        Command::Stats { input, json } => {
+            let source = sand::include::expand(&input)?;
+
+            let Some(doc) = convert_source_to_doc(&source, ParseOptions::default(), tab_width)
+            else {
+                std::process::exit(1)
+            };
+            let stats = doc.stats();
+            if json {
+                println!("{}", serde_json::to_string_pretty(&stats)?);
+            } else {
+                print!("{}", stats_text(&stats));
+            }
+        }
+        Command::Normalize { input } => {
+            let source = sand::include::expand(&input)?;
+
+            let Some(doc) = convert_source_to_doc(&source, ParseOptions::default(), tab_width)
+            else {
+                std::process::exit(1)
+            };
+            // 入力のファイルの文や見出しの中だけを書き換え、読み込んだファイルは出さない
+            let file = source.files().last().unwrap();
+            print!("{}", doc.normalize_escapes_in(&file.contents, file.offset));
+        }
+        Command::Diff { old, new } => {
+            let old_source = sand::include::expand(&old)?;
+            let new_source = sand::include::expand(&new)?;
+
+            let (Some(old_doc), Some(new_doc)) = (
+                convert_source_to_doc(&old_source, ParseOptions::default(), tab_width),
+                convert_source_to_doc(&new_source, ParseOptions::default(), tab_width),
+            ) else {
+                std::process::exit(1)
+            };
+
+            for change in sand::formatter::diff(&old_doc, &new_doc) {
+                println!("{change}");
+            }
+        }
+        Command::Export {
+            input,
+            format,
+            name,
+        } => {
+            let source = sand::include::expand(&input)?;
+
+            let Some(doc) = convert_source_to_doc(&source, ParseOptions::default(), tab_width)
+            else {
+                std::process::exit(1)
+            };
+            match format {
+                ExportFormat::Csv => print!("{}", translation_csv(&doc)),
+                ExportFormat::Po => {
+                    let target = match &name {
+                        Some(name) => doc.name_index(name),
+                        None => (doc.names().len() > 1).then_some(1),
+                    };
+                    let Some(target) = target else {
+                        report(
+                            &SimpleFiles::new(),
+                            Diagnostic::error()
+                                .with_message("no name to translate into")
+                                .with_notes(vec![format!(
+                                    "declared names: {}",
+                                    doc.names().join(", ")
+                                )]),
+                            tab_width,
+                        );
+                        std::process::exit(1)
+                    };
+                    print!("{}", translation_po(&doc, target));
+                }
+            }
         }
         Command::Lsp => {
-            use sand::lsp::SandServer;
-            use tower_lsp::{LspService, Server};
+            use tower_lsp::Server;
 
             let stdin = tokio::io::stdin();
             let stdout = tokio::io::stdout();
 
-            let (service, socket) = LspService::new(SandServer::new);
+            let (service, socket) = sand::lsp::service();
             Server::new(stdin, stdout, socket).serve(service).await;
         }
         Command::Completions { shell } => {
             print_completions(shell);
         }
-        Command::Out {
-            selector,
-            markdown,
-            input,
-        } => {
-            let mut file = File::open(&input).await?;
-
-            let mut contents = String::new();
-            file.read_to_string(&mut contents).await?;
-
-            let filename = input.display().to_string();
-            let doc = convert_to_doc_displaying_errs(&contents, &filename);
-            let sel = convert_to_sel_displaying_errs(&selector, &doc, "<user>");
+        Command::Out(args) => {
+            match args.color {
+                clap::ColorChoice::Always => colored::control::set_override(true),
+                clap::ColorChoice::Never => colored::control::set_override(false),
+                clap::ColorChoice::Auto => {}
+            }
 
-            let rendered = sand::formatter::render_plain(&doc, &sel, markdown);
-            if rendered.len() == 1 {
-                println!("{}", rendered[0]);
+            if args.watch {
+                watch_out(&args, tab_width).await?;
             } else {
-                let width = terminal_size::terminal_size()
-                    .map(|(w, _h)| match w {
-                        terminal_size::Width(w) => w as usize,
-                    })
-                    .unwrap_or(80);
-
-                for (content, name) in rendered.into_iter().zip(doc.names.iter()) {
-                    use colored::Colorize;
-
-                    let bar = "─".repeat(width.saturating_sub(name.len() + 1));
+                let source = sand::include::expand(&args.input)?;
 
-                    println!("{} {bar}", name.bold().underline().red());
-                    println!();
-                    println!("{content}");
-                    println!();
+                if let Err(code) = print_out(&source, &args, tab_width) {
+                    std::process::exit(code)
                 }
             }
         }
@@ -8,7 +8,12 @@ pub struct SandParser;
 
 #[derive(Debug)]
 pub struct Document {
-    pub names: Vec<String>,
+    /// See [`Document::names`]. Private so that `name_indices` is always built from it.
+    names: Vec<String>,
+    /// Where each of `names` is written in the `#(...)` declaration.
+    pub name_spans: Vec<Span>,
+    /// The index of each of `names`, see [`Document::name_index`].
+    name_indices: FxHashMap<String, usize>,
     pub ast: AST,
 }
 
@@ -30,8 +35,9 @@ impl From<pest::Span<'_>> for Span {
 use thiserror::Error;
 #[derive(Error, Debug, Hash, PartialEq, Eq)]
 pub enum ParseError {
+    /// The first and the repeated definition.
     #[error("names are defined more than once")]
-    MultipleNameDefine(Span),
+    MultipleNameDefine(Span, Span),
     #[error("the same names are defined more than once: {0}")]
     DuplicateNames(String, Span),
     #[error("aliases are duplicated: {0}")]
@@ -40,10 +46,33 @@ pub enum ParseError {
     AliasConflictWithNames(String, Span),
     #[error("names are not defined")]
     MissingNames,
+    /// `#()` declares no names. Allowed with [`ParseOptions::allow_empty_names`].
+    #[error("the name declaration is empty")]
+    EmptyNames(Span),
+    /// More names are declared than [`ParseOptions::max_names`], which is given first.
+    #[error("more than {0} names are declared")]
+    TooManyNames(usize, Span),
     #[error("selector is incorrect: {0}")]
     Selector(SelectorError, Span),
     #[error("the number of sentences does not match the number of names.")]
     NumberOfSentences(Span),
+    #[error("the sentence label is not a declared name: {0}")]
+    UnknownLabel(String, Span),
+    #[error("the sentence label is used more than once: {0}")]
+    DuplicateLabel(String, Span),
+    #[error("no sentence is labeled with the name: {0}")]
+    MissingLabel(String, Span),
+    /// A section more than one level deeper than its parent (level 0 is the top level).
+    /// Only reported in [`ParseOptions::strict`] mode; otherwise it is a child of the parent.
+    #[error("the section skips from level {from} to level {to}")]
+    SkippedSectionLevel { from: usize, to: usize, span: Span },
+    /// Text that is not part of any statement and would be dropped.
+    /// Free text is only reported in [`ParseOptions::strict`] mode.
+    #[error("the text is not a statement and is ignored")]
+    UnknownStatement(Span),
+    /// The same key is given more than once in the `{key=value, ...}` of a section.
+    #[error("the section attribute is given more than once: {0}")]
+    DuplicateAttribute(String, Span),
 }
 
 #[derive(Error, Debug, Hash, PartialEq, Eq)]
@@ -56,6 +85,196 @@ pub enum SelectorError {
     Neither(String),
     #[error("expected to be global selector , but found a local selector")]
     Local,
+    #[error("local selector is not inside a section")]
+    LocalAtTopLevel,
+    #[error("no section matches the content: {0}")]
+    NoMatchingSection(String),
+    #[error("more than one section matches the content: {0}")]
+    AmbiguousSelector(String),
+    #[error("the name in the name list is not declared: {0}")]
+    UnknownName(String),
+    /// The path goes on past a sentence or apply-all block, which has no children.
+    /// Holds the segment that can't be followed and the span of the block.
+    #[error("cannot descend into a sentence or apply-all block: {0}")]
+    NotASection(String, Span),
+    /// A `.` that expands to every name is followed by more segments, e.g. `#.sec1..en`.
+    #[error("the dot that expands to every name must be at the end of the selector")]
+    DotInsidePath,
+    /// The text is not a selector, or has more text after one.
+    #[error("not a selector: {0}")]
+    Syntax(String),
+}
+
+/// A syntax error from [`parse_fragment`].
+#[derive(Error, Debug, Clone, Hash, PartialEq, Eq)]
+#[error("{message}")]
+pub struct FragmentError {
+    pub message: String,
+    /// Where in the fragment the error is.
+    pub span: Span,
+}
+
+impl From<pest::error::Error<Rule>> for FragmentError {
+    fn from(e: pest::error::Error<Rule>) -> Self {
+        Self {
+            message: e.variant.message().to_string(),
+            span: match e.location {
+                pest::error::InputLocation::Pos(pos) => Span {
+                    start: pos,
+                    end: pos,
+                },
+                pest::error::InputLocation::Span((start, end)) => Span { start, end },
+            },
+        }
+    }
+}
+
+/// Why [`reparse_range`] couldn't build a document from the edited text.
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ReparseError {
+    /// The edited text doesn't match the grammar.
+    #[error("syntax error: {0}")]
+    Syntax(FragmentError),
+    /// The edited text parses, but isn't a valid document.
+    #[error("the document is invalid: {} error(s)", .0.len())]
+    Invalid(Vec<ParseError>),
+}
+
+/// Parses `input` as `rule` on its own instead of as a whole document, e.g. a selector typed
+/// by a user or a single section in an editor snippet.
+///
+/// The whole of `input` has to match, text after the fragment is an error.
+///
+/// ```
+/// use sand::parser::{Rule, parse_fragment};
+///
+/// let pair = parse_fragment(Rule::Selector, "#.intro.en").unwrap().next().unwrap();
+/// assert_eq!(pair.as_rule(), Rule::Selector);
+/// assert!(parse_fragment(Rule::Selector, "#.intro en").is_err());
+/// ```
+pub fn parse_fragment(rule: Rule, input: &str) -> Result<Pairs<'_, Rule>, FragmentError> {
+    use pest::Parser as _;
+
+    let pairs = SandParser::parse(rule, input).map_err(FragmentError::from)?;
+
+    let end = pairs
+        .clone()
+        .next_back()
+        .map_or(0, |pair| pair.as_span().end());
+    if end < input.len() {
+        return Err(FragmentError {
+            message: format!("unexpected text: {}", &input[end..]),
+            span: Span {
+                start: end,
+                end: input.len(),
+            },
+        });
+    }
+
+    Ok(pairs)
+}
+
+impl ParseError {
+    /// A stable kebab-case identifier of the kind of error, e.g. `duplicate-alias`.
+    pub fn id(&self) -> &'static str {
+        match self {
+            ParseError::MultipleNameDefine(..) => "multiple-name-define",
+            ParseError::DuplicateNames(..) => "duplicate-names",
+            ParseError::DuplicateAlias(..) => "duplicate-alias",
+            ParseError::AliasConflictWithNames(..) => "alias-conflict-with-names",
+            ParseError::MissingNames => "missing-names",
+            ParseError::EmptyNames(..) => "empty-names",
+            ParseError::TooManyNames(..) => "too-many-names",
+            ParseError::Selector(..) => "selector",
+            ParseError::NumberOfSentences(..) => "number-of-sentences",
+            ParseError::UnknownLabel(..) => "unknown-label",
+            ParseError::DuplicateLabel(..) => "duplicate-label",
+            ParseError::MissingLabel(..) => "missing-label",
+            ParseError::SkippedSectionLevel { .. } => "skipped-section-level",
+            ParseError::UnknownStatement(..) => "unknown-statement",
+            ParseError::DuplicateAttribute(..) => "duplicate-attribute",
+        }
+    }
+
+    /// The part of the source the error is about. `None` for [`ParseError::MissingNames`].
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            ParseError::MultipleNameDefine(_, span)
+            | ParseError::DuplicateNames(_, span)
+            | ParseError::DuplicateAlias(_, span)
+            | ParseError::AliasConflictWithNames(_, span)
+            | ParseError::Selector(_, span)
+            | ParseError::NumberOfSentences(span)
+            | ParseError::UnknownLabel(_, span)
+            | ParseError::DuplicateLabel(_, span)
+            | ParseError::MissingLabel(_, span)
+            | ParseError::UnknownStatement(span)
+            | ParseError::DuplicateAttribute(_, span)
+            | ParseError::EmptyNames(span)
+            | ParseError::TooManyNames(_, span)
+            | ParseError::SkippedSectionLevel { span, .. } => Some(span),
+            ParseError::MissingNames => None,
+        }
+    }
+
+    /// Renders the error as `filename:line:col: message`, followed by the line the error starts
+    /// on and a caret under the span. Without a span, only `filename: message` is rendered.
+    pub fn render(&self, source: &str, filename: &str) -> String {
+        let Some(span) = self.span() else {
+            return format!("{filename}: {self}\n");
+        };
+
+        let (line, column) = line_column(source, span.start);
+        let start = span.start.min(source.len());
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..]
+            .find('\n')
+            .map_or(source.len(), |i| start + i);
+        let text = source[line_start..line_end].trim_end_matches('\r');
+
+        let indent = source[line_start..start].chars().count();
+        let end = span.end.clamp(start, line_start + text.len());
+        let width = source[start..end].chars().count().max(1);
+
+        format!(
+            "{filename}:{line}:{column}: {self}\n{text}\n{}{}\n",
+            " ".repeat(indent),
+            "^".repeat(width)
+        )
+    }
+}
+
+/// The message of a syntax error from the grammar, with the rules that were expected and the
+/// ones that were not, e.g. `failed to parse input, expected: [Sen]`.
+pub fn pest_error_message(variant: &pest::error::ErrorVariant<Rule>) -> String {
+    use pest::error::ErrorVariant;
+
+    match variant {
+        ErrorVariant::ParsingError {
+            positives,
+            negatives,
+        } => {
+            let mut msg = String::from("failed to parse input");
+            if !positives.is_empty() {
+                msg += &format!(", expected: {positives:?}");
+            }
+            if !negatives.is_empty() {
+                msg += &format!(", not: {negatives:?}");
+            }
+            msg
+        }
+        ErrorVariant::CustomError { message } => message.clone(),
+    }
+}
+
+/// The 1-based line and column of a byte offset in `source`. Columns count UTF-16 code units.
+pub fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let offset = offset.min(source.len());
+    let before = source.get(..offset).unwrap_or(source);
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let line = before.matches('\n').count() + 1;
+    let column = before[line_start..].encode_utf16().count() + 1;
+    (line, column)
 }
 
 pub fn validate_non_local_selector(doc: &Document, sel: &AST) -> Vec<ParseError> {
@@ -65,6 +284,9 @@ pub fn validate_non_local_selector(doc: &Document, sel: &AST) -> Vec<ParseError>
         local,
         path,
         trailing_dot,
+        misplaced_dots,
+        names,
+        ..
     } = &sel.node
     {
         if *local {
@@ -72,8 +294,24 @@ pub fn validate_non_local_selector(doc: &Document, sel: &AST) -> Vec<ParseError>
             return v;
         }
 
-        let range = if !trailing_dot && !path.is_empty() {
-            if !doc.names.contains(path.last().unwrap()) {
+        for span in misplaced_dots {
+            v.push(ParseError::Selector(
+                SelectorError::DotInsidePath,
+                span.clone(),
+            ));
+        }
+
+        for name in names.iter().flatten() {
+            if doc.name_index(name).is_none() {
+                v.push(ParseError::Selector(
+                    SelectorError::UnknownName(name.clone()),
+                    sel.get_span(),
+                ));
+            }
+        }
+
+        let range = if !trailing_dot && names.is_none() && !path.is_empty() {
+            if !last_is_name(&doc.name_indices, path) {
                 v.push(ParseError::Selector(
                     SelectorError::LastIsNotDotOrName,
                     sel.get_span(),
@@ -84,50 +322,55 @@ pub fn validate_non_local_selector(doc: &Document, sel: &AST) -> Vec<ParseError>
             0..(path.len())
         };
 
-        let mut curr = &doc.ast;
-
-        for k in &path[range] {
-            if matches!(curr.node, NodeKind::Sen { .. })
-                || matches!(curr.node, NodeKind::All { .. })
-            {
-                break;
-            }
-            let (alias, children) = curr.take_section_like().unwrap();
+        if let Err(e) = doc.ast.resolve_path(&path[range]) {
+            v.push(ParseError::Selector(e, sel.get_span()));
+        }
+    }
+    v
+}
 
-            if let Some(index) = alias.get(k) {
-                curr = &children[*index];
-            } else if let Ok(index) = k.parse::<usize>() {
-                let children_without_sel: Vec<&AST> = children
-                    .iter()
-                    .filter(|p| !matches!(&p.node, NodeKind::Selector { .. }))
-                    .collect();
+/// The default of [`ParseOptions::max_names`].
+pub const DEFAULT_MAX_NAMES: usize = 1024;
+
+/// Options for building a [`Document`], see `TryFrom<(Pairs, ParseOptions)>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Report free text between statements as [`ParseError::UnknownStatement`] instead of
+    /// ignoring it, and sections that skip a level as [`ParseError::SkippedSectionLevel`].
+    pub strict: bool,
+    /// Accept `#()` instead of reporting [`ParseError::EmptyNames`], e.g. for templates whose
+    /// names are filled in later. Such a document renders nothing.
+    pub allow_empty_names: bool,
+    /// Declaring more names than this is [`ParseError::TooManyNames`]. Everything is rendered
+    /// once for each name, so this bounds the work on untrusted input.
+    pub max_names: usize,
+}
 
-                if index >= children_without_sel.len() {
-                    v.push(ParseError::Selector(
-                        SelectorError::OutOfIndex,
-                        sel.get_span(),
-                    ));
-                    break;
-                } else {
-                    curr = children_without_sel[index];
-                }
-            } else {
-                v.push(ParseError::Selector(
-                    SelectorError::Neither(k.clone()),
-                    sel.get_span(),
-                ));
-                break;
-            }
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            strict: false,
+            allow_empty_names: false,
+            max_names: DEFAULT_MAX_NAMES,
         }
     }
-    v
 }
 
-// TODO: validateでエラーをまとめて出す
 impl TryFrom<Pairs<'_, Rule>> for Document {
     type Error = Vec<ParseError>;
 
-    fn try_from(mut pairs: Pairs<'_, Rule>) -> Result<Self, Vec<ParseError>> {
+    fn try_from(pairs: Pairs<'_, Rule>) -> Result<Self, Vec<ParseError>> {
+        (pairs, ParseOptions::default()).try_into()
+    }
+}
+
+// TODO: validateでエラーをまとめて出す
+impl TryFrom<(Pairs<'_, Rule>, ParseOptions)> for Document {
+    type Error = Vec<ParseError>;
+
+    fn try_from(
+        (mut pairs, options): (Pairs<'_, Rule>, ParseOptions),
+    ) -> Result<Self, Vec<ParseError>> {
         let mut ast = vec![AST {
             node: NodeKind::Top {
                 aliases: FxHashMap::default(),
@@ -139,8 +382,11 @@ impl TryFrom<Pairs<'_, Rule>> for Document {
             },
         }];
         let mut names: Option<(Span, Vec<String>)> = None;
+        let mut name_spans = vec![];
 
-        let root = pairs.next().unwrap();
+        let Some(root) = pairs.next() else {
+            return Err(vec![ParseError::MissingNames]);
+        };
 
         let mut errs = FxHashSet::default();
 
@@ -152,16 +398,24 @@ impl TryFrom<Pairs<'_, Rule>> for Document {
             match pair.as_rule() {
                 Rule::PartName => {
                     if let Some((prev_span, _)) = names {
-                        errs.insert(ParseError::MultipleNameDefine(prev_span.clone()));
-                        errs.insert(ParseError::MultipleNameDefine(span.clone()));
+                        errs.insert(ParseError::MultipleNameDefine(prev_span, span.clone()));
                     }
-                    let ident_list_pair = pair.into_inner().next().unwrap();
-
-                    let raw_names: Vec<String> = ident_list_pair
+                    let idents: Vec<_> = pair
                         .into_inner()
+                        .next()
+                        .into_iter()
+                        .flat_map(|ident_list| ident_list.into_inner())
                         .filter(|p| p.as_rule() == Rule::Ident)
-                        .map(|p| p.as_str().to_string())
                         .collect();
+                    if idents.is_empty() && !options.allow_empty_names {
+                        errs.insert(ParseError::EmptyNames(span.clone()));
+                    }
+                    if idents.len() > options.max_names {
+                        errs.insert(ParseError::TooManyNames(options.max_names, span.clone()));
+                    }
+                    let raw_names: Vec<String> =
+                        idents.iter().map(|p| p.as_str().to_string()).collect();
+                    name_spans = idents.iter().map(|p| p.as_span().into()).collect();
 
                     let mut seen = FxHashSet::default();
                     for name in &raw_names {
@@ -180,7 +434,22 @@ impl TryFrom<Pairs<'_, Rule>> for Document {
                     let hashes = inner.next().unwrap().as_str();
                     let level = hashes.chars().count();
 
-                    let content = inner.next().unwrap().as_str().to_string();
+                    let mut attributes = Attributes::new();
+                    if let Some(p) = inner.peek().filter(|p| p.as_rule() == Rule::Attributes) {
+                        inner.next();
+                        for attribute in p.into_inner() {
+                            let attribute_span: Span = attribute.as_span().into();
+                            let mut kv = attribute.into_inner();
+                            let key = kv.next().unwrap().as_str().to_string();
+                            let value = kv.next().unwrap().as_str().to_string();
+
+                            if attributes.insert(key.clone(), value).is_some() {
+                                errs.insert(ParseError::DuplicateAttribute(key, attribute_span));
+                            }
+                        }
+                    }
+
+                    let content = join_continuations(inner.next().unwrap().as_str());
 
                     let mut top_level =
                         { (ast.last_mut().unwrap()).take_mut_section_like().unwrap().0 };
@@ -207,11 +476,21 @@ impl TryFrom<Pairs<'_, Rule>> for Document {
                         }
                     }
 
+                    // 間のレベルを飛ばしても子として扱う。strictならエラーにする
+                    if options.strict && level > top_level + 1 {
+                        errs.insert(ParseError::SkippedSectionLevel {
+                            from: top_level,
+                            to: level,
+                            span: span.clone(),
+                        });
+                    }
+
                     ast.push(AST {
                         meta: NodeMeta { span, alias },
                         node: NodeKind::Section {
                             level,
                             content,
+                            attributes,
                             aliases: FxHashMap::default(),
                             children: vec![],
                         },
@@ -223,7 +502,7 @@ impl TryFrom<Pairs<'_, Rule>> for Document {
                     let alias = take_alias(&mut inner);
                     let p = inner.next().unwrap();
                     let elements = match p.as_rule() {
-                        Rule::string => (None, p.as_str().into()),
+                        Rule::string => (None, normalize_line_endings(p.as_str())),
                         Rule::Idents => (
                             Some(
                                 p.into_inner()
@@ -234,9 +513,9 @@ impl TryFrom<Pairs<'_, Rule>> for Document {
                                     .map(|p| p.as_str().to_string())
                                     .collect(),
                             ),
-                            inner.next().unwrap().as_str().into(),
+                            normalize_line_endings(inner.next().unwrap().as_str()),
                         ),
-                        Rule::All => (None, inner.next().unwrap().as_str().into()),
+                        Rule::All => (None, normalize_line_endings(inner.next().unwrap().as_str())),
                         _ => (None, String::new()),
                     };
 
@@ -256,23 +535,49 @@ impl TryFrom<Pairs<'_, Rule>> for Document {
 
                     let alias = take_alias(&mut inner);
 
-                    let sentences: Vec<_> = inner
+                    let blocks: Vec<_> = inner
                         .filter(|p| p.as_rule() == Rule::Sen)
-                        .map(|p| p.into_inner().next().unwrap().as_str().to_string())
+                        .map(SenBlock::from)
                         .collect();
 
+                    let declared = names.as_ref().map_or(&[][..], |(_, n)| n.as_slice());
+                    // 全てのブロックにラベルがあり、宣言された名前が一つでもあればラベルで対応付ける
+                    // `[en: ...]`のような文を誤ってラベルとして読まないように
+                    let labeled = blocks.iter().all(|b| b.label.is_some())
+                        && blocks
+                            .iter()
+                            .any(|b| b.label.as_ref().is_some_and(|l| declared.contains(l)));
+
+                    let node = if labeled {
+                        let sentences = resolve_labels(declared, blocks, &span, &mut errs);
+                        NodeKind::Sen(sentences, Some(declared.to_vec()))
+                    } else {
+                        NodeKind::Sen(blocks.into_iter().map(|b| b.raw).collect(), None)
+                    };
+
                     to_push_at_last = Some(AST {
                         meta: NodeMeta {
                             span: span.clone(),
                             alias: alias.clone(),
                         },
-                        node: NodeKind::Sen(sentences),
+                        node,
                     });
                 }
                 Rule::Selector => {
                     to_push_at_last = Some(parse_selector(span, pair));
                 }
-                _ => (),
+                Rule::non_escaped_string => {
+                    let text = pair.as_str();
+                    if options.strict && !text.trim().is_empty() {
+                        let start = span.start + (text.len() - text.trim_start().len());
+                        let end = span.start + text.trim_end().len();
+                        errs.insert(ParseError::UnknownStatement(Span { start, end }));
+                    }
+                }
+                Rule::EOI => (),
+                _ => {
+                    errs.insert(ParseError::UnknownStatement(span));
+                }
             }
 
             if let Some(to_add) = to_push_at_last
@@ -302,134 +607,444 @@ impl TryFrom<Pairs<'_, Rule>> for Document {
         }
 
         if let Some((_, names)) = &names {
-            // TODO: DRY, foldみたいな
-            fn check_conflict_with_names(names: &Vec<String>, ast: &AST) -> Vec<(Span, String)> {
-                let (alias, children) = ast.take_section_like().unwrap();
-                let mut v = vec![];
-                for n in names {
-                    if let Some(index) = alias.get(n) {
-                        v.push((children[*index].get_span(), n.clone()));
-                    }
-                }
-                for p in children {
-                    if let NodeKind::Section { .. } = &p.node {
-                        v.extend(check_conflict_with_names(names, p));
-                    }
+            validate_tree(names, &index_names(names), &ast[0], &mut errs);
+        }
+
+        let names = if let Some(names) = names {
+            names.1
+        } else {
+            // エラーを追加してからのほうが優しい
+            errs.insert(ParseError::MissingNames);
+            return Err(in_source_order(errs));
+        };
+
+        if !errs.is_empty() {
+            return Err(in_source_order(errs));
+        }
+
+        Ok(Document::from_parts(
+            names,
+            name_spans,
+            ast.into_iter().next().unwrap(),
+        ))
+    }
+}
+
+/// Orders the collected errors by where they are, so they are reported from the top of the
+/// document. [`ParseError::MissingNames`] comes first.
+fn in_source_order(errs: FxHashSet<ParseError>) -> Vec<ParseError> {
+    let mut errs: Vec<_> = errs.into_iter().collect();
+    errs.sort_by_key(|err| err.span().map(|span| (span.start, span.end)));
+    errs
+}
+
+/// Removes a leading UTF-8 byte order mark, which some editors write at the start of a file.
+///
+/// Spans of a document parsed from the result are offsets after the BOM.
+pub fn strip_bom(text: &str) -> &str {
+    text.strip_prefix('\u{feff}').unwrap_or(text)
+}
+
+/// Turns the `\r\n` and bare `\r` line breaks in multi-line content into `\n`, so a
+/// document reads the same whatever line endings it was saved with.
+fn normalize_line_endings(s: &str) -> String {
+    s.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+/// Joins the lines of a section content continued with a trailing `\` into one line.
+///
+/// The whitespace around the line break becomes a single space. Other escapes, including
+/// `\\`, are kept as they are.
+fn join_continuations(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+
+        match chars.peek() {
+            Some('\n' | '\r') => {
+                chars.next_if_eq(&'\r');
+                chars.next_if_eq(&'\n');
+                while chars.next_if(|c| matches!(c, ' ' | '\t')).is_some() {}
+
+                out.truncate(out.trim_end().len());
+                if chars.peek().is_some() {
+                    out.push(' ');
                 }
-                v
             }
-            for (span, name) in check_conflict_with_names(names, &ast[0]) {
-                errs.insert(ParseError::AliasConflictWithNames(name, span));
+            Some(_) => {
+                out.push(c);
+                out.extend(chars.next());
             }
+            None => out.push(c),
         }
+    }
 
-        if let Some((_, names)) = &names {
-            fn check_sen_len(names: usize, ast: &AST) -> Vec<Span> {
-                let (_, children) = ast.take_section_like().unwrap();
-                let mut errs: Vec<Span> = vec![];
-                for p in children {
-                    if let NodeKind::Section { .. } = &p.node {
-                        errs.extend(check_sen_len(names, p));
-                    }
-                    if let NodeKind::Sen(sentences) = &p.node
-                        && sentences.len() != names {
-                            errs.push(p.get_span());
-                        }
+    out
+}
+
+/// One `[...]` of a sentence block.
+struct SenBlock {
+    /// The `name:` prefix, if the block starts with one.
+    label: Option<String>,
+    /// The content after the label.
+    content: String,
+    /// The content including the label, for blocks that turn out to be unlabeled.
+    raw: String,
+    span: Span,
+}
+
+impl From<pest::iterators::Pair<'_, Rule>> for SenBlock {
+    fn from(pair: pest::iterators::Pair<'_, Rule>) -> Self {
+        let span: Span = pair.as_span().into();
+        let source = pair.as_str();
+        let mut inner = pair.into_inner();
+        let first = inner.next().unwrap();
+
+        match inner.next() {
+            Some(string) => {
+                let start = first.as_span().start() - span.start;
+                let end = string.as_span().end() - span.start;
+                Self {
+                    label: Some(first.into_inner().next().unwrap().as_str().to_string()),
+                    content: normalize_line_endings(string.as_str()),
+                    raw: normalize_line_endings(&source[start..end]),
+                    span,
                 }
-                errs
             }
-            for span in check_sen_len(names.len(), &ast[0]) {
-                errs.insert(ParseError::NumberOfSentences(span));
+            None => Self {
+                label: None,
+                content: normalize_line_endings(first.as_str()),
+                raw: normalize_line_endings(first.as_str()),
+                span,
+            },
+        }
+    }
+}
+
+/// Orders labeled sentence blocks by the declared names. Every block has a label.
+///
+/// Names without a block get an empty sentence after the error is recorded, so the
+/// number of sentences is not reported again.
+fn resolve_labels(
+    names: &[String],
+    blocks: Vec<SenBlock>,
+    span: &Span,
+    errs: &mut FxHashSet<ParseError>,
+) -> Vec<String> {
+    let mut slots: Vec<Option<String>> = vec![None; names.len()];
+
+    for block in blocks {
+        let Some(label) = block.label else {
+            continue;
+        };
+
+        match names.iter().position(|n| *n == label) {
+            None => {
+                errs.insert(ParseError::UnknownLabel(label, block.span));
+            }
+            Some(index) if slots[index].is_some() => {
+                errs.insert(ParseError::DuplicateLabel(label, block.span));
             }
+            Some(index) => slots[index] = Some(block.content),
         }
+    }
 
-        // Selectorの妥当性
-        if let Some((_, names)) = &names {
-            fn check_selector(names: &Vec<String>, top_ast: &AST, ast: &AST) -> Vec<ParseError> {
-                let (_, children) = ast.take_section_like().unwrap();
-                let mut v = vec![];
-                for p in children {
-                    if let NodeKind::Selector {
-                        local,
-                        path,
-                        trailing_dot,
-                    } = &p.node
-                    {
-                        let range = if !trailing_dot && !path.is_empty() {
-                            if !names.contains(path.last().unwrap()) {
-                                v.push(ParseError::Selector(
-                                    SelectorError::LastIsNotDotOrName,
-                                    p.get_span(),
-                                ));
-                            }
-                            0..(path.len() - 1)
-                        } else {
-                            0..(path.len())
-                        };
+    names
+        .iter()
+        .zip(slots)
+        .map(|(name, slot)| {
+            slot.unwrap_or_else(|| {
+                errs.insert(ParseError::MissingLabel(name.clone(), span.clone()));
+                String::new()
+            })
+        })
+        .collect()
+}
+
+/// Checks the constraints that need the whole tree: aliases against names, the number of sentences and selectors.
+fn validate_tree(
+    names: &[String],
+    name_indices: &FxHashMap<String, usize>,
+    top: &AST,
+    errs: &mut FxHashSet<ParseError>,
+) {
+    // TODO: DRY, foldみたいな
+    fn check_conflict_with_names(names: &[String], ast: &AST) -> Vec<(Span, String)> {
+        let (alias, children) = ast.take_section_like().unwrap();
+        let mut v = vec![];
+        for n in names {
+            if let Some(child) = alias.get(n).and_then(|index| children.get(*index)) {
+                v.push((child.get_span(), n.clone()));
+            }
+        }
+        for p in children {
+            if let NodeKind::Section { .. } = &p.node {
+                v.extend(check_conflict_with_names(names, p));
+            }
+        }
+        v
+    }
+    for (span, name) in check_conflict_with_names(names, top) {
+        errs.insert(ParseError::AliasConflictWithNames(name, span));
+    }
 
-                        let mut curr = if *local { ast } else { top_ast };
+    fn check_sen_len(names: usize, ast: &AST) -> Vec<Span> {
+        let (_, children) = ast.take_section_like().unwrap();
+        let mut errs: Vec<Span> = vec![];
+        for p in children {
+            if let NodeKind::Section { .. } = &p.node {
+                errs.extend(check_sen_len(names, p));
+            }
+            if let NodeKind::Sen(sentences, _) = &p.node
+                && sentences.len() != names {
+                    errs.push(p.get_span());
+                }
+        }
+        errs
+    }
+    for span in check_sen_len(names.len(), top) {
+        errs.insert(ParseError::NumberOfSentences(span));
+    }
 
-                        for k in &path[range] {
-                            if matches!(curr.node, NodeKind::Sen { .. })
-                                || matches!(curr.node, NodeKind::All { .. })
-                            {
-                                break;
-                            }
-                            let (alias, children) = curr.take_section_like().unwrap();
-
-                            if let Some(index) = alias.get(k) {
-                                curr = &children[*index];
-                            } else if let Ok(index) = k.parse::<usize>() {
-                                let children_without_sel: Vec<&AST> = children
-                                    .iter()
-                                    .filter(|p| !matches!(&p.node, NodeKind::Selector { .. }))
-                                    .collect();
-
-                                if index >= children_without_sel.len() {
-                                    v.push(ParseError::Selector(
-                                        SelectorError::OutOfIndex,
-                                        p.get_span(),
-                                    ));
-                                    break;
-                                } else {
-                                    curr = children_without_sel[index];
-                                }
-                            } else {
-                                v.push(ParseError::Selector(
-                                    SelectorError::Neither(k.clone()),
-                                    p.get_span(),
-                                ));
-                                break;
-                            }
-                        }
+    // Selectorの妥当性
+    fn check_selector(
+        names: &FxHashMap<String, usize>,
+        top_ast: &AST,
+        ast: &AST,
+    ) -> Vec<ParseError> {
+        let (_, children) = ast.take_section_like().unwrap();
+        let mut v = vec![];
+        for p in children {
+            if let NodeKind::Selector {
+                local,
+                path,
+                trailing_dot,
+                misplaced_dots,
+                names: name_list,
+                ..
+            } = &p.node
+            {
+                // トップレベルには相対的に辿る元になるセクションがない
+                if *local && matches!(ast.node, NodeKind::Top { .. }) {
+                    v.push(ParseError::Selector(
+                        SelectorError::LocalAtTopLevel,
+                        p.get_span(),
+                    ));
+                    continue;
+                }
+
+                for span in misplaced_dots {
+                    v.push(ParseError::Selector(
+                        SelectorError::DotInsidePath,
+                        span.clone(),
+                    ));
+                }
+
+                for name in name_list.iter().flatten() {
+                    if !names.contains_key(name) {
+                        v.push(ParseError::Selector(
+                            SelectorError::UnknownName(name.clone()),
+                            p.get_span(),
+                        ));
                     }
+                }
 
-                    if let NodeKind::Section { .. } = &p.node {
-                        v.extend(check_selector(names, top_ast, p));
+                let range = if !trailing_dot && name_list.is_none() && !path.is_empty() {
+                    if !last_is_name(names, path) {
+                        v.push(ParseError::Selector(
+                            SelectorError::LastIsNotDotOrName,
+                            p.get_span(),
+                        ));
                     }
+                    0..(path.len() - 1)
+                } else {
+                    0..(path.len())
+                };
+
+                let curr = if *local { ast } else { top_ast };
+
+                if let Err(e) = curr.resolve_path(&path[range]) {
+                    v.push(ParseError::Selector(e, p.get_span()));
                 }
-                v
             }
-            errs.extend(check_selector(names, &ast[0], &ast[0]));
+
+            if let NodeKind::Section { .. } = &p.node {
+                v.extend(check_selector(names, top_ast, p));
+            }
         }
+        v
+    }
+    errs.extend(check_selector(name_indices, top, top));
+}
 
-        let names = if let Some(names) = names {
-            names.1
-        } else {
-            // エラーを追加してからのほうが優しい
-            errs.insert(ParseError::MissingNames);
-            return Err(errs.into_iter().collect());
-        };
+/// Reparses `new_text` after the bytes `changed` of `old_text` were replaced, reusing `old`.
+///
+/// Only the top-level sections enclosing the change are run through the grammar again; the
+/// resulting nodes are spliced into a copy of `old.ast` and the whole tree is validated. When
+/// the change can't be isolated that way (e.g. it touches the name declaration or changes how
+/// the neighbouring sections nest) this falls back to a full parse.
+///
+/// Returns the errors of the full parse if `new_text` is not a valid document.
+pub fn reparse_range(
+    old: &Document,
+    old_text: &str,
+    new_text: &str,
+    changed: Span,
+) -> Result<Document, ReparseError> {
+    use pest::Parser as _;
 
-        if !errs.is_empty() {
-            return Err(errs.into_iter().collect());
+    if let Some(doc) = reparse_incrementally(old, old_text, new_text, &changed) {
+        return Ok(doc);
+    }
+    SandParser::parse(Rule::doc, new_text)
+        .map_err(|e| ReparseError::Syntax(e.into()))?
+        .try_into()
+        .map_err(ReparseError::Invalid)
+}
+
+fn reparse_incrementally(
+    old: &Document,
+    old_text: &str,
+    new_text: &str,
+    changed: &Span,
+) -> Option<Document> {
+    use pest::Parser as _;
+
+    fn level_of(ast: &AST) -> Option<usize> {
+        match ast.node {
+            NodeKind::Section { level, .. } => Some(level),
+            _ => None,
         }
+    }
 
-        Ok(Document {
-            names,
-            ast: ast.into_iter().next().unwrap(),
-        })
+    let (_, children) = old.ast.take_section_like()?;
+
+    // 最初のセクションより後ろのトップレベルの子は全てセクション
+    let sections: Vec<usize> = children
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| matches!(c.node, NodeKind::Section { .. }))
+        .map(|(i, _)| i)
+        .collect();
+
+    let first = sections
+        .iter()
+        .rposition(|&i| children[i].meta.span.start <= changed.start)?;
+    let start = sections[first];
+    let end = sections[first..]
+        .iter()
+        .copied()
+        .find(|&i| children[i].meta.span.start > changed.end);
+
+    let region_start = children[start].meta.span.start;
+    let old_region_end = end.map_or(old_text.len(), |i| children[i].meta.span.start);
+    let delta = new_text.len() as isize - old_text.len() as isize;
+    let new_region_end = usize::try_from(old_region_end as isize + delta).ok()?;
+
+    let old_region = old_text.get(region_start..old_region_end)?;
+    let new_region = new_text.get(region_start..new_region_end)?;
+
+    // 範囲外が変わっていないことを確かめる
+    if old_text.get(..region_start)? != new_text.get(..region_start)?
+        || old_text.get(old_region_end..)? != new_text.get(new_region_end..)?
+    {
+        return None;
+    }
+
+    // 名前の定義はASTに残らないので、触れていそうなら全体をパースし直す
+    if old_region.contains("#(") || new_region.contains("#(") {
+        return None;
+    }
+
+    let header = format!("#({})\n", old.names.join(", "));
+    let region: Document = SandParser::parse(Rule::doc, &format!("{header}{new_region}"))
+        .ok()?
+        .try_into()
+        .ok()?;
+    let NodeKind::Top {
+        children: mut new_children,
+        ..
+    } = region.ast.node
+    else {
+        return None;
+    };
+
+    // 範囲の先頭はトップレベルのセクションのままでなければならない
+    let first_node = new_children.first()?;
+    let first_level = level_of(first_node)?;
+    if first_node.meta.span.start != header.len() {
+        return None;
+    }
+    if let Some(prev) = first.checked_sub(1).map(|k| &children[sections[k]])
+        && level_of(prev)? < first_level
+    {
+        return None;
+    }
+    // 後ろのセクションが範囲内のセクションの子にならないこと
+    if let Some(next) = end
+        && new_children.iter().rev().find_map(level_of)? < level_of(&children[next])?
+    {
+        return None;
+    }
+
+    let region_delta = region_start as isize - header.len() as isize;
+    for child in &mut new_children {
+        child.shift_spans(region_delta);
     }
+
+    let mut top_children = children[..start].to_vec();
+    top_children.extend(new_children);
+    for child in &children[end.unwrap_or(children.len())..] {
+        let mut child = child.clone();
+        child.shift_spans(delta);
+        top_children.push(child);
+    }
+
+    let mut aliases = FxHashMap::default();
+    for (index, child) in top_children.iter().enumerate() {
+        if let Some(alias) = &child.meta.alias
+            && aliases.insert(alias.clone(), index).is_some()
+        {
+            return None;
+        }
+    }
+
+    let ast = AST {
+        node: NodeKind::Top {
+            aliases,
+            children: top_children,
+        },
+        meta: old.ast.meta.clone(),
+    };
+
+    let mut errs = FxHashSet::default();
+    validate_tree(&old.names, &old.name_indices, &ast, &mut errs);
+    if !errs.is_empty() {
+        return None;
+    }
+
+    // 名前の定義が範囲より後ろにあればずらす
+    let name_spans = old
+        .name_spans
+        .iter()
+        .map(|span| {
+            if span.start >= old_region_end {
+                Span {
+                    start: (span.start as isize + delta) as usize,
+                    end: (span.end as isize + delta) as usize,
+                }
+            } else {
+                span.clone()
+            }
+        })
+        .collect();
+
+    Some(Document::from_parts(old.names.clone(), name_spans, ast))
 }
 
 pub fn parse_selector(span: Span, pair: pest::iterators::Pair<'_, Rule>) -> AST {
@@ -444,61 +1059,189 @@ pub fn parse_selector(span: Span, pair: pest::iterators::Pair<'_, Rule>) -> AST
     };
 
     let mut path = vec![];
-    let mut trailing_dot = false;
+    let mut segment_spans = vec![];
+    // `.` の位置と、その時点でのセグメント数
+    let mut dots = vec![];
+    let mut names = None;
     for p in inner {
+        if matches!(p.as_rule(), Rule::Ident | Rule::Glob | Rule::ContentMatch) {
+            segment_spans.push(p.as_span().into());
+        }
+
         match p.as_rule() {
             Rule::Ident => {
-                path.push(p.as_str().to_string());
+                path.push(PathSegment::Ident(p.as_str().to_string()));
+            }
+            Rule::Glob => {
+                path.push(PathSegment::Glob);
+            }
+            Rule::ContentMatch => {
+                let quoted = p.into_inner().next().unwrap();
+                let inner = quoted.into_inner().next().unwrap().as_str();
+                path.push(PathSegment::Content(unescape_quoted(inner)));
             }
             Rule::LastDot => {
-                trailing_dot = true;
+                dots.push((path.len(), Span::from(p.as_span())));
+            }
+            Rule::Idents => {
+                names = Some(
+                    p.into_inner()
+                        .next()
+                        .unwrap()
+                        .into_inner()
+                        .filter(|p| p.as_rule() == Rule::Ident)
+                        .map(|p| p.as_str().to_string())
+                        .collect(),
+                );
             }
             _ => {}
         }
     }
+    let (trailing, misplaced): (Vec<_>, Vec<_>) =
+        dots.into_iter().partition(|(at, _)| *at == path.len());
+
     AST {
         meta: NodeMeta { span, alias: None },
         node: NodeKind::Selector {
             local,
             path,
-            trailing_dot,
+            segment_spans,
+            trailing_dot: !trailing.is_empty(),
+            misplaced_dots: misplaced.into_iter().map(|(_, span)| span).collect(),
+            names,
         },
     }
 }
 
-type Alias = FxHashMap<String, usize>;
+fn unescape_quoted(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            out.extend(chars.next());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
 
-#[derive(Debug, Clone)]
-pub struct NodeMeta {
-    span: Span,
-    alias: Option<String>,
+fn last_is_name(names: &FxHashMap<String, usize>, path: &[PathSegment]) -> bool {
+    matches!(path.last(), Some(PathSegment::Ident(name)) if names.contains_key(name))
 }
 
-#[derive(Debug, Clone)]
-pub enum NodeKind {
-    ///  Contents
-    Sen(Vec<String>),
-    /// All or Name, Content
-    All {
-        all_or_names: Option<Vec<String>>,
-        content: String,
-    },
-    ///  depth,  Content, Children
-    Section {
-        level: usize,
-        content: String,
-        aliases: Alias,
-        children: Vec<AST>,
-    },
+/// Maps each name to its index. A repeated name keeps its first index.
+fn index_names(names: &[String]) -> FxHashMap<String, usize> {
+    let mut indices = FxHashMap::default();
+    for (index, name) in names.iter().enumerate() {
+        indices.entry(name.clone()).or_insert(index);
+    }
+    indices
+}
+
+/// One dot-separated part of a selector path.
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub enum PathSegment {
+    /// An alias or a zero-based index, or a name when it is the last segment.
+    Ident(String),
+    /// `{"..."}`, the child section whose content equals (or else contains) the string.
+    Content(String),
+    /// `*`, every child section.
+    Glob,
+}
+
+impl PathSegment {
+    pub fn as_ident(&self) -> Option<&str> {
+        match self {
+            PathSegment::Ident(ident) => Some(ident),
+            PathSegment::Content(_) | PathSegment::Glob => None,
+        }
+    }
+}
+
+/// What a selector path segment matched, see [`crate::formatter::explain`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    /// An alias, to the child at this position.
+    Alias(usize),
+    /// A number, to the child at this position in [`AST::content_children`].
+    Index(usize),
+    /// `{"..."}`, to the section with this content.
+    Content(String),
+    /// `*`, to this many child sections.
+    Glob(usize),
+    /// The name at the end of the path, with its index in [`Document::names`].
+    Name(usize),
+}
+
+impl std::fmt::Display for Resolution {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Resolution::Alias(index) => write!(f, "alias of child {index}"),
+            Resolution::Index(index) => write!(f, "index {index}"),
+            Resolution::Content(content) => write!(f, "section `{content}`"),
+            Resolution::Glob(count) => write!(f, "{count} sections"),
+            Resolution::Name(index) => write!(f, "name {index}"),
+        }
+    }
+}
+
+impl std::fmt::Display for PathSegment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PathSegment::Ident(ident) => write!(f, "{ident}"),
+            PathSegment::Glob => write!(f, "*"),
+            PathSegment::Content(content) => write!(
+                f,
+                "{{\"{}\"}}",
+                content.replace('\\', "\\\\").replace('"', "\\\"")
+            ),
+        }
+    }
+}
+
+type Alias = FxHashMap<String, usize>;
+
+/// The `{key=value, ...}` attributes of a section, sorted by key.
+pub type Attributes = std::collections::BTreeMap<String, String>;
+
+#[derive(Debug, Clone)]
+pub struct NodeMeta {
+    pub(crate) span: Span,
+    pub(crate) alias: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum NodeKind {
+    ///  Contents, one per name in the declared order,
+    ///  and the `name:` labels if the blocks were written with them
+    Sen(Vec<String>, Option<Vec<String>>),
+    /// All or Name, Content
+    All {
+        all_or_names: Option<Vec<String>>,
+        content: String,
+    },
+    ///  depth,  Content, `{key=value, ...}` attributes, Children
+    Section {
+        level: usize,
+        content: String,
+        attributes: Attributes,
+        aliases: Alias,
+        children: Vec<AST>,
+    },
     Top {
         aliases: Alias,
         children: Vec<AST>,
     },
-    /// local, paths, last dot
+    /// local, paths, the span of each path segment, last dot, the spans of dots followed by
+    /// more segments (`#.sec1..en`), the `[...]` name list
     Selector {
         local: bool,
-        path: Vec<String>,
+        path: Vec<PathSegment>,
+        segment_spans: Vec<Span>,
         trailing_dot: bool,
+        misplaced_dots: Vec<Span>,
+        names: Option<Vec<String>>,
     },
 }
 
@@ -508,6 +1251,240 @@ pub struct AST {
     pub meta: NodeMeta,
 }
 
+/// Pre-order iterator over an [`AST`], created by [`AST::iter`].
+#[derive(Debug, Clone)]
+pub struct Iter<'a> {
+    stack: Vec<&'a AST>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a AST;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        if let Some((_, children)) = node.take_section_like() {
+            // 先頭の子が先に出るように逆順で積む
+            self.stack.extend(children.iter().rev());
+        }
+        Some(node)
+    }
+}
+
+/// Writes the node back as sand source.
+///
+/// Free text between statements is not part of the AST, so it is not reproduced.
+impl std::fmt::Display for AST {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let alias = self.meta.alias.as_deref().unwrap_or_default();
+
+        match &self.node {
+            NodeKind::Sen(sentences, labels) => {
+                write!(f, "#{alias}")?;
+                for (i, sen) in sentences.iter().enumerate() {
+                    match labels.as_ref().and_then(|l| l.get(i)) {
+                        Some(label) => write!(f, "[{label}: {sen}]")?,
+                        None => write!(f, "[{sen}]")?,
+                    }
+                }
+                writeln!(f)
+            }
+            NodeKind::All {
+                all_or_names: Some(names),
+                content,
+            } => writeln!(f, "#{alias}{{[{}], {{{content}}}}}", names.join(", ")),
+            NodeKind::All {
+                all_or_names: None,
+                content,
+            } => writeln!(f, "#{alias}{{{{{content}}}}}"),
+            NodeKind::Section {
+                level,
+                content,
+                attributes,
+                children,
+                ..
+            } => {
+                write!(f, "\n#{alias}{}", "#".repeat(*level))?;
+                if !attributes.is_empty() {
+                    let attributes: Vec<_> =
+                        attributes.iter().map(|(k, v)| format!("{k}={v}")).collect();
+                    write!(f, "{{{}}}", attributes.join(", "))?;
+                }
+                writeln!(f, " {content}")?;
+                for child in children {
+                    write!(f, "{child}")?;
+                }
+                Ok(())
+            }
+            NodeKind::Top { children, .. } => {
+                for child in children {
+                    write!(f, "{child}")?;
+                }
+                Ok(())
+            }
+            NodeKind::Selector {
+                local,
+                path,
+                trailing_dot,
+                names,
+                ..
+            } => {
+                write!(f, "#.")?;
+                if *local {
+                    write!(f, "/")?;
+                }
+                write!(
+                    f,
+                    "{}",
+                    path.iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(".")
+                )?;
+                if *trailing_dot {
+                    write!(f, ".")?;
+                }
+                if let Some(names) = names {
+                    write!(f, "[{}]", names.join(", "))?;
+                }
+                writeln!(f)
+            }
+        }
+    }
+}
+
+/// Writes the document back as sand source, starting with the name declaration.
+impl std::fmt::Display for Document {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "#({})", self.names.join(", "))?;
+        write!(f, "{}", self.ast)
+    }
+}
+
+impl Document {
+    /// Builds a document from its parts, indexing `names` for [`Document::name_index`].
+    pub(crate) fn from_parts(names: Vec<String>, name_spans: Vec<Span>, ast: AST) -> Self {
+        Self {
+            name_indices: index_names(&names),
+            names,
+            name_spans,
+            ast,
+        }
+    }
+
+    /// The declared names, in the order of the `#(...)` declaration.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Lists every alias with the path leading to it from the top and the span of its node.
+    ///
+    /// The path holds the alias of each enclosing node, or its index if it has none, and ends
+    /// with the alias itself, so it can be written as a selector.
+    pub fn alias_index(&self) -> Vec<(String, Vec<String>, Span)> {
+        fn walk(ast: &AST, path: &mut Vec<String>, index: &mut Vec<(String, Vec<String>, Span)>) {
+            for (i, child) in ast.content_children().into_iter().enumerate() {
+                path.push(child.meta.alias.clone().unwrap_or_else(|| i.to_string()));
+                if let Some(alias) = &child.meta.alias {
+                    index.push((alias.clone(), path.clone(), child.meta.span.clone()));
+                }
+                walk(child, path, index);
+                path.pop();
+            }
+        }
+
+        let mut index = Vec::new();
+        walk(&self.ast, &mut Vec::new(), &mut index);
+        index
+    }
+
+    /// Parses `s` as a selector on its own, e.g. typed by a user, and checks it against this
+    /// document like a selector in the document.
+    ///
+    /// Syntax errors and trailing text are [`SelectorError::Syntax`], with spans into `s`.
+    pub fn validate_selector_str(&self, s: &str) -> Result<(), Vec<ParseError>> {
+        let pair = parse_fragment(Rule::Selector, s)
+            .map_err(|e| {
+                vec![ParseError::Selector(
+                    SelectorError::Syntax(e.message),
+                    e.span,
+                )]
+            })?
+            .next()
+            .unwrap();
+
+        let errs = validate_non_local_selector(self, &parse_selector(pair.as_span().into(), pair));
+        if errs.is_empty() { Ok(()) } else { Err(errs) }
+    }
+
+    /// The index of `name` in [`Document::names`], without a linear search.
+    pub fn name_index(&self, name: &str) -> Option<usize> {
+        self.name_indices.get(name).copied()
+    }
+
+    /// Whether nothing but the names is written, so every name renders as empty.
+    pub fn is_empty(&self) -> bool {
+        matches!(&self.ast.node, NodeKind::Top { children, .. } if children.is_empty())
+    }
+
+    /// Counts the names and the nodes of each kind.
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats {
+            names: self.names.len(),
+            ..Default::default()
+        };
+
+        for node in self.ast.iter() {
+            match &node.node {
+                NodeKind::Section { level, .. } => {
+                    if stats.sections_per_level.len() < *level {
+                        stats.sections_per_level.resize(*level, 0);
+                    }
+                    stats.sections_per_level[level - 1] += 1;
+                }
+                NodeKind::Sen(..) => stats.sentences += 1,
+                NodeKind::All { .. } => stats.apply_alls += 1,
+                NodeKind::Selector { .. } => stats.selectors += 1,
+                NodeKind::Top { .. } => {}
+            }
+        }
+
+        stats.max_depth = section_depth(&self.ast);
+
+        stats
+    }
+}
+
+/// How deeply sections are nested in `ast`, not counting `ast` itself. Heading levels can be
+/// skipped, so this is not the deepest level.
+fn section_depth(ast: &AST) -> usize {
+    ast.take_section_like().map_or(0, |(_, children)| {
+        children
+            .iter()
+            .filter(|child| matches!(child.node, NodeKind::Section { .. }))
+            .map(|child| section_depth(child) + 1)
+            .max()
+            .unwrap_or(0)
+    })
+}
+
+/// Counts of the nodes in a [`Document`], see [`Document::stats`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct Stats {
+    /// The number of declared names.
+    pub names: usize,
+    /// The number of sections at each level, starting from level 1.
+    pub sections_per_level: Vec<usize>,
+    /// The number of sentence blocks.
+    pub sentences: usize,
+    /// The number of apply-all blocks.
+    pub apply_alls: usize,
+    /// The number of selectors, local ones included.
+    pub selectors: usize,
+    /// How deeply the sections are nested, 0 if there are no sections. A section that skips
+    /// a heading level is still one deeper than its parent.
+    pub max_depth: usize,
+}
+
 fn take_alias(inner: &mut Pairs<'_, Rule>) -> Option<String> {
     let alias = inner
         .peek()
@@ -519,6 +1496,10 @@ fn take_alias(inner: &mut Pairs<'_, Rule>) -> Option<String> {
     alias
 }
 
+/// Registers `alias` for the child at `new_index`, reporting both nodes if it is taken.
+///
+/// A taken alias keeps pointing at the first node that registered it, so selectors resolve
+/// the same way no matter how many duplicates follow.
 fn check_alias_conflict(
     alias: &str,
     aliases: &mut FxHashMap<String, usize>,
@@ -527,17 +1508,24 @@ fn check_alias_conflict(
     new_span: Span,
     errs: &mut FxHashSet<ParseError>,
 ) {
-    if let Some(conflict_index) = aliases.insert(alias.to_string(), new_index) {
-        errs.insert(ParseError::DuplicateAlias(alias.to_string(), new_span));
-        errs.insert(ParseError::DuplicateAlias(
-            alias.to_string(),
-            children[conflict_index].get_span(),
-        ));
+    use std::collections::hash_map::Entry;
+
+    match aliases.entry(alias.to_string()) {
+        Entry::Occupied(first) => {
+            errs.insert(ParseError::DuplicateAlias(alias.to_string(), new_span));
+            errs.insert(ParseError::DuplicateAlias(
+                alias.to_string(),
+                children[*first.get()].get_span(),
+            ));
+        }
+        Entry::Vacant(entry) => {
+            entry.insert(new_index);
+        }
     }
 }
 
 impl AST {
-    fn take_mut_section_like(&mut self) -> Option<(usize, &mut Alias, &mut Vec<AST>)> {
+    pub(crate) fn take_mut_section_like(&mut self) -> Option<(usize, &mut Alias, &mut Vec<AST>)> {
         match &mut self.node {
             NodeKind::Top {
                 aliases: a,
@@ -546,8 +1534,8 @@ impl AST {
             NodeKind::Section {
                 aliases: a,
                 children: v,
-                content: _,
                 level: d,
+                ..
             } => Some((*d, a, v)),
             _ => None,
         }
@@ -568,14 +1556,320 @@ impl AST {
         }
     }
 
-    fn get_span(&self) -> Span {
+    /// The children that the index segments of a selector count, like the `1` in `#.1.en`.
+    ///
+    /// Selectors are left out, so adding one doesn't shift the indices of the nodes after it.
+    /// Empty if this node has no children.
+    pub fn content_children(&self) -> Vec<&AST> {
+        self.take_section_like()
+            .map_or_else(Vec::new, |(_, children)| {
+                children
+                    .iter()
+                    .filter(|p| !matches!(&p.node, NodeKind::Selector { .. }))
+                    .collect()
+            })
+    }
+
+    /// The `{key=value, ...}` attributes if this is a section.
+    pub fn attributes(&self) -> Option<&Attributes> {
+        match &self.node {
+            NodeKind::Section { attributes, .. } => Some(attributes),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn get_span(&self) -> Span {
         self.meta.span.clone()
     }
 
-    // TODO: bin searchにできるかも
+    /// Where the node is written in the source. The span of a section covers its heading
+    /// line only.
+    ///
+    /// ```
+    /// use pest::Parser as _;
+    /// use sand::parser::{Document, NodeKind, Rule, SandParser};
+    ///
+    /// let input = "#(en, ja)\n#intro# Intro\n#[Hi][やあ]\n";
+    /// let doc: Document = SandParser::parse(Rule::doc, input)
+    ///     .unwrap()
+    ///     .try_into()
+    ///     .unwrap();
+    ///
+    /// let section = doc
+    ///     .ast
+    ///     .iter()
+    ///     .find(|ast| matches!(ast.node, NodeKind::Section { .. }))
+    ///     .unwrap();
+    /// let span = section.span();
+    /// assert_eq!(&input[span.start..span.end], "#intro# Intro\n");
+    /// assert_eq!(section.alias(), Some("intro"));
+    /// ```
+    pub fn span(&self) -> Span {
+        self.get_span()
+    }
+
+    /// The text the node is written with in `text`, escapes included. `text` should be the
+    /// source the node was parsed from. Like [`AST::span`], this is only the heading line for
+    /// a section, so it is not always a full statement.
+    ///
+    /// The span is clamped to `text` and narrowed to char boundaries, so this doesn't panic
+    /// for other text.
+    pub fn source<'a>(&self, text: &'a str) -> &'a str {
+        let mut end = self.meta.span.end.min(text.len());
+        let mut start = self.meta.span.start.min(end);
+        while !text.is_char_boundary(start) {
+            start += 1;
+        }
+        while end > start && !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        &text[start..end.max(start)]
+    }
+
+    /// The alias the node is written with, e.g. `intro` for `#intro# Intro`.
+    pub fn alias(&self) -> Option<&str> {
+        self.meta.alias.as_deref()
+    }
+
+    /// Whether both trees have the same nodes with the same content and aliases, ignoring
+    /// every span. Useful for comparing a document with the one parsed from its output.
+    pub fn structurally_eq(&self, other: &AST) -> bool {
+        let children_eq = |a: &[AST], b: &[AST]| {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.structurally_eq(b))
+        };
+
+        self.meta.alias == other.meta.alias
+            && match (&self.node, &other.node) {
+                (NodeKind::Sen(a, a_labels), NodeKind::Sen(b, b_labels)) => {
+                    a == b && a_labels == b_labels
+                }
+                (
+                    NodeKind::All {
+                        all_or_names: a_names,
+                        content: a,
+                    },
+                    NodeKind::All {
+                        all_or_names: b_names,
+                        content: b,
+                    },
+                ) => a_names == b_names && a == b,
+                (
+                    NodeKind::Section {
+                        level: a_level,
+                        content: a,
+                        attributes: a_attributes,
+                        aliases: a_aliases,
+                        children: a_children,
+                    },
+                    NodeKind::Section {
+                        level: b_level,
+                        content: b,
+                        attributes: b_attributes,
+                        aliases: b_aliases,
+                        children: b_children,
+                    },
+                ) => {
+                    a_level == b_level
+                        && a == b
+                        && a_attributes == b_attributes
+                        && a_aliases == b_aliases
+                        && children_eq(a_children, b_children)
+                }
+                (
+                    NodeKind::Top {
+                        aliases: a_aliases,
+                        children: a_children,
+                    },
+                    NodeKind::Top {
+                        aliases: b_aliases,
+                        children: b_children,
+                    },
+                ) => a_aliases == b_aliases && children_eq(a_children, b_children),
+                (
+                    NodeKind::Selector {
+                        local: a_local,
+                        path: a_path,
+                        trailing_dot: a_dot,
+                        misplaced_dots: a_dots,
+                        names: a_names,
+                        ..
+                    },
+                    NodeKind::Selector {
+                        local: b_local,
+                        path: b_path,
+                        trailing_dot: b_dot,
+                        misplaced_dots: b_dots,
+                        names: b_names,
+                        ..
+                    },
+                ) => {
+                    a_local == b_local
+                        && a_path == b_path
+                        && a_dot == b_dot
+                        && a_dots.len() == b_dots.len()
+                        && a_names == b_names
+                }
+                _ => false,
+            }
+    }
+
+    /// Resolves one selector path segment against the children of this section-like node, and
+    /// returns what it matched with the node.
+    fn resolve_segment(&self, segment: &PathSegment) -> Result<(&AST, Resolution), SelectorError> {
+        let (alias, children) = self.take_section_like().ok_or(SelectorError::OutOfIndex)?;
+
+        match segment {
+            PathSegment::Ident(k) => {
+                if let Some(index) = alias.get(k) {
+                    // 別名の表が古くてもpanicしない
+                    children
+                        .get(*index)
+                        .map(|node| (node, Resolution::Alias(*index)))
+                        .ok_or(SelectorError::OutOfIndex)
+                } else if let Ok(index) = k.parse::<usize>() {
+                    self.content_children()
+                        .get(index)
+                        .map(|node| (*node, Resolution::Index(index)))
+                        .ok_or(SelectorError::OutOfIndex)
+                } else {
+                    Err(SelectorError::Neither(k.clone()))
+                }
+            }
+            PathSegment::Content(text) => {
+                let sections = || {
+                    children.iter().filter_map(|p| match &p.node {
+                        NodeKind::Section { content, .. } => Some((p, content)),
+                        _ => None,
+                    })
+                };
+
+                let mut matches: Vec<&AST> = sections()
+                    .filter(|(_, content)| *content == text)
+                    .map(|(p, _)| p)
+                    .collect();
+                if matches.is_empty() {
+                    matches = sections()
+                        .filter(|(_, content)| content.contains(text.as_str()))
+                        .map(|(p, _)| p)
+                        .collect();
+                }
+
+                match matches.as_slice() {
+                    [found] => {
+                        let NodeKind::Section { content, .. } = &found.node else {
+                            unreachable!()
+                        };
+                        Ok((*found, Resolution::Content(content.clone())))
+                    }
+                    [] => Err(SelectorError::NoMatchingSection(text.clone())),
+                    _ => Err(SelectorError::AmbiguousSelector(text.clone())),
+                }
+            }
+            PathSegment::Glob => Err(SelectorError::AmbiguousSelector("*".to_string())),
+        }
+    }
+
+    /// Follows `path` from this node and returns every node it leads to.
+    ///
+    /// A `*` segment branches into all child sections. A segment after a sentence or
+    /// apply-all block is a [`SelectorError::NotASection`].
+    pub(crate) fn resolve_path(&self, path: &[PathSegment]) -> Result<Vec<&AST>, SelectorError> {
+        self.resolve_path_traced(path, &mut vec![])
+    }
+
+    /// Like [`AST::resolve_path`], but also records what each segment matched in `trace`.
+    /// After a `*`, a segment is recorded once for every node it is resolved from.
+    pub(crate) fn resolve_path_traced(
+        &self,
+        path: &[PathSegment],
+        trace: &mut Vec<(PathSegment, Resolution)>,
+    ) -> Result<Vec<&AST>, SelectorError> {
+        let mut frontier = vec![self];
+
+        for segment in path {
+            let mut next = vec![];
+            for curr in frontier {
+                match (&curr.node, segment) {
+                    (NodeKind::Sen(..) | NodeKind::All { .. }, _) => {
+                        return Err(SelectorError::NotASection(
+                            segment.to_string(),
+                            curr.get_span(),
+                        ));
+                    }
+                    (_, PathSegment::Glob) => {
+                        let (_, children) =
+                            curr.take_section_like().ok_or(SelectorError::OutOfIndex)?;
+                        let sections = children
+                            .iter()
+                            .filter(|c| matches!(c.node, NodeKind::Section { .. }));
+                        trace.push((segment.clone(), Resolution::Glob(sections.clone().count())));
+                        next.extend(sections);
+                    }
+                    _ => {
+                        let (node, resolution) = curr.resolve_segment(segment)?;
+                        trace.push((segment.clone(), resolution));
+                        next.push(node);
+                    }
+                }
+            }
+            frontier = next;
+        }
+
+        Ok(frontier)
+    }
+
+    /// Moves the spans of this node and its descendants by `delta` bytes.
+    pub(crate) fn shift_spans(&mut self, delta: isize) {
+        self.meta.span.start = self.meta.span.start.saturating_add_signed(delta);
+        self.meta.span.end = self.meta.span.end.saturating_add_signed(delta);
+        if let NodeKind::Selector { segment_spans, .. } = &mut self.node {
+            for span in segment_spans {
+                span.start = span.start.saturating_add_signed(delta);
+                span.end = span.end.saturating_add_signed(delta);
+            }
+        }
+        if let Some((_, _, children)) = self.take_mut_section_like() {
+            for child in children {
+                child.shift_spans(delta);
+            }
+        }
+    }
+
+    /// Iterates over this node and all of its descendants in pre-order (document order).
+    pub fn iter(&self) -> Iter<'_> {
+        Iter { stack: vec![self] }
+    }
+
+    /// Finds the selector at `offset` and the index of the path segment the offset is on.
+    ///
+    /// The end of a segment counts as on it, so a cursor right after `en` in `#.sec.en` finds
+    /// `en`. Offsets on the `#.`, the dots or the slash between segments return `None`.
+    pub fn selector_segment_at(&self, offset: usize) -> Option<(&AST, usize)> {
+        let node = self.find_node_at_position(offset)?;
+        let NodeKind::Selector { segment_spans, .. } = &node.node else {
+            return None;
+        };
+
+        segment_spans
+            .iter()
+            .position(|span| span.start <= offset && offset <= span.end)
+            .map(|index| (node, index))
+    }
+
+    /// Finds the deepest node whose span contains `position`, both ends inclusive. If two
+    /// siblings touch at `position`, the earlier one wins.
+    ///
+    /// The children are in source order, so they are binary searched by their start.
     pub fn find_node_at_position(&self, position: usize) -> Option<&AST> {
         if let Some((_, children)) = self.take_section_like() {
-            for child in children {
+            let end = children.partition_point(|child| child.meta.span.start <= position);
+            // 前の兄弟の子孫が位置まで届いていれば、そちらが先に見つかる
+            let mut start = end;
+            while start > 0 && children[start - 1].subtree_end() >= position {
+                start -= 1;
+            }
+
+            for child in &children[start..end] {
                 if let Some(found) = child.find_node_at_position(position) {
                     return Some(found);
                 }
@@ -588,27 +1882,39 @@ impl AST {
         }
     }
 
-    // TODO: bug
+    /// Where the last of this node and its descendants ends.
+    fn subtree_end(&self) -> usize {
+        self.take_section_like()
+            .and_then(|(_, children)| children.last())
+            .map_or(self.meta.span.end, |last| {
+                last.subtree_end().max(self.meta.span.end)
+            })
+    }
+
+    /// Finds the section (or the top) whose child is at `position`.
+    ///
+    /// Like [`AST::find_node_at_position`], the deepest match wins, so a node that starts
+    /// where a section header ends belongs to that section.
     pub fn find_parent_at_position(&self, position: usize) -> Option<&AST> {
-        if let Some((_, children)) = self.take_section_like() {
-            for child in children {
-                if child.meta.span.start <= position && position <= child.meta.span.end {
-                    return Some(self);
-                }
-            }
-            for child in children {
-                if let Some(deeper_parent) = child.find_parent_at_position(position) {
-                    return Some(deeper_parent);
-                }
-            }
-        }
-        None
+        let (_, children) = self.take_section_like()?;
+
+        children
+            .iter()
+            .find_map(|child| child.find_parent_at_position(position))
+            .or_else(|| {
+                children
+                    .iter()
+                    .any(|child| {
+                        child.meta.span.start <= position && position <= child.meta.span.end
+                    })
+                    .then_some(self)
+            })
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::parser::{Document, ParseError, Rule, SandParser};
+    use crate::parser::{Document, ParseError, Rule, SandParser, Span};
     use pest::Parser as _;
 
     /// Helper to parse input into Document or capture errors.
@@ -641,6 +1947,15 @@ Content
         );
     }
 
+    #[test]
+    fn empty_input() {
+        let err = parse_doc("").unwrap_err();
+        assert!(
+            matches!(err.as_slice(), [ParseError::MissingNames]),
+            "Expected MissingNames error for empty input"
+        );
+    }
+
     #[test]
     fn duplicate_names_error() {
         let doc = r#"
@@ -656,6 +1971,19 @@ Content
         );
     }
 
+    #[test]
+    fn multiple_name_define_error() {
+        let doc = "#(en, ja)\n#(en)\n";
+        let errs = parse_doc(doc).unwrap_err();
+        assert_eq!(
+            errs,
+            [ParseError::MultipleNameDefine(
+                Span { start: 0, end: 9 },
+                Span { start: 10, end: 15 }
+            )]
+        );
+    }
+
     #[test]
     fn duplicate_alias_error() {
         let doc = r#"
@@ -673,6 +2001,57 @@ Content
         );
     }
 
+    #[test]
+    fn duplicate_alias_keeps_first() {
+        use crate::parser::{
+            AST, NodeKind, NodeMeta, ParseError, PathSegment, Span, check_alias_conflict,
+        };
+        use rustc_hash::{FxHashMap, FxHashSet};
+
+        let sen = |content: &str, start| AST {
+            node: NodeKind::Sen(vec![content.to_string()], None),
+            meta: NodeMeta {
+                span: Span {
+                    start,
+                    end: start + 5,
+                },
+                alias: Some("dup".to_string()),
+            },
+        };
+        let children = vec![sen("first", 0), sen("second", 6), sen("third", 12)];
+
+        let mut aliases = FxHashMap::default();
+        let mut errs = FxHashSet::default();
+        for (index, child) in children.iter().enumerate() {
+            check_alias_conflict(
+                "dup",
+                &mut aliases,
+                &children,
+                index,
+                child.get_span(),
+                &mut errs,
+            );
+        }
+        assert_eq!(aliases["dup"], 0);
+        assert_eq!(errs.len(), 3);
+        assert!(errs.contains(&ParseError::DuplicateAlias(
+            "dup".to_string(),
+            Span { start: 0, end: 5 }
+        )));
+
+        let top = AST {
+            node: NodeKind::Top { aliases, children },
+            meta: NodeMeta {
+                span: Span { start: 0, end: 0 },
+                alias: None,
+            },
+        };
+        let found = top
+            .resolve_path(&[PathSegment::Ident("dup".to_string())])
+            .unwrap();
+        assert!(matches!(&found[0].node, NodeKind::Sen(v, _) if v[0] == "first"));
+    }
+
     #[test]
     fn alias_conflict_with_names() {
         // alias 'en' conflicts with declared name 'en'
@@ -689,6 +2068,58 @@ Content
         );
     }
 
+    #[test]
+    fn escaped_hash_in_content() {
+        use crate::parser::NodeKind;
+
+        let doc = parse_doc(
+            r#"#(en, ja)
+#sec# \#1 Title
+#[\#tag][\#タグ]
+\#not a section
+"#,
+        )
+        .unwrap();
+
+        let (_, children) = doc.ast.take_section_like().unwrap();
+        assert_eq!(children.len(), 1);
+        let NodeKind::Section {
+            content, children, ..
+        } = &children[0].node
+        else {
+            panic!("Expected a section")
+        };
+        assert_eq!(content, r"\#1 Title");
+        let [sen] = &children[..] else {
+            panic!("Expected a single sentence block")
+        };
+        assert!(matches!(&sen.node, NodeKind::Sen(v, None) if v == &[r"\#tag", r"\#タグ"]));
+    }
+
+    #[test]
+    fn escaped_delimiters_in_content() {
+        use crate::parser::NodeKind;
+
+        let doc = parse_doc(
+            r#"#(en, ja)
+#[a\]b][c]
+#{{x\}y}}
+"#,
+        )
+        .unwrap();
+
+        let (_, children) = doc.ast.take_section_like().unwrap();
+        let [sen, all] = &children[..] else {
+            panic!("Expected a sentence block and an apply-all block")
+        };
+        assert!(matches!(&sen.node, NodeKind::Sen(v, None) if v == &[r"a\]b", "c"]));
+        assert!(matches!(&all.node, NodeKind::All { content, .. } if content == r"x\}y"));
+
+        let plain = |ast| crate::formatter::plain_text(ast, (0, "en"));
+        assert_eq!(plain(sen), "a]b");
+        assert_eq!(plain(all), "x}y");
+    }
+
     #[test]
     fn parse_apply_all_and_sentences_and_selector() {
         let doc = r#"
@@ -710,6 +2141,40 @@ A section.
         );
     }
 
+    #[test]
+    fn find_node_matches_linear_search() {
+        use crate::parser::AST;
+
+        fn linear(ast: &AST, position: usize) -> Option<&AST> {
+            ast.take_section_like()
+                .into_iter()
+                .flat_map(|(_, children)| children)
+                .find_map(|child| linear(child, position))
+                .or_else(|| {
+                    (ast.meta.span.start <= position && position <= ast.meta.span.end)
+                        .then_some(ast)
+                })
+        }
+
+        let mut text = String::from("#(en, ja)\n");
+        for i in 0..20 {
+            text += &format!(
+                "#s{i}# Section {i}\n#[A{i}][あ{i}]\n#e{i}## Empty\n#n{i}## Nested\n#[B][い] #{{{{C}}}}\n#.s{i}.en\n"
+            );
+        }
+        let doc = parse_doc(&text).unwrap();
+
+        for offset in 0..=text.len() + 1 {
+            let expected = linear(&doc.ast, offset);
+            let found = doc.ast.find_node_at_position(offset);
+            assert_eq!(
+                found.map(|n| n as *const AST),
+                expected.map(|n| n as *const AST),
+                "{offset}"
+            );
+        }
+    }
+
     #[test]
     fn find() {
         use crate::parser::{AST, NodeKind, NodeMeta, Span};
@@ -720,21 +2185,21 @@ A section.
                 aliases: FxHashMap::default(),
                 children: vec![
                     AST {
-                        node: NodeKind::Sen(vec!["1".into()]),
+                        node: NodeKind::Sen(vec!["1".into()], None),
                         meta: NodeMeta {
                             span: Span { start: 0, end: 10 },
                             alias: None,
                         },
                     },
                     AST {
-                        node: NodeKind::Sen(vec!["2".into()]),
+                        node: NodeKind::Sen(vec!["2".into()], None),
                         meta: NodeMeta {
                             span: Span { start: 11, end: 20 },
                             alias: None,
                         },
                     },
                     AST {
-                        node: NodeKind::Sen(vec!["3".into()]),
+                        node: NodeKind::Sen(vec!["3".into()], None),
                         meta: NodeMeta {
                             span: Span { start: 21, end: 30 },
                             alias: None,
@@ -744,14 +2209,16 @@ A section.
                         node: NodeKind::Section {
                             aliases: FxHashMap::default(),
                             content: "aaaaaaa".into(),
+                            attributes: Default::default(),
                             level: 1,
                             children: vec![AST {
                                 node: NodeKind::Section {
                                     aliases: FxHashMap::default(),
                                     content: "aaaaaaa".into(),
+                                    attributes: Default::default(),
                                     level: 2,
                                     children: vec![AST {
-                                        node: NodeKind::Sen(vec![]),
+                                        node: NodeKind::Sen(vec![], None),
                                         meta: NodeMeta {
                                             span: Span { start: 51, end: 51 },
                                             alias: None,
@@ -777,17 +2244,17 @@ A section.
             },
         };
 
-        if let NodeKind::Sen(v) = &ast.find_node_at_position(15).unwrap().node {
+        if let NodeKind::Sen(v, _) = &ast.find_node_at_position(15).unwrap().node {
             assert_eq!(v[0], "2")
         } else {
             panic!()
         }
-        if let NodeKind::Sen(v) = &ast.find_node_at_position(21).unwrap().node {
+        if let NodeKind::Sen(v, _) = &ast.find_node_at_position(21).unwrap().node {
             assert_eq!(v[0], "3")
         } else {
             panic!()
         }
-        if let NodeKind::Sen(v) = &ast.find_node_at_position(2).unwrap().node {
+        if let NodeKind::Sen(v, _) = &ast.find_node_at_position(2).unwrap().node {
             assert_eq!(v[0], "1")
         } else {
             panic!()
@@ -809,4 +2276,779 @@ A section.
             NodeKind::Sen(..)
         ));
     }
+
+    fn edit(text: &str, start: usize, end: usize, with: &str) -> (String, crate::parser::Span) {
+        let mut new_text = text.to_string();
+        new_text.replace_range(start..end, with);
+        (new_text, crate::parser::Span { start, end })
+    }
+
+    const LARGE: &str = r#"
+#(en, ja)
+
+#a# A
+#s[One][一]
+
+#b# B
+#s[Two][二]
+#inner## Inner
+#[Three][三]
+
+#c# C
+#.b.s.en
+"#;
+
+    #[test]
+    fn reparse_range_matches_full_parse() {
+        use crate::parser::reparse_range;
+
+        let old = parse_doc(LARGE).unwrap();
+
+        let cases = [
+            // 文の中身だけの変更
+            ("[Two]", "[Two!]"),
+            // 見出しのレベルが変わって前のセクションの子になる
+            ("#c# C", "#c## C"),
+            // 名前の定義の変更
+            ("#(en, ja)", "#(ja, en)"),
+            // 次のセクションの見出しを巻き込む変更
+            ("[三]\n\n#c# C", "[三]\n#c### C"),
+        ];
+
+        for (from, to) in cases {
+            let start = LARGE.find(from).unwrap();
+            let (new_text, changed) = edit(LARGE, start, start + from.len(), to);
+
+            let reparsed = reparse_range(&old, LARGE, &new_text, changed).unwrap();
+            let full = parse_doc(&new_text).unwrap();
+
+            assert_eq!(
+                format!("{reparsed:?}"),
+                format!("{full:?}"),
+                "{from} -> {to}"
+            );
+        }
+    }
+
+    #[test]
+    fn reparse_range_rejects_invalid_edit() {
+        use crate::parser::{ReparseError, reparse_range};
+
+        let old = parse_doc(LARGE).unwrap();
+
+        // セレクターが参照している文のエイリアスを消す
+        let start = LARGE.find("#s[Two]").unwrap();
+        let (new_text, changed) = edit(LARGE, start, start + 2, "#");
+        assert_eq!(
+            reparse_range(&old, LARGE, &new_text, changed).unwrap_err(),
+            ReparseError::Invalid(parse_doc(&new_text).unwrap_err())
+        );
+    }
+
+    #[test]
+    fn reparse_range_in_many_sections() {
+        use crate::parser::reparse_range;
+
+        let mut text = String::from("#(en, ja)\n");
+        for i in 0..200 {
+            text += &format!("#s{i}# Section {i}\n#[Hello {i}][こんにちは {i}]\n\n");
+        }
+        let old = parse_doc(&text).unwrap();
+
+        let start = text.find("[Hello 100]").unwrap() + 1;
+        let (new_text, changed) = edit(&text, start, start + 5, "Bye");
+
+        let reparsed = reparse_range(&old, &text, &new_text, changed).unwrap();
+        let full = parse_doc(&new_text).unwrap();
+        assert_eq!(format!("{reparsed:?}"), format!("{full:?}"));
+    }
+
+    #[test]
+    fn content_selector() {
+        use crate::parser::{NodeKind, PathSegment, SelectorError};
+
+        let doc = r#"#(en, ja)
+#intro# Introduction
+#[Hi][やあ]
+#sub## Intro "quoted"
+#[Hey][へい]
+#.{"Introduction"}.en
+#.intro.{"\"quoted\""}.0.ja
+"#;
+        let doc = parse_doc(doc).unwrap();
+
+        let (_, children) = doc.ast.take_section_like().unwrap();
+        let (_, children) = children[0].take_section_like().unwrap();
+        let (_, children) = children[1].take_section_like().unwrap();
+        let NodeKind::Selector { path, .. } = &children.last().unwrap().node else {
+            panic!("Expected a selector")
+        };
+        assert_eq!(
+            path,
+            &[
+                PathSegment::Ident("intro".to_string()),
+                PathSegment::Content(r#""quoted""#.to_string()),
+                PathSegment::Ident("0".to_string()),
+                PathSegment::Ident("ja".to_string()),
+            ]
+        );
+
+        let errs = parse_doc(
+            r#"#(en, ja)
+## Intro 1
+## Intro 2
+#.{"Intro"}.en
+#.{"Outro"}.en
+"#,
+        )
+        .unwrap_err();
+        assert!(errs.contains(&ParseError::Selector(
+            SelectorError::AmbiguousSelector("Intro".to_string()),
+            Span { start: 32, end: 46 }
+        )));
+        assert!(errs.iter().any(|e| matches!(
+            e,
+            ParseError::Selector(SelectorError::NoMatchingSection(text), _) if text == "Outro"
+        )));
+    }
+
+    #[test]
+    fn selector_segment_at() {
+        use crate::parser::{NodeKind, PathSegment};
+
+        let text = "#(en, ja)\n#sec1# A\n#sec2## B\n#[a][b]\n#.sec1.sec2.en\n";
+        let doc = parse_doc(text).unwrap();
+        let start = text.find("#.").unwrap();
+
+        let (selector, index) = doc.ast.selector_segment_at(start + 3).unwrap();
+        assert_eq!(index, 0);
+        let NodeKind::Selector {
+            path,
+            segment_spans,
+            ..
+        } = &selector.node
+        else {
+            panic!("Expected a selector")
+        };
+        assert_eq!(path[index], PathSegment::Ident("sec1".to_string()));
+        assert_eq!(
+            segment_spans[index],
+            Span {
+                start: start + 2,
+                end: start + 6
+            }
+        );
+
+        let last = text.rfind("en").unwrap();
+        assert_eq!(doc.ast.selector_segment_at(last).unwrap().1, 2);
+        assert_eq!(doc.ast.selector_segment_at(last + 2).unwrap().1, 2);
+
+        assert!(doc.ast.selector_segment_at(start).is_none());
+        assert!(
+            doc.ast
+                .selector_segment_at(text.find("B").unwrap())
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn continued_section_content() {
+        use crate::parser::NodeKind;
+
+        let doc = parse_doc(
+            "#(en, ja)\n#long# A very long \\\n    title\n#[a][b]\n#path## C:\\\\\n#[c][d]\n",
+        )
+        .unwrap();
+        let (aliases, children) = doc.ast.take_section_like().unwrap();
+
+        let NodeKind::Section {
+            content, children, ..
+        } = &children[aliases["long"]].node
+        else {
+            panic!("Expected a section")
+        };
+        assert_eq!(content, "A very long title");
+        assert_eq!(children.len(), 2);
+
+        // 最後の`\\`はエスケープされたバックスラッシュで、続きではない
+        let NodeKind::Section { content, .. } = &children[1].node else {
+            panic!("Expected a section")
+        };
+        assert_eq!(content, r"C:\\");
+    }
+
+    #[test]
+    fn crlf_and_cr_line_endings() {
+        let lf = "#(en, ja)\n#sec# A long \\\n  title\n#[Hello\nworld][こんにちは]\n#{{Note\nend}}\n#sub## Sub\n#.sec.0.\n";
+
+        let expected = parse_doc(lf).unwrap().to_string();
+        for newline in ["\r\n", "\r"] {
+            let doc = parse_doc(&lf.replace('\n', newline)).unwrap();
+            assert_eq!(doc.to_string(), expected, "{newline:?}");
+        }
+    }
+
+    #[test]
+    fn skipped_section_level() {
+        use crate::parser::ParseOptions;
+
+        let parse_strict = |input| {
+            let pairs = SandParser::parse(Rule::doc, input).unwrap();
+            let strict = ParseOptions {
+                strict: true,
+                ..Default::default()
+            };
+            Document::try_from((pairs, strict))
+        };
+
+        // strictでなければ子として扱う
+        assert!(parse_doc("#(en, ja)\n## A\n#### C\n").is_ok());
+
+        let errs = parse_strict("#(en, ja)\n## A\n#### C\n").unwrap_err();
+        assert_eq!(
+            errs,
+            [ParseError::SkippedSectionLevel {
+                from: 1,
+                to: 3,
+                span: Span { start: 15, end: 22 }
+            }]
+        );
+
+        let errs = parse_strict("#(en, ja)\n### B\n").unwrap_err();
+        assert!(matches!(
+            errs.as_slice(),
+            [ParseError::SkippedSectionLevel { from: 0, to: 2, .. }]
+        ));
+
+        // 浅いレベルに戻るのは問題ない
+        assert!(parse_strict("#(en, ja)\n## A\n### B\n#### C\n## D\n").is_ok());
+    }
+
+    #[test]
+    fn strict_reports_free_text() {
+        use crate::parser::ParseOptions;
+
+        let input = "#(en, ja)\n#[Hi][やあ]\n  Stray text \n#[Bye][じゃあ]\n";
+        assert!(parse_doc(input).is_ok());
+
+        let strict = ParseOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let pairs = SandParser::parse(Rule::doc, input).unwrap();
+        let errs = Document::try_from((pairs, strict.clone())).unwrap_err();
+        let start = input.find("Stray").unwrap();
+        assert_eq!(
+            errs,
+            [ParseError::UnknownStatement(Span {
+                start,
+                end: start + "Stray text".len()
+            })]
+        );
+
+        // 文の間の改行だけなら問題ない
+        let pairs = SandParser::parse(Rule::doc, "#(en, ja)\n\n#[Hi][やあ]\n\n").unwrap();
+        assert!(Document::try_from((pairs, strict)).is_ok());
+    }
+
+    #[test]
+    fn empty_name_declaration() {
+        use crate::formatter::{Selector, render_plain};
+        use crate::parser::ParseOptions;
+
+        let input = "#()\n#sec# Section\n#{{ Shared }}\n";
+        assert_eq!(
+            parse_doc(input).unwrap_err(),
+            [ParseError::EmptyNames(Span { start: 0, end: 3 })]
+        );
+
+        let options = ParseOptions {
+            allow_empty_names: true,
+            ..Default::default()
+        };
+        let pairs = SandParser::parse(Rule::doc, input).unwrap();
+        let doc = Document::try_from((pairs, options)).unwrap();
+        assert!(doc.names.is_empty());
+        assert_eq!(doc.to_string().lines().next(), Some("#()"));
+
+        for selector in ["#.", "#.sec."] {
+            let pairs = SandParser::parse(Rule::Selector, selector).unwrap();
+            let sel = Selector::try_from((&doc, pairs)).unwrap();
+            assert!(render_plain(&doc, &sel, false).is_empty());
+        }
+    }
+
+    #[test]
+    fn too_many_names() {
+        use crate::parser::ParseOptions;
+
+        let names: Vec<_> = (0..5).map(|i| format!("n{i}")).collect();
+        let input = format!("#({})\n", names.join(", "));
+        let options = |max_names| ParseOptions {
+            max_names,
+            ..Default::default()
+        };
+
+        let pairs = SandParser::parse(Rule::doc, &input).unwrap();
+        assert!(Document::try_from((pairs, options(5))).is_ok());
+
+        let pairs = SandParser::parse(Rule::doc, &input).unwrap();
+        assert_eq!(
+            Document::try_from((pairs, options(4))).unwrap_err(),
+            [ParseError::TooManyNames(
+                4,
+                Span {
+                    start: 0,
+                    end: input.len() - 1
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn iter_pre_order() {
+        use crate::parser::NodeKind;
+
+        let doc = parse_doc("#(en, ja)\n#a# A\n#[1][一]\n#b## B\n#{{2}}\n#c# C\n#.c.en\n").unwrap();
+
+        let kinds: Vec<_> = doc
+            .ast
+            .iter()
+            .map(|ast| match &ast.node {
+                NodeKind::Top { .. } => "top".to_string(),
+                NodeKind::Section { content, .. } => content.clone(),
+                NodeKind::Sen(..) => "sen".to_string(),
+                NodeKind::All { .. } => "all".to_string(),
+                NodeKind::Selector { .. } => "selector".to_string(),
+            })
+            .collect();
+        assert_eq!(kinds, ["top", "A", "sen", "B", "all", "C", "selector"]);
+        assert_eq!(doc.ast.iter().count(), 7);
+    }
+
+    #[test]
+    fn alias_index() {
+        let input =
+            "#(en, ja)\n#sec1# One\n#intro[Hi][やあ]\n#sec2## Two\n## Three\n#note[A][あ]\n";
+        let doc = parse_doc(input).unwrap();
+
+        let index: Vec<_> = doc
+            .alias_index()
+            .into_iter()
+            .map(|(alias, path, span)| (alias, path, &input[span.start..span.end]))
+            .collect();
+        assert_eq!(index.len(), 4);
+        assert_eq!(index[0].0, "sec1");
+        assert_eq!(index[0].1, ["sec1"]);
+        assert_eq!(index[1].1, ["sec1", "intro"]);
+        assert_eq!(index[2].0, "sec2");
+        assert_eq!(index[2].1, ["sec1", "sec2"]);
+        assert!(index[2].2.starts_with("#sec2## Two"));
+        assert_eq!(index[3].0, "note");
+        assert_eq!(index[3].1, ["1", "note"]);
+        assert_eq!(index[3].2, "#note[A][あ]");
+    }
+
+    #[test]
+    fn labeled_sentences() {
+        use crate::parser::NodeKind;
+
+        let doc =
+            parse_doc("#(en, ja)\n#[ja: こんにちは][en: Hello]\n#[Note: a][メモ: b]\n").unwrap();
+        let (_, children) = doc.ast.take_section_like().unwrap();
+
+        let NodeKind::Sen(sentences, labels) = &children[0].node else {
+            panic!("Expected sentences")
+        };
+        assert_eq!(sentences, &["Hello", "こんにちは"]);
+        assert_eq!(
+            labels.as_deref(),
+            Some(&["en".to_string(), "ja".to_string()][..])
+        );
+
+        // 宣言された名前でなければただの文
+        let NodeKind::Sen(sentences, labels) = &children[1].node else {
+            panic!("Expected sentences")
+        };
+        assert_eq!(sentences, &["Note: a", "メモ: b"]);
+        assert!(labels.is_none());
+
+        assert_eq!(
+            doc.to_string(),
+            "#(en, ja)\n#[en: Hello][ja: こんにちは]\n#[Note: a][メモ: b]\n"
+        );
+    }
+
+    #[test]
+    fn render_error() {
+        let input = "#(en, ja)\n\n#[Hi]\n";
+        let errs = parse_doc(input).unwrap_err();
+        assert_eq!(
+            errs[0].render(input, "doc.sand"),
+            "doc.sand:3:1: the number of sentences does not match the number of names.\n#[Hi]\n^^^^^\n"
+        );
+
+        assert_eq!(
+            ParseError::MissingNames.render("", "doc.sand"),
+            format!("doc.sand: {}\n", ParseError::MissingNames)
+        );
+    }
+
+    #[test]
+    fn mismatched_sentence_labels() {
+        let errs = parse_doc("#(en, ja)\n#[en: Hello][fr: Bonjour]\n").unwrap_err();
+        assert!(errs.contains(&ParseError::UnknownLabel(
+            "fr".to_string(),
+            Span { start: 22, end: 35 }
+        )));
+        assert!(errs.contains(&ParseError::MissingLabel(
+            "ja".to_string(),
+            Span { start: 10, end: 35 }
+        )));
+        assert!(
+            !errs
+                .iter()
+                .any(|e| matches!(e, ParseError::NumberOfSentences(_)))
+        );
+
+        let errs = parse_doc("#(en, ja)\n#[en: Hello][en: Hi][ja: やあ]\n").unwrap_err();
+        assert_eq!(
+            errs,
+            [ParseError::DuplicateLabel(
+                "en".to_string(),
+                Span { start: 22, end: 30 }
+            )]
+        );
+    }
+
+    #[test]
+    fn partially_labeled_sentences_are_text() {
+        use crate::parser::NodeKind;
+
+        // ラベルのないブロックがあれば、`en:`で始まっていてもただの文
+        let doc = parse_doc("#(en, ja)\n#[en: see below][やあ]\n").unwrap();
+        let (_, children) = doc.ast.take_section_like().unwrap();
+
+        let NodeKind::Sen(sentences, labels) = &children[0].node else {
+            panic!("Expected sentences")
+        };
+        assert_eq!(sentences, &["en: see below", "やあ"]);
+        assert!(labels.is_none());
+    }
+
+    #[test]
+    fn glob_selector() {
+        use crate::parser::{NodeKind, PathSegment, SelectorError};
+
+        let doc =
+            parse_doc("#(en, ja)\n#sec1# One\n#a## A\n#[A][あ]\n#b## B\n#[B][び]\n#.sec1.*.en\n")
+                .unwrap();
+
+        let (_, children) = doc.ast.take_section_like().unwrap();
+        let (_, children) = children[0].take_section_like().unwrap();
+        let (_, children) = children[1].take_section_like().unwrap();
+        let NodeKind::Selector { path, .. } = &children.last().unwrap().node else {
+            panic!("Expected a selector")
+        };
+        assert_eq!(
+            path,
+            &[
+                PathSegment::Ident("sec1".to_string()),
+                PathSegment::Glob,
+                PathSegment::Ident("en".to_string()),
+            ]
+        );
+
+        let errs = parse_doc("#(en, ja)\n#sec1# One\n#a## A\n#[A][あ]\n#b## B\n#.sec1.*.0.en\n")
+            .unwrap_err();
+        assert!(
+            errs.iter()
+                .any(|e| matches!(e, ParseError::Selector(SelectorError::OutOfIndex, _)))
+        );
+    }
+
+    #[test]
+    fn section_attributes() {
+        let doc =
+            parse_doc("#(en, ja)\n#sec#{status=draft, owner=docs-team} Title\n#[A][あ]\n").unwrap();
+
+        let (_, children) = doc.ast.take_section_like().unwrap();
+        let attributes = children[0].attributes().unwrap();
+        assert_eq!(attributes.len(), 2);
+        assert_eq!(attributes["status"], "draft");
+        assert_eq!(attributes["owner"], "docs-team");
+        assert!(
+            doc.to_string()
+                .contains("#sec#{owner=docs-team, status=draft} Title\n")
+        );
+
+        let errs = parse_doc("#(en, ja)\n##{status=draft, status=done} Title\n").unwrap_err();
+        assert!(matches!(
+            errs.as_slice(),
+            [ParseError::DuplicateAttribute(key, _)] if key == "status"
+        ));
+    }
+
+    #[test]
+    fn name_list_selector() {
+        use crate::parser::{NodeKind, SelectorError};
+
+        let input = "#(en, ja, fr)\n#sec1# One\n#sec2## Two\n#[A][あ][a]\n#.sec1.sec2[en, fr]\n";
+        let doc = parse_doc(input).unwrap();
+
+        let (_, children) = doc.ast.take_section_like().unwrap();
+        let (_, children) = children[0].take_section_like().unwrap();
+        let (_, children) = children[0].take_section_like().unwrap();
+        let NodeKind::Selector {
+            path,
+            trailing_dot,
+            names,
+            ..
+        } = &children.last().unwrap().node
+        else {
+            panic!("Expected a selector")
+        };
+        assert_eq!(path.len(), 2);
+        assert!(!trailing_dot);
+        assert_eq!(
+            names.as_deref(),
+            Some(&["en".to_string(), "fr".to_string()][..])
+        );
+        assert!(doc.to_string().contains("#.sec1.sec2[en, fr]\n"));
+
+        let errs = parse_doc("#(en, ja)\n#sec1# One\n#[A][あ]\n#.sec1[en, de]\n").unwrap_err();
+        assert!(matches!(
+            errs.as_slice(),
+            [ParseError::Selector(SelectorError::UnknownName(name), _)] if name == "de"
+        ));
+    }
+
+    #[test]
+    fn name_index() {
+        let doc = parse_doc("#(en, ja, fr)\n#[Hi][やあ][Salut]\n").unwrap();
+
+        for (index, name) in doc.names.iter().enumerate() {
+            assert_eq!(doc.name_index(name), Some(index));
+        }
+        assert_eq!(doc.name_index("de"), None);
+    }
+
+    #[test]
+    fn validate_selector_str() {
+        use crate::parser::SelectorError;
+
+        let doc = parse_doc("#(en, ja)\n#sec# Section\n#[Hi][やあ]\n").unwrap();
+        assert_eq!(doc.validate_selector_str("#.sec.0.en"), Ok(()));
+        assert_eq!(doc.validate_selector_str("#.0."), Ok(()));
+
+        assert_eq!(
+            doc.validate_selector_str("#.3.en"),
+            Err(vec![ParseError::Selector(
+                SelectorError::OutOfIndex,
+                Span { start: 0, end: 6 }
+            )])
+        );
+        assert!(matches!(
+            doc.validate_selector_str("#.sec.en trailing")
+                .unwrap_err()
+                .as_slice(),
+            [ParseError::Selector(
+                SelectorError::Syntax(_),
+                Span { start: 9, end: 17 }
+            )]
+        ));
+        assert!(matches!(
+            doc.validate_selector_str("sec.en").unwrap_err().as_slice(),
+            [ParseError::Selector(SelectorError::Syntax(_), _)]
+        ));
+    }
+
+    #[test]
+    fn stale_alias_is_out_of_index() {
+        use crate::parser::{
+            PathSegment, SelectorError, parse_selector, validate_non_local_selector,
+        };
+
+        let mut doc = parse_doc("#(en, ja)\n#sec# Section\n#[Hi][やあ]\n").unwrap();
+        let (_, aliases, _) = doc.ast.take_mut_section_like().unwrap();
+        aliases.insert("ghost".to_string(), 5);
+
+        assert_eq!(
+            doc.ast
+                .resolve_path(&[PathSegment::Ident("ghost".to_string())])
+                .unwrap_err(),
+            SelectorError::OutOfIndex
+        );
+
+        let pair = SandParser::parse(Rule::Selector, "#.ghost.en")
+            .unwrap()
+            .next()
+            .unwrap();
+        let sel = parse_selector(pair.as_span().into(), pair);
+        assert!(matches!(
+            validate_non_local_selector(&doc, &sel).as_slice(),
+            [ParseError::Selector(SelectorError::OutOfIndex, _)]
+        ));
+    }
+
+    #[test]
+    fn selector_into_sentences() {
+        use crate::parser::SelectorError;
+
+        let input = "#(en, ja)\n#sents[Hi][やあ]\n";
+        assert!(parse_doc(&format!("{input}#.sents.en\n")).is_ok());
+
+        let errs = parse_doc(&format!("{input}#.sents.en.extra\n")).unwrap_err();
+        assert!(errs.iter().any(|e| matches!(
+            e,
+            ParseError::Selector(SelectorError::NotASection(segment, span), _)
+                if segment == "en" && span.start == 10
+        )));
+    }
+
+    #[test]
+    fn content_children_skip_selectors() {
+        use crate::formatter::{Selector, render_plain};
+
+        // 子の間にあるセレクタは番号に数えない
+        let doc = parse_doc("#(en, ja)\n#[A][あ]\n#.0.en\n#[B][い]\n").unwrap();
+        assert_eq!(doc.ast.content_children().len(), 2);
+
+        let pairs = SandParser::parse(Rule::Selector, "#.1.en").unwrap();
+        let sel = Selector::try_from((&doc, pairs)).unwrap();
+        assert_eq!(render_plain(&doc, &sel, false), ["B"]);
+    }
+
+    #[test]
+    fn parse_fragments() {
+        use crate::parser::parse_fragment;
+
+        let pair = parse_fragment(Rule::Selector, "#.sec.en")
+            .unwrap()
+            .next()
+            .unwrap();
+        assert_eq!(pair.as_rule(), Rule::Selector);
+
+        let pair = parse_fragment(Rule::Section, "#sec# Title\n")
+            .unwrap()
+            .next()
+            .unwrap();
+        assert_eq!(pair.as_rule(), Rule::Section);
+
+        // 続きの文字列があればエラー
+        let err = parse_fragment(Rule::Selector, "#.sec en").unwrap_err();
+        assert!(err.message.starts_with("unexpected text"));
+        assert_eq!(err.span.end, 8);
+
+        assert!(parse_fragment(Rule::Section, "#sec# Title").is_err());
+    }
+
+    #[test]
+    fn dot_inside_selector_path() {
+        use crate::parser::{NodeKind, SelectorError, parse_selector, validate_non_local_selector};
+
+        let input = "#(en, ja)\n#sec1# Title\n#[Hi][やあ]\n";
+        assert!(parse_doc(&format!("{input}#.sec1.\n")).is_ok());
+        assert!(parse_doc(&format!("{input}#.sec1.en\n")).is_ok());
+
+        let errs = parse_doc(&format!("{input}#.sec1..en\n")).unwrap_err();
+        assert!(matches!(
+            errs.as_slice(),
+            [ParseError::Selector(SelectorError::DotInsidePath, span)] if span.start == 44
+        ));
+
+        let doc = parse_doc(input).unwrap();
+        let pair = SandParser::parse(Rule::Selector, "#.sec1..en")
+            .unwrap()
+            .next()
+            .unwrap();
+        let sel = parse_selector(pair.as_span().into(), pair);
+        assert!(matches!(
+            &sel.node,
+            NodeKind::Selector { trailing_dot: false, misplaced_dots, .. }
+                if misplaced_dots.len() == 1
+        ));
+        assert!(matches!(
+            validate_non_local_selector(&doc, &sel).as_slice(),
+            [ParseError::Selector(SelectorError::DotInsidePath, _)]
+        ));
+    }
+
+    #[test]
+    fn local_selector_at_top_level() {
+        use crate::parser::SelectorError;
+
+        let errs = parse_doc("#(en, ja)\n#[Hi][やあ]\n#./0.\n").unwrap_err();
+        assert_eq!(
+            errs,
+            [ParseError::Selector(
+                SelectorError::LocalAtTopLevel,
+                Span { start: 24, end: 29 }
+            )]
+        );
+
+        assert!(parse_doc("#(en, ja)\n## Section\n#[Hi][やあ]\n#./0.\n").is_ok());
+    }
+
+    #[test]
+    fn display_round_trip() {
+        let doc = parse_doc(
+            r#"#(en, ja)
+Some free text.
+#[Top][トップ]
+
+#intro# Intro \#1
+#s1[
+    Hello, \] world!
+][
+    こんにちは
+]
+#{{ \n }}
+#only{[en], { English }}
+#.intro.s1.en
+#./0.
+#sub## Deep
+#[Deep][深い]
+
+## Next
+#.
+"#,
+        )
+        .unwrap();
+
+        let displayed = doc.to_string();
+        let reparsed = parse_doc(&displayed).unwrap();
+
+        assert_eq!(reparsed.names, doc.names);
+        assert!(reparsed.ast.structurally_eq(&doc.ast));
+    }
+
+    #[test]
+    fn node_source() {
+        let text = "#(en, ja)\n#intro# Intro \\#1\n#[Hi][やあ]\n";
+        let doc = parse_doc(text).unwrap();
+
+        let section = &doc.ast.content_children()[0];
+        assert_eq!(section.source(text), "#intro# Intro \\#1\n");
+
+        let sentence = &section.content_children()[0];
+        assert_eq!(sentence.source(text), "#[Hi][やあ]");
+        // 別のテキストでも文字の途中で切らない
+        assert_eq!(sentence.source(&"あ".repeat(20)), "あああ");
+        assert_eq!(sentence.source(""), "");
+    }
+
+    #[test]
+    fn structurally_eq_ignores_spans() {
+        let doc = parse_doc("#(en, ja)\n#sec# Title\n#[A][あ]\n#.sec.en\n").unwrap();
+        let moved = parse_doc("#(en, ja)\n\n\n#sec# Title\n\n#[A][あ]\n\n#.sec.en\n").unwrap();
+        assert_ne!(
+            doc.ast.content_children()[0].span(),
+            moved.ast.content_children()[0].span()
+        );
+        assert!(doc.ast.structurally_eq(&moved.ast));
+
+        let changed = parse_doc("#(en, ja)\n#sec# Title\n#[B][あ]\n#.sec.en\n").unwrap();
+        assert!(!doc.ast.structurally_eq(&changed.ast));
+    }
 }
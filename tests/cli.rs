@@ -0,0 +1,707 @@
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// Writes `contents` to a fresh file in the temp dir and returns its path.
+fn write_input(name: &str, contents: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("sand-cli-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+fn sand(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_sand"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+const DOC: &str = r#"#(en, ja)
+
+#sec# Section
+
+#[Hello][こんにちは]
+"#;
+
+#[test]
+fn parse_quiet() {
+    let input = write_input("quiet.sand", DOC);
+
+    let out = sand(&["parse", "--quiet", input.to_str().unwrap()]);
+    assert!(out.status.success());
+    assert!(out.stdout.is_empty());
+}
+
+#[test]
+fn parse_with_bom() {
+    let input = write_input("bom.sand", &format!("\u{feff}{DOC}"));
+    let input = input.to_str().unwrap();
+
+    let out = sand(&["parse", input]);
+    assert!(out.status.success());
+    // 名前の位置はBOMを除いて数える
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(stdout.contains("Span { start: 2, end: 4 }"), "{stdout}");
+
+    let out = sand(&["out", "#.sec.en", "--input", input]);
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "Hello\n");
+}
+
+#[test]
+fn parse_reports_every_error() {
+    let input = write_input(
+        "errors.sand",
+        "#(en, ja)\n#[Only one]\n#a# One\n#a# Two\n#.missing.en\n",
+    );
+
+    let out = Command::new(env!("CARGO_BIN_EXE_sand"))
+        .args(["parse", input.to_str().unwrap()])
+        .env("NO_COLOR", "1")
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    let positions: Vec<_> = [
+        "the number of sentences does not match",
+        "duplicate alias: `a`",
+        "selector syntax is incorrect",
+    ]
+    .iter()
+    .map(|message| stderr.find(message).expect(message))
+    .collect();
+    // 文書の上から順に報告する
+    assert!(positions.is_sorted(), "{stderr}");
+}
+
+#[test]
+fn check_all_directory() {
+    let dir = std::env::temp_dir()
+        .join(format!("sand-cli-{}", std::process::id()))
+        .join("check-all");
+    std::fs::create_dir_all(dir.join("nested")).unwrap();
+    std::fs::write(dir.join("good.sand"), DOC).unwrap();
+    std::fs::write(dir.join("nested/bad.sand"), "#(en, ja)\n#[Only one]\n").unwrap();
+    std::fs::write(dir.join("notes.txt"), "not a document").unwrap();
+
+    let out = sand(&["check-all", dir.to_str().unwrap()]);
+    assert_eq!(out.status.code(), Some(1));
+
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(stdout.contains("good.sand: ok"), "{stdout}");
+    assert!(stdout.contains("bad.sand: failed"), "{stdout}");
+    assert!(!stdout.contains("notes.txt"));
+    assert!(stdout.contains("2 files checked, 1 with errors"));
+}
+
+#[test]
+fn out_with_name_filter() {
+    let input = write_input("name.sand", DOC);
+    let input = input.to_str().unwrap();
+
+    let out = sand(&["out", "#.sec.", "--name", "ja", "--input", input]);
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "こんにちは\n");
+
+    let out = sand(&["out", "#.sec.", "--name", "fr", "--input", input]);
+    assert!(!out.status.success());
+}
+
+#[test]
+fn tree_lists_names_and_sections() {
+    let input = write_input(
+        "tree.sand",
+        "#(en, ja)\n#first# First\n#[A][あ]\n#second# Second\n#inner## Inner\n",
+    );
+
+    let out = sand(&["tree", input.to_str().unwrap(), "--depth", "1"]);
+    assert!(out.status.success());
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        "names: en, ja\n# First (first)\n# Second (second)\n"
+    );
+}
+
+#[test]
+fn out_watch_rerenders_on_change() {
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    let input = write_input("watch.sand", "#(en, ja)\n#[Hello][こんにちは]\n");
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_sand"))
+        .args(["out", "#.en", "--watch", "--input", input.to_str().unwrap()])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    let stdout = child.stdout.take().unwrap();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            if tx.send(line.unwrap()).is_err() {
+                break;
+            }
+        }
+    });
+
+    let wait_for = |expected: &str| loop {
+        let line = rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        if line.ends_with(expected) {
+            break;
+        }
+    };
+
+    wait_for("Hello");
+    std::fs::write(&input, "#(en, ja)\n#[Goodbye][さようなら]\n").unwrap();
+    wait_for("Goodbye");
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn normalize_is_idempotent() {
+    let input = write_input(
+        "normalize.sand",
+        "#(en, ja)\n#sec# Issue \\#1\nSome free text \\# note\n#[a\\/b \\] \\n][\\#あ]\n#{{ \\#tag \\} }}\n",
+    );
+
+    let out = sand(&["normalize", input.to_str().unwrap()]);
+    assert!(out.status.success());
+    let once = String::from_utf8(out.stdout).unwrap();
+    assert_eq!(
+        once,
+        "#(en, ja)\n#sec# Issue #1\nSome free text \\# note\n#[a/b \\] \\n][#あ]\n#{{ #tag \\} }}\n"
+    );
+
+    let again = write_input("normalize-again.sand", &once);
+    let out = sand(&["normalize", again.to_str().unwrap()]);
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), once);
+}
+
+#[test]
+fn normalize_keeps_includes() {
+    write_input("normalize-names.sand", "#(en, ja)\n#shared# Shared \\#1\n");
+    let input = write_input(
+        "normalize-include.sand",
+        "@include \"normalize-names.sand\"\n#[a\\/b][\\#あ]\n",
+    );
+
+    // 読み込んだファイルは展開せず、入力のファイルだけを出す
+    let out = sand(&["normalize", input.to_str().unwrap()]);
+    assert!(out.status.success());
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        "@include \"normalize-names.sand\"\n#[a/b][#あ]\n"
+    );
+}
+
+#[test]
+fn out_error_on_empty() {
+    let input = write_input(
+        "empty.sand",
+        "#(en, ja)\n#sec# Section\n#{[ja], {だけ}}\n#other# Other\n#[Hi][やあ]\n",
+    );
+    let input = input.to_str().unwrap();
+
+    let out = sand(&["out", "#.sec.en", "--error-on-empty", "--input", input]);
+    assert_eq!(out.status.code(), Some(2));
+    assert!(out.stdout.is_empty());
+    assert!(
+        String::from_utf8(out.stderr)
+            .unwrap()
+            .contains("renders nothing")
+    );
+
+    let out = sand(&["out", "#.sec.ja", "--error-on-empty", "--input", input]);
+    assert!(out.status.success());
+
+    let out = sand(&["out", "#.sec.en", "--input", input]);
+    assert!(out.status.success());
+}
+
+#[test]
+fn tab_width_in_diagnostics() {
+    let input = write_input("tab.sand", "#(en, ja)\n\t#[Hello]\n");
+
+    for width in [2, 8] {
+        let out = Command::new(env!("CARGO_BIN_EXE_sand"))
+            .args(["parse", input.to_str().unwrap(), "--tab-width"])
+            .arg(width.to_string())
+            .env("NO_COLOR", "1")
+            .output()
+            .unwrap();
+        assert!(!out.status.success());
+
+        let stderr = String::from_utf8(out.stderr).unwrap();
+        let caret = format!("│ {}^^^^^^^^", " ".repeat(width));
+        assert!(stderr.contains(&caret), "{stderr}");
+    }
+}
+
+#[test]
+fn check_sarif_report() {
+    let input = write_input("sarif.sand", "#(en, ja)\n#a# A\n#[x]\n#a# B\n");
+
+    let out = sand(&["check", input.to_str().unwrap(), "--format", "sarif"]);
+    assert!(!out.status.success());
+
+    let report: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(report["version"], "2.1.0");
+
+    let results = report["runs"][0]["results"].as_array().unwrap();
+    let mut rule_ids: Vec<_> = results
+        .iter()
+        .map(|r| r["ruleId"].as_str().unwrap())
+        .collect();
+    rule_ids.sort_unstable();
+    assert_eq!(
+        rule_ids,
+        [
+            "sand/duplicate-alias",
+            "sand/duplicate-alias",
+            "sand/number-of-sentences"
+        ]
+    );
+
+    let sentences = results
+        .iter()
+        .find(|r| r["ruleId"] == "sand/number-of-sentences")
+        .unwrap();
+    let location = &sentences["locations"][0]["physicalLocation"];
+    assert_eq!(location["artifactLocation"]["uri"], input.to_str().unwrap());
+    assert_eq!(location["region"]["startLine"], 3);
+    assert_eq!(location["region"]["startColumn"], 1);
+
+    let ok = write_input("sarif-ok.sand", DOC);
+    let out = sand(&["check", ok.to_str().unwrap(), "--format", "sarif"]);
+    assert!(out.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert!(report["runs"][0]["results"].as_array().unwrap().is_empty());
+}
+
+#[test]
+fn check_strict_reports_free_text() {
+    let input = write_input("strict.sand", "#(en, ja)\n#[Hi][やあ]\nStray text\n");
+    let path = input.to_str().unwrap();
+
+    assert!(sand(&["check", path]).status.success());
+
+    let out = sand(&["check", path, "--strict"]);
+    assert!(!out.status.success());
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(stderr.contains("not a statement"), "{stderr}");
+
+    let out = sand(&["check", path, "--strict", "--format", "sarif"]);
+    let report: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(
+        report["runs"][0]["results"][0]["ruleId"],
+        "sand/unknown-statement"
+    );
+}
+
+#[test]
+fn check_strict_reports_skipped_section_level() {
+    let input = write_input("skip.sand", "#(en, ja)\n## A\n#### C\n#[Hi][やあ]\n");
+    let path = input.to_str().unwrap();
+
+    assert!(sand(&["check", path]).status.success());
+
+    let out = sand(&["check", path, "--strict"]);
+    assert!(!out.status.success());
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(stderr.contains("skips from level 1 to level 3"), "{stderr}");
+}
+
+#[test]
+fn check_github_annotations() {
+    let input = write_input("github.sand", "#(en, ja)\n#sec# Section\n#[x]\n");
+    let path = input.to_str().unwrap();
+
+    let out = sand(&["check", path, "--format", "github"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        format!(
+            "::error file={path},line=3,col=1::the number of sentences does not match the number of names.\n"
+        )
+    );
+
+    let input = write_input("github-warning.sand", "#(en, ja)\n#empty# Empty\n");
+    let out = sand(&["check", input.to_str().unwrap(), "--format", "github"]);
+    assert!(out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(
+        stdout.lines().any(|l| l.starts_with("::warning file=")),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn check_deny_warnings() {
+    let input = write_input(
+        "deny-warnings.sand",
+        "#(en, ja)\n#empty# Empty\n\n#full# Full\n#[Hi][やあ]\n",
+    );
+    let path = input.to_str().unwrap();
+
+    let out = sand(&["check", path]);
+    assert!(out.status.success());
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(stderr.contains("no sentences"), "{stderr}");
+
+    assert_eq!(
+        sand(&["check", path, "--deny-warnings"]).status.code(),
+        Some(1)
+    );
+
+    let out = sand(&["check", path, "--format", "sarif", "--deny-warnings"]);
+    assert_eq!(out.status.code(), Some(1));
+    let report: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let result = &report["runs"][0]["results"][0];
+    assert_eq!(result["ruleId"], "sand/empty-section");
+    assert_eq!(result["level"], "warning");
+}
+
+#[test]
+fn check_skips_opt_in_lints() {
+    let input = write_input(
+        "opt-in-lints.sand",
+        "#(en, ja)\n#sec# Title\n#g[Hi][やあ]\n#.0.g.en\n#same# Same\n#[OK][OK]\n",
+    );
+    let path = input.to_str().unwrap();
+
+    let out = sand(&["check", path, "--deny-warnings"]);
+    assert!(out.status.success());
+    assert!(
+        out.stderr.is_empty(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+}
+
+#[test]
+fn diff_revisions() {
+    let old = write_input("diff-old.sand", "#(en, ja)\n#intro# Intro\n#hi[Hi][やあ]\n");
+    let new = write_input(
+        "diff-new.sand",
+        "#(en, ja)\n#intro# Intro\n#hi[Hello][やあ]\n",
+    );
+
+    let out = sand(&["diff", old.to_str().unwrap(), new.to_str().unwrap()]);
+    assert!(out.status.success());
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        "~ intro.hi.en: \"Hi\" -> \"Hello\"\n"
+    );
+}
+
+#[test]
+fn out_with_include() {
+    write_input("include-names.sand", "#(en, ja)\n");
+    let input = write_input(
+        "include-doc.sand",
+        "@include \"include-names.sand\"\n#sec# Section\n#[Hello][こんにちは]\n",
+    );
+
+    let out = sand(&["out", "#.sec.ja", "--input", input.to_str().unwrap()]);
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "こんにちは\n");
+
+    let input = write_input("include-self.sand", "@include \"include-self.sand\"\n");
+    let out = sand(&["check", input.to_str().unwrap()]);
+    assert!(!out.status.success());
+}
+
+#[test]
+fn include_in_every_command() {
+    write_input("every-names.sand", "#(en, ja)\n");
+    write_input("every-left.sand", "@include \"every-names.sand\"\n");
+    write_input("every-right.sand", "@include \"./every-names.sand\"\n");
+    let input = write_input(
+        "every-doc.sand",
+        "@include \"every-left.sand\"\n@include \"every-right.sand\"\n#sec# Section\n#[Hello][こんにちは]\n",
+    );
+    let input = input.to_str().unwrap();
+
+    // 二回読み込まれても名前の定義は一つ
+    for args in [
+        &["check", input][..],
+        &["tree", input],
+        &["stats", input],
+        &["normalize", input],
+        &["export", input],
+        &["diff", input, input],
+    ] {
+        let out = sand(args);
+        assert!(out.status.success(), "{args:?}: {out:?}");
+    }
+
+    let input = write_input(
+        "every-error.sand",
+        "@include \"every-names.sand\"\n#[Hello]\n",
+    );
+    let out = sand(&["check", input.to_str().unwrap(), "--format", "sarif"]);
+    let report: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let location = &report["runs"][0]["results"][0]["locations"][0]["physicalLocation"];
+    assert_eq!(location["artifactLocation"]["uri"], input.to_str().unwrap());
+    assert_eq!(location["region"]["startLine"], 2);
+}
+
+#[test]
+fn export_csv() {
+    let input = write_input(
+        "export.sand",
+        "#(en, ja)\n#intro# Intro\n#hi[Hello, world][こんにちは]\n#[\"Bye\"][さようなら]\n",
+    );
+
+    let out = sand(&["export", input.to_str().unwrap()]);
+    assert!(out.status.success());
+
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(lines[0], "path,en,ja");
+    assert_eq!(lines[1], "intro.hi,\"Hello, world\",こんにちは");
+    assert_eq!(lines[2], "intro.1,\"\"\"Bye\"\"\",さようなら");
+    assert_eq!(lines.len(), 3);
+}
+
+#[test]
+fn export_po() {
+    let input = write_input(
+        "export-po.sand",
+        "#(en, ja, fr)\n#intro# Intro\n#hi[en: Hello][ja: こんにちは][fr: Bonjour]\n",
+    );
+
+    let out = sand(&[
+        "export",
+        "--format",
+        "po",
+        "--name",
+        "fr",
+        input.to_str().unwrap(),
+    ]);
+    assert!(out.status.success());
+
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(stdout.contains("\"Language: fr\\n\"\n"));
+    assert!(stdout.contains("msgctxt \"intro.hi\"\nmsgid \"Hello\"\nmsgstr \"Bonjour\"\n"));
+}
+
+#[test]
+fn stats_counts_nodes() {
+    let input = write_input(
+        "stats.sand",
+        "#(en, ja)\n#first# First\n#[A][あ]\n#{{Note}}\n#inner## Inner\n#[B][い]\n#second# Second\n#.first.\n",
+    );
+    let input = input.to_str().unwrap();
+
+    let out = sand(&["stats", input]);
+    assert!(out.status.success());
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        "names      2\n\
+         sections   3\n  \
+         level 1  2\n  \
+         level 2  1\n\
+         sentences  2\n\
+         apply-all  1\n\
+         selectors  1\n\
+         max depth  2\n"
+    );
+
+    let out = sand(&["stats", "--json", input]);
+    assert!(out.status.success());
+    let stats: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(stats["sections_per_level"], serde_json::json!([2, 1]));
+    assert_eq!(stats["max_depth"], 2);
+
+    // 見出しのレベルを飛ばしても入れ子は二段
+    let input = write_input(
+        "stats-skipped.sand",
+        "#(en, ja)\n## One\n##### Four\n#[A][あ]\n",
+    );
+    let out = sand(&["stats", "--json", input.to_str().unwrap()]);
+    assert!(out.status.success());
+    let stats: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(stats["sections_per_level"], serde_json::json!([1, 0, 0, 1]));
+    assert_eq!(stats["max_depth"], 2);
+}
+
+#[test]
+fn out_pretty_without_color() {
+    let input = write_input(
+        "pretty.sand",
+        r#"#(en, ja)
+#sec# Section
+#[Hello][こんにちは]
+#sub## Sub
+#{{ Shared }}
+"#,
+    );
+    let input = input.to_str().unwrap();
+
+    let plain = sand(&["out", "#.en", "--markdown", "--input", input]);
+    assert!(plain.status.success());
+    assert!(String::from_utf8_lossy(&plain.stdout).contains("# Section"));
+
+    let pretty = sand(&[
+        "out",
+        "#.en",
+        "--markdown",
+        "--pretty",
+        "--color",
+        "never",
+        "--input",
+        input,
+    ]);
+    assert!(pretty.status.success());
+    assert_eq!(pretty.stdout, plain.stdout);
+}
+
+#[test]
+fn out_long_name_header() {
+    let long = "a".repeat(80);
+    let input = write_input("long-name.sand", &format!("#(en, {long})\n#[Hi][Yo]\n"));
+
+    let out = sand(&[
+        "out",
+        "#.",
+        "--color",
+        "never",
+        "--input",
+        input.to_str().unwrap(),
+    ]);
+    assert!(out.status.success());
+
+    // 端末でなければ幅は80として、名前を切り詰めて見出しを1行に収める
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    let header = stdout.lines().find(|line| line.starts_with("aaa")).unwrap();
+    assert_eq!(header, format!("{}… ───", "a".repeat(75)));
+    assert!(
+        stdout
+            .lines()
+            .any(|line| line == format!("en {}", "─".repeat(77)))
+    );
+}
+
+#[test]
+fn out_with_front_matter() {
+    let input = write_input("front-matter.sand", DOC);
+    let input = input.to_str().unwrap();
+
+    let out = sand(&[
+        "out",
+        "#.sec.en",
+        "--markdown",
+        "--front-matter",
+        "--input",
+        input,
+    ]);
+    assert!(out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(stdout.starts_with("---\nname: en\nnames: [en, ja]\n---\n"));
+    assert!(stdout.ends_with("Hello\n"));
+
+    let out = sand(&[
+        "out",
+        "#.sec.",
+        "--markdown",
+        "--front-matter",
+        "--color",
+        "never",
+        "--input",
+        input,
+    ]);
+    assert!(out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    for name in ["en", "ja"] {
+        assert!(stdout.contains(&format!("\n\n---\nname: {name}\nnames: [en, ja]\n---\n")));
+    }
+
+    let out = sand(&["out", "#.sec.en", "--front-matter", "--input", input]);
+    assert!(!out.status.success());
+}
+
+#[test]
+fn out_all_names() {
+    let input = write_input("all-names.sand", DOC);
+    let input = input.to_str().unwrap();
+
+    let out = sand(&[
+        "out",
+        "#.sec.en",
+        "--all-names",
+        "--color",
+        "never",
+        "--input",
+        input,
+    ]);
+    assert!(out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(stdout.contains("Hello"));
+    assert!(stdout.contains("こんにちは"));
+}
+
+#[test]
+fn out_explain() {
+    let input = write_input(
+        "explain.sand",
+        "#(en, ja)\n#sec# Section\n### Sub\n#[Hello][こんにちは]\n",
+    );
+    let input = input.to_str().unwrap();
+
+    let out = sand(&["out", "#.sec.0.en", "--explain", "--input", input]);
+    assert!(out.status.success());
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        "sec: alias of child 0\n0: index 0\nen: name 0\ntarget: section\nnames: en\n\nHello\n"
+    );
+}
+
+#[test]
+fn out_with_template() {
+    let input = write_input("template.sand", DOC);
+    let input = input.to_str().unwrap();
+
+    let out = sand(&[
+        "out",
+        "#.sec.",
+        "--template",
+        r"## {name}\n{content}\n",
+        "--input",
+        input,
+    ]);
+    assert!(out.status.success());
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        "## en\nHello\n## ja\nこんにちは\n"
+    );
+
+    let out = sand(&["out", "#.sec.", "--template", "{name}", "--input", input]);
+    assert!(!out.status.success());
+}
+
+#[test]
+fn out_names_only_document() {
+    let input = write_input("names-only.sand", "#(en, ja)\n");
+    let input = input.to_str().unwrap();
+
+    let out = sand(&["out", "#.", "--input", input]);
+    assert!(out.status.success());
+    assert!(out.stdout.is_empty());
+    assert!(
+        String::from_utf8(out.stderr)
+            .unwrap()
+            .contains("the document declares names but has no content")
+    );
+
+    let out = sand(&["out", "#.", "--error-on-empty", "--input", input]);
+    assert_eq!(out.status.code(), Some(2));
+}